@@ -0,0 +1,151 @@
+use std::{
+    fmt::Display,
+    str::FromStr
+};
+use json::JsonValue;
+use crate::{
+    config::ConfigurationError,
+    http::{HttpMethod,HttpError}
+};
+
+/// What a client may do. Each role may do everything the ones before it may.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Reads state, presets and settings
+    Viewer,
+    /// Also recalls presets, changes ties, mutes and volumes, and runs macros
+    Operator,
+    /// Also stores, renames and imports presets, and changes names, lockout and schedules
+    Admin
+}
+
+impl FromStr for Role {
+    type Err = ConfigurationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(ConfigurationError::new("role must be \"viewer\", \"operator\" or \"admin\""))
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+/// Someone allowed to use the API, configured under `users`:
+///
+/// `{ "name": "lounge", "password": "secret", "role": "operator" }` signs in with HTTP Basic
+/// authentication, and `{ "name": "panel", "token": "abc123", "role": "viewer" }` sends
+/// `Authorization: Bearer abc123`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct User {
+    pub name: String,
+    pub credential: Credential,
+    pub role: Role
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Credential {
+    Password(String),
+    Token(String)
+}
+
+impl User {
+    pub fn parse(user: &JsonValue) -> Result<User, ConfigurationError> {
+        let name = match user["name"].as_str() {
+            Some(n) if !n.is_empty() && !n.contains(':') => n.to_string(),
+            _ => return Err(ConfigurationError::new("Every user needs a name without a colon"))
+        };
+        let invalid = |problem: &str| ConfigurationError::new(&format!("User \"{}\": {}", name, problem));
+
+        let credential = match (user["password"].as_str(), user["token"].as_str()) {
+            (Some(p), None) if !p.is_empty() => Credential::Password(p.to_string()),
+            (None, Some(t)) if !t.is_empty() => Credential::Token(t.to_string()),
+            _ => return Err(invalid("needs either a password or a token"))
+        };
+        let role = match user["role"].as_str().map(Role::from_str) {
+            Some(Ok(r)) => r,
+            Some(Err(e)) => return Err(invalid(&e.to_string())),
+            None => return Err(invalid("needs a role"))
+        };
+
+        Ok(User { name, credential, role })
+    }
+}
+
+/// The user an `Authorization` header belongs to, if it names one
+pub fn authenticate<'a>(authorization: &str, users: &'a [User]) -> Option<&'a User> {
+    let (scheme, value) = authorization.trim().split_once(' ')?;
+    let value = value.trim();
+    if scheme.eq_ignore_ascii_case("Bearer") {
+        users.iter().find(|u| matches!(&u.credential, Credential::Token(t) if same(t, value)))
+    } else if scheme.eq_ignore_ascii_case("Basic") {
+        let decoded = String::from_utf8(decode_base64(value)?).ok()?;
+        let (name, password) = decoded.split_once(':')?;
+        users.iter().find(|u| u.name == name && matches!(&u.credential, Credential::Password(p) if same(p, password)))
+    } else {
+        None
+    }
+}
+
+/// Checks a request's `Authorization` header against the users. Answers 401 when it is
+/// missing or wrong, and 403 when the user's role is not enough for the route.
+pub fn authorize(method: HttpMethod, path: &str, authorization: Option<&str>, users: &[User]) -> Result<(), HttpError> {
+    let user = match authorization.and_then(|a| authenticate(a, users)) {
+        Some(u) => u,
+        None => return Err(HttpError::new(401, "Authentication required"))
+    };
+    let needed = required_role(method, path);
+    if user.role < needed {
+        log::warn!("Refused {} for user \"{}\", which needs the {} role", path, user.name, needed);
+        return Err(HttpError::new(403, &format!("The {} role is needed", needed)));
+    }
+    Ok(())
+}
+
+/// The least role that may use a route. Anything not listed only reads.
+pub fn required_role(method: HttpMethod, path: &str) -> Role {
+    match (method, path) {
+        (HttpMethod::GET, "/loadPreset")
+            | (HttpMethod::POST, "/setVolume" | "/setGain" | "/videoMute" | "/audioMute" | "/restoreHistory" | "/undo" | "/runMacro") => Role::Operator,
+        (HttpMethod::GET, "/saveCurrentToPreset")
+            | (HttpMethod::POST, "/createPreset" | "/renamePreset" | "/importPresets" | "/saveSchedule" | "/removeSchedule"
+                | "/syncNames" | "/setLockout") => Role::Admin,
+        (HttpMethod::GET, _) | (HttpMethod::POST, "/comparePresets") => Role::Viewer,
+        (_, _) => Role::Admin
+    }
+}
+
+/// Compares secrets without stopping at the first difference
+fn same(expected: &str, given: &str) -> bool {
+    expected.len() == given.len() && expected.bytes().zip(given.bytes()).fold(0, |d, (a, b)| d | (a ^ b)) == 0
+}
+
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut bits = 0u32;
+    let mut bit_count = 0;
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None
+        };
+        bits = ((bits << 6) | value as u32) & 0xffff;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+        }
+    }
+    Some(decoded)
+}
@@ -0,0 +1,159 @@
+use std::io::{Error,ErrorKind};
+use json::{JsonValue,object::Object,number::Number};
+use crate::{
+    crosspoint::{CrossPoint,CrossPointPreset,CrossPointTie},
+    state
+};
+
+/// Format of the backup documents written by this version; older ones can still be restored
+pub const BACKUP_VERSION: i32 = 1;
+
+/// Every stored preset of a switcher, as saved to a backup file:
+///
+/// `{ "Version": 1, "Inputs": 12, "Outputs": 8, "Presets": [ { "PresetNumber": 2, "PresetName": "SNES",
+///    "Inputs": [ { "InputChannel": 6, "OutputChannels": [3], "IOType": "All" } ] } ] }`
+///
+/// Each preset uses the createPreset request body format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PresetBackup {
+    pub input_count: i32,
+    pub output_count: i32,
+    pub presets: Vec<CrossPointPreset>
+}
+
+/// What restoring a backup does to one preset slot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PresetChangeKind {
+    /// The slot is empty on the switcher
+    Create,
+    /// The slot holds a different name or ties
+    Replace,
+    /// The backup has nothing in a slot the switcher uses
+    Clear
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PresetChange {
+    pub preset_number: i32,
+    pub kind: PresetChangeKind,
+    /// Name the slot has once the change is made, or had before it was cleared
+    pub name: String
+}
+
+impl PresetChange {
+    pub fn to_json(&self) -> JsonValue {
+        let mut o = Object::new();
+        o.insert("PresetNumber", JsonValue::Number(Number::from(self.preset_number)));
+        o.insert("Change", JsonValue::String(format!("{:?}", self.kind)));
+        o.insert("PresetName", JsonValue::String(self.name.clone()));
+        JsonValue::Object(o)
+    }
+}
+
+impl PresetBackup {
+    /// Reads every stored preset from the switcher
+    pub fn read(cp: &mut CrossPoint) -> Result<PresetBackup, Error> {
+        let mut presets = Vec::new();
+        for preset_number in 1..=cp.preset_count()? {
+            if let Some(preset) = cp.get_preset(preset_number)? { presets.push(preset); }
+        }
+        Ok(PresetBackup { input_count: cp.input_port_count(), output_count: cp.output_port_count(), presets })
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut o = Object::new();
+        o.insert("Version", JsonValue::Number(Number::from(BACKUP_VERSION)));
+        o.insert("Inputs", JsonValue::Number(Number::from(self.input_count)));
+        o.insert("Outputs", JsonValue::Number(Number::from(self.output_count)));
+        o.insert("Presets", JsonValue::Array(self.presets.iter().map(|p| p.to_json()).collect()));
+        JsonValue::Object(o)
+    }
+
+    pub fn parse(document: &str) -> Result<PresetBackup, String> {
+        let backup_json = match json::parse(document) {
+            Ok(j) => j,
+            Err(_) => return Err(String::from("Backup is not in JSON format"))
+        };
+
+        match backup_json["Version"].as_i32() {
+            Some(v) if (1..=BACKUP_VERSION).contains(&v) => (),
+            Some(v) => return Err(format!("Backup version {} is not supported", v)),
+            None => return Err(String::from("Missing backup version"))
+        }
+        let input_count = backup_json["Inputs"].as_i32().unwrap_or_default();
+        let output_count = backup_json["Outputs"].as_i32().unwrap_or_default();
+
+        let mut presets: Vec<CrossPointPreset> = Vec::new();
+        for preset in backup_json["Presets"].members() {
+            let preset = CrossPointPreset::from(preset.clone())?;
+            if presets.iter().any(|p| p.number == preset.number) {
+                return Err(format!("Preset {} appears more than once", preset.number));
+            }
+            presets.push(preset);
+        }
+
+        Ok(PresetBackup { input_count, output_count, presets })
+    }
+
+    /// Works out what restoring the backup would change, without writing anything. Fails if
+    /// the backup uses presets or channels the switcher does not have.
+    pub fn plan(&self, cp: &mut CrossPoint) -> Result<Vec<PresetChange>, Error> {
+        let preset_count = cp.preset_count()?;
+        let (input_count, output_count) = (cp.input_port_count(), cp.output_port_count());
+        let audio_support = cp.audio_is_supported();
+        for preset in &self.presets {
+            if !(1..=preset_count).contains(&preset.number) {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("Switcher has no preset {}", preset.number)));
+            }
+            if let Some(tie) = preset.ties.iter().find(|t| t.input_channel as i32 > input_count || t.output_channel as i32 > output_count) {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("Preset {} ties input {} to output {}, which the switcher does not have",
+                    preset.number, tie.input_channel, tie.output_channel)));
+            }
+        }
+
+        let mut changes = Vec::new();
+        for preset_number in 1..=preset_count {
+            let stored = cp.get_preset(preset_number)?;
+            let wanted = self.presets.iter().find(|p| p.number == preset_number);
+            let kind = match (&stored, wanted) {
+                (None, Some(_)) => PresetChangeKind::Create,
+                (Some(s), Some(w)) if !same_preset(s, w, output_count, audio_support) => PresetChangeKind::Replace,
+                (Some(_), None) => PresetChangeKind::Clear,
+                _ => continue
+            };
+            let name = match (wanted, &stored) {
+                (Some(w), Some(s)) if w.name.is_empty() => s.name.clone(),
+                (Some(w), _) => w.name.clone(),
+                (None, Some(s)) => s.name.clone(),
+                (None, None) => String::new()
+            };
+            changes.push(PresetChange { preset_number, kind, name });
+        }
+        Ok(changes)
+    }
+
+    /// Rewrites the switcher's presets to match the backup and returns the changes made
+    pub fn restore(&self, cp: &mut CrossPoint) -> Result<Vec<PresetChange>, Error> {
+        let changes = self.plan(cp)?;
+        for change in &changes {
+            match self.presets.iter().find(|p| p.number == change.preset_number) {
+                Some(preset) => cp.create_preset(preset.clone())?,
+                None => cp.clear_preset(change.preset_number)?
+            };
+        }
+        Ok(changes)
+    }
+}
+
+/// Whether two presets route the same way, however their ties are split up
+fn same_preset(stored: &CrossPointPreset, wanted: &CrossPointPreset, output_count: i32, audio_support: bool) -> bool {
+    (wanted.name.is_empty() || stored.name == wanted.name)
+        && normalize(&stored.ties, output_count, audio_support) == normalize(&wanted.ties, output_count, audio_support)
+}
+
+/// Ties as the switcher reads them back. Models without audio only keep the video half.
+fn normalize(ties: &[CrossPointTie], output_count: i32, audio_support: bool) -> Vec<CrossPointTie> {
+    let (video, mut audio) = state::split_ties(ties, output_count as usize);
+    if !audio_support { audio = video.clone(); }
+    state::merge_ties(&video, &audio)
+}
@@ -0,0 +1,73 @@
+use json::{JsonValue,object::Object,number::Number};
+use crate::{
+    crosspoint::{CrossPointTie,CrossPointIO},
+    state
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TieChangeKind {
+    /// Nothing fed the output before
+    Added,
+    /// Nothing feeds the output afterwards
+    Removed,
+    /// The output switches to another input
+    Changed
+}
+
+/// How one output's video, audio or both differ between two tie lists. Inputs are None
+/// when nothing is tied.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TieChange {
+    pub output_channel: u8,
+    pub io_type: CrossPointIO,
+    pub kind: TieChangeKind,
+    pub from_input: Option<u8>,
+    pub to_input: Option<u8>
+}
+
+impl TieChange {
+    pub fn to_json(&self) -> JsonValue {
+        let input = |i: Option<u8>| i.map_or(JsonValue::Null, |i| JsonValue::Number(Number::from(i)));
+        let mut o = Object::new();
+        o.insert("OutputChannel", JsonValue::Number(Number::from(self.output_channel)));
+        o.insert("IOType", JsonValue::String(format!("{:?}", self.io_type)));
+        o.insert("Change", JsonValue::String(format!("{:?}", self.kind)));
+        o.insert("FromInput", input(self.from_input));
+        o.insert("ToInput", input(self.to_input));
+        JsonValue::Object(o)
+    }
+}
+
+/// Lists what going from one set of ties to another changes, by output. An output whose
+/// video and audio change the same way is reported once as `All`; otherwise each half is
+/// reported as `Vid` or `Aud`. RGB ties count as video.
+pub fn compare_ties(from: &[CrossPointTie], to: &[CrossPointTie]) -> Vec<TieChange> {
+    let output_count = from.iter().chain(to).map(|t| t.output_channel as usize).max().unwrap_or(0);
+    let (from_video, from_audio) = state::split_ties(from, output_count);
+    let (to_video, to_audio) = state::split_ties(to, output_count);
+
+    let mut changes = Vec::new();
+    for index in 0..output_count {
+        let output_channel = index as u8 + 1;
+        let video = (from_video[index], to_video[index]);
+        let audio = (from_audio[index], to_audio[index]);
+        if video == audio {
+            changes.extend(change(output_channel, CrossPointIO::All, video));
+            continue;
+        }
+        changes.extend(change(output_channel, CrossPointIO::Vid, video));
+        changes.extend(change(output_channel, CrossPointIO::Aud, audio));
+    }
+    changes
+}
+
+fn change(output_channel: u8, io_type: CrossPointIO, (from, to): (u8, u8)) -> Option<TieChange> {
+    let kind = match (from, to) {
+        _ if from == to => return None,
+        (0, _) => TieChangeKind::Added,
+        (_, 0) => TieChangeKind::Removed,
+        _ => TieChangeKind::Changed
+    };
+    let input = |i: u8| if i == 0 { None } else { Some(i) };
+    Some(TieChange { output_channel, io_type, kind, from_input: input(from), to_input: input(to) })
+}
@@ -3,51 +3,198 @@ use std::{
     net::{SocketAddrV4},
     collections::HashMap,
     fs, str::FromStr,
+    time::Duration
 };
+use json::{JsonValue,object::Object,number::Number};
+use serialport::{DataBits,Parity,StopBits,FlowControl};
+use crate::{
+    transport::{SerialSettings,ConnectionSettings},
+    http::CorsPolicy,
+    logging::{self,LogSettings},
+    crosspoint::{MAX_LOCKOUT_LEVEL,MAX_PRESET_COUNT},
+    routing::RoutingRule,
+    macros::Macro,
+    scheduler::Schedule,
+    auth::User
+};
+
+const CONFIG_FILE: &str = "service.json";
 
+#[derive(Default)]
 pub struct ServiceConfiguration {
+    /// File the configuration was loaded from, where pulled channel names are saved
+    pub path: Option<String>,
     pub serial_port: Option<String>,
+    pub serial_transcript: Option<String>,
+    pub device_password: Option<String>,
+    /// Idle time after which a Telnet link is probed
+    pub device_keep_alive: Option<Duration>,
+    pub serial: SerialSettings,
+    pub logging: LogSettings,
+    /// Executive mode to hold the front panel in while the service runs
+    pub front_panel_lockout: Option<i32>,
+    /// Global presets the switcher stores. None asks the switcher.
+    pub preset_count: Option<i32>,
+    /// How often to check inputs for a signal. None uses the default; zero turns polling off.
+    pub signal_poll_interval: Option<Duration>,
     pub http_root: Option<String>,
     pub binding: Option<SocketAddrV4>,
+    /// PEM files with the certificate chain and private key to serve HTTPS with
+    pub tls_certificate: Option<String>,
+    pub tls_key: Option<String>,
+    /// Where to answer plain HTTP with a redirect to HTTPS
+    pub redirect_binding: Option<SocketAddrV4>,
+    /// Other origins whose pages may call the API. None keeps browsers to the site's own origin.
+    pub cors: Option<CorsPolicy>,
     pub inputs: HashMap<i32, String>,
-    pub outputs: HashMap<i32, String>
+    pub outputs: HashMap<i32, String>,
+    pub routing_rules: Vec<RoutingRule>,
+    pub macros: Vec<Macro>,
+    pub schedules: Vec<Schedule>,
+    /// Minutes east of UTC that schedules are run in
+    pub utc_offset: i32,
+    /// Who may use the API. Anyone may when there are none.
+    pub users: Vec<User>
 }
 
 impl ServiceConfiguration {
     pub fn load() -> Result<ServiceConfiguration, ConfigurationError> {
-        let config_file = match fs::read_to_string("service.json") {
+        let config_file = match fs::read_to_string(CONFIG_FILE) {
             Ok(f) => f,
             Err(e) => return Err(ConfigurationError::new(&format!("Error opening config file. {}.", e)))
         };
-        let config_json = match json::parse(&config_file) {
+        let mut config = ServiceConfiguration::parse(&config_file)?;
+        config.path = Some(String::from(CONFIG_FILE));
+        Ok(config)
+    }
+
+    /// How to reach the switcher, or None if no serial port is configured
+    pub fn connection_settings(&self) -> Option<ConnectionSettings> {
+        Some(ConnectionSettings {
+            port_name: self.serial_port.clone()?,
+            transcript: self.serial_transcript.clone(),
+            password: self.device_password.clone(),
+            keep_alive: self.device_keep_alive,
+            serial: self.serial
+        })
+    }
+
+    /// Writes the input and output names back to the configuration file, leaving the other
+    /// settings as they are. Does nothing for a configuration that was not loaded from a file.
+    pub fn save_channel_names(&self) -> Result<(), ConfigurationError> {
+        self.update_file(|config_json| {
+            config_json["inputs"] = channel_array(&self.inputs);
+            config_json["outputs"] = channel_array(&self.outputs);
+        })
+    }
+
+    /// Writes the schedules back to the configuration file in the same way
+    pub fn save_schedules(&self) -> Result<(), ConfigurationError> {
+        self.update_file(|config_json| {
+            config_json["schedules"] = JsonValue::Array(self.schedules.iter().map(Schedule::to_json).collect());
+        })
+    }
+
+    fn update_file(&self, change: impl FnOnce(&mut JsonValue)) -> Result<(), ConfigurationError> {
+        let path = match self.path.as_ref() {
+            Some(p) => p,
+            None => return Ok(())
+        };
+        let mut config_json = match fs::read_to_string(path).ok().and_then(|f| json::parse(&f).ok()) {
+            Some(j) => j,
+            None => return Err(ConfigurationError::new("Config file is not in JSON format"))
+        };
+
+        change(&mut config_json);
+
+        match fs::write(path, json::stringify_pretty(config_json, 4)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(ConfigurationError::new(&format!("Error writing config file. {}.", e)))
+        }
+    }
+
+    pub fn parse(config_file: &str) -> Result<ServiceConfiguration, ConfigurationError> {
+        let config_json = match json::parse(config_file) {
             Ok(j) => j,
             Err(_) => return Err(ConfigurationError::new("Config file is not in JSON format"))
         };
 
-        let mut config = ServiceConfiguration {
-            binding: None,
-            http_root: None,
-            serial_port: None,
-            inputs: HashMap::new(),
-            outputs: HashMap::new()
-        };
-        
-        let serial_port = config_json["serial-port"].as_str();
-        if serial_port.is_some() { config.serial_port = Some(serial_port.unwrap().to_string()); }
+        let mut config = ServiceConfiguration::default();
+
+        if let Some(serial_port) = config_json["serial-port"].as_str() { config.serial_port = Some(serial_port.to_string()); }
+
+        if let Some(transcript) = config_json["serial-transcript"].as_str() { config.serial_transcript = Some(transcript.to_string()); }
+
+        if let Some(password) = config_json["device-password"].as_str() { config.device_password = Some(password.to_string()); }
+
+        let keep_alive = &config_json["device-keep-alive"];
+        if !keep_alive.is_null() {
+            config.device_keep_alive = match keep_alive.as_u64() {
+                Some(s) if s > 0 => Some(Duration::from_secs(s)),
+                _ => return Err(ConfigurationError::new("device-keep-alive must be a number of seconds"))
+            };
+        }
 
-        let http_root = config_json["http_root"].as_str();
-        if http_root.is_some() { config.http_root = Some(http_root.unwrap().to_string()); }
-        
-        let binding = SocketAddrV4::from_str(config_json["binding"].as_str().unwrap_or_default());
-        if binding.is_ok() { config.binding = Some(binding.unwrap()); }
+        config.serial = parse_serial_settings(&config_json)?;
+
+        config.logging = parse_log_settings(&config_json)?;
+
+        let lockout = &config_json["front-panel-lockout"];
+        if !lockout.is_null() {
+            config.front_panel_lockout = match lockout.as_i32() {
+                Some(l) if (0..=MAX_LOCKOUT_LEVEL).contains(&l) => Some(l),
+                _ => return Err(ConfigurationError::new(&format!("front-panel-lockout must be between 0 and {}", MAX_LOCKOUT_LEVEL)))
+            };
+        }
+
+        let preset_count = &config_json["preset-count"];
+        if !preset_count.is_null() {
+            config.preset_count = match preset_count.as_i32() {
+                Some(c) if (1..=MAX_PRESET_COUNT).contains(&c) => Some(c),
+                _ => return Err(ConfigurationError::new(&format!("preset-count must be between 1 and {}", MAX_PRESET_COUNT)))
+            };
+        }
+
+        let poll_interval = &config_json["signal-poll-interval"];
+        if !poll_interval.is_null() {
+            config.signal_poll_interval = match poll_interval.as_u64() {
+                Some(i) => Some(Duration::from_millis(i)),
+                None => return Err(ConfigurationError::new("signal-poll-interval must be a number of milliseconds"))
+            };
+        }
+
+        if let Some(http_root) = config_json["http_root"].as_str() { config.http_root = Some(http_root.to_string()); }
+
+        if let Ok(binding) = SocketAddrV4::from_str(config_json["binding"].as_str().unwrap_or_default()) { config.binding = Some(binding); }
+
+        match (config_json["tls-certificate"].as_str(), config_json["tls-key"].as_str()) {
+            (Some(certificate), Some(key)) => {
+                config.tls_certificate = Some(certificate.to_string());
+                config.tls_key = Some(key.to_string());
+            }
+            (None, None) => (),
+            _ => return Err(ConfigurationError::new("tls-certificate and tls-key must be set together"))
+        }
+
+        let redirect_binding = &config_json["https-redirect"];
+        if !redirect_binding.is_null() {
+            config.redirect_binding = match redirect_binding.as_str().map(SocketAddrV4::from_str) {
+                Some(Ok(b)) if config.tls_certificate.is_some() => Some(b),
+                Some(Ok(_)) => return Err(ConfigurationError::new("https-redirect needs tls-certificate and tls-key")),
+                _ => return Err(ConfigurationError::new("https-redirect must be an address such as 0.0.0.0:80"))
+            };
+        }
+
+        let cors = &config_json["cors"];
+        if !cors.is_null() { config.cors = Some(parse_cors(cors)?); }
 
         let input_array = config_json["inputs"].to_owned();
         if input_array.is_array() {
             for input in input_array.members() {
                 let name = input["description"].as_str();
                 let number = input["channel"].as_i32();
-                if name.is_some() && number.is_some() {
-                    config.inputs.insert(number.unwrap(), String::from(name.unwrap()));
+                if let (Some(name), Some(number)) = (name, number) {
+                    config.inputs.insert(number, String::from(name));
                 }
             }
         }
@@ -57,16 +204,206 @@ impl ServiceConfiguration {
             for output in output_array.members() {
                 let name = output["description"].as_str();
                 let number = output["channel"].as_i32();
-                if name.is_some() && number.is_some() {
-                    config.outputs.insert(number.unwrap(), String::from(name.unwrap()));
+                if let (Some(name), Some(number)) = (name, number) {
+                    config.outputs.insert(number, String::from(name));
                 }
             }
         }
 
+        for rule in config_json["auto-routing"].members() {
+            let rule = RoutingRule::parse(rule, &config.inputs, &config.outputs, config.preset_count.unwrap_or(MAX_PRESET_COUNT))?;
+            config.routing_rules.push(rule);
+        }
+
+        for definition in config_json["macros"].members() {
+            let definition = Macro::parse(definition, &config.inputs, &config.outputs)?;
+            if config.macros.iter().any(|m| m.name == definition.name) {
+                return Err(ConfigurationError::new(&format!("Macro \"{}\" is defined more than once", definition.name)));
+            }
+            config.macros.push(definition);
+        }
+
+        for schedule in config_json["schedules"].members() {
+            let schedule = Schedule::parse(schedule, &config.inputs, &config.outputs, &config.macros, config.preset_count.unwrap_or(MAX_PRESET_COUNT))?;
+            if config.schedules.iter().any(|s| s.name == schedule.name) {
+                return Err(ConfigurationError::new(&format!("Schedule \"{}\" is defined more than once", schedule.name)));
+            }
+            config.schedules.push(schedule);
+        }
+
+        for user in config_json["users"].members() {
+            let user = User::parse(user)?;
+            if config.users.iter().any(|u| u.name == user.name) {
+                return Err(ConfigurationError::new(&format!("User \"{}\" is defined more than once", user.name)));
+            }
+            config.users.push(user);
+        }
+
+        let utc_offset = &config_json["utc-offset"];
+        if !utc_offset.is_null() {
+            config.utc_offset = match utc_offset.as_str().and_then(parse_utc_offset) {
+                Some(o) => o,
+                None => return Err(ConfigurationError::new("utc-offset must be written as +HH:MM or -HH:MM"))
+            };
+        }
+
         Ok(config)
     }
 }
 
+fn channel_array(names: &HashMap<i32, String>) -> JsonValue {
+    let mut channels: Vec<(&i32, &String)> = names.iter().collect();
+    channels.sort();
+    JsonValue::Array(channels.into_iter().map(|(channel, description)| {
+        let mut o = Object::new();
+        o.insert("channel", JsonValue::Number(Number::from(*channel)));
+        o.insert("description", JsonValue::String(description.clone()));
+        JsonValue::Object(o)
+    }).collect())
+}
+
+/// Minutes east of UTC from an offset such as `+01:00`, limited to the offsets in use
+fn parse_utc_offset(offset: &str) -> Option<i32> {
+    let (sign, rest) = match offset.strip_prefix('+') {
+        Some(rest) => (1, rest),
+        None => (-1, offset.strip_prefix('-')?)
+    };
+    let (hours, minutes) = rest.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 { return None; }
+    let (hours, minutes): (i32, i32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    let offset = sign * (hours * 60 + minutes);
+    if minutes >= 60 || !(-12 * 60..=14 * 60).contains(&offset) { return None; }
+    Some(offset)
+}
+
+/// Reads `log-level`, `log-levels`, `log-file` and `access-log`. Logging is at info level to
+/// standard output when they are left out.
+fn parse_log_settings(config_json: &JsonValue) -> Result<LogSettings, ConfigurationError> {
+    let mut settings = LogSettings::default();
+    let invalid_level = || ConfigurationError::new("log levels must be \"off\", \"error\", \"warn\", \"info\", \"debug\" or \"trace\"");
+
+    let level = &config_json["log-level"];
+    if !level.is_null() {
+        settings.level = match level.as_str().and_then(logging::parse_level) {
+            Some(l) => l,
+            None => return Err(invalid_level())
+        };
+    }
+
+    let levels = &config_json["log-levels"];
+    if !levels.is_null() && !levels.is_object() {
+        return Err(ConfigurationError::new("log-levels must map module names to levels"));
+    }
+    for (module, level) in levels.entries() {
+        match level.as_str().and_then(logging::parse_level) {
+            Some(l) => settings.levels.insert(module.to_string(), l),
+            None => return Err(invalid_level())
+        };
+    }
+
+    if let Some(file) = config_json["log-file"].as_str() { settings.file = Some(file.to_string()); }
+    if let Some(file) = config_json["access-log"].as_str() { settings.access_log = Some(file.to_string()); }
+
+    Ok(settings)
+}
+
+/// Reads `origins`, `methods`, `headers` and `max-age` from the `cors` object. Methods and
+/// headers default to what the API uses.
+fn parse_cors(cors: &JsonValue) -> Result<CorsPolicy, ConfigurationError> {
+    let list = |key: &str| -> Result<Option<Vec<String>>, ConfigurationError> {
+        let value = &cors[key];
+        if value.is_null() { return Ok(None); }
+        let items: Vec<String> = value.members().filter_map(|m| m.as_str()).map(String::from).collect();
+        if !value.is_array() || items.len() != value.len() || items.is_empty() {
+            return Err(ConfigurationError::new(&format!("cors {} must be a list of strings", key)));
+        }
+        Ok(Some(items))
+    };
+
+    let origins = match list("origins")? {
+        Some(o) => o,
+        None => return Err(ConfigurationError::new("cors needs a list of origins, or [\"*\"] for any"))
+    };
+    let mut policy = CorsPolicy { origins, ..CorsPolicy::default() };
+    if let Some(methods) = list("methods")? { policy.methods = methods; }
+    if let Some(headers) = list("headers")? { policy.headers = headers; }
+
+    let max_age = &cors["max-age"];
+    if !max_age.is_null() {
+        policy.max_age = match max_age.as_u64() {
+            Some(a) => Some(Duration::from_secs(a)),
+            None => return Err(ConfigurationError::new("cors max-age must be a number of seconds"))
+        };
+    }
+    Ok(policy)
+}
+
+/// Reads `baud-rate`, `data-bits`, `parity`, `stop-bits`, `flow-control` and `serial-timeout`.
+/// Keys that are left out keep the 9600 8N1 defaults; `"baud-rate": "auto"` tries each supported rate.
+fn parse_serial_settings(config_json: &JsonValue) -> Result<SerialSettings, ConfigurationError> {
+    let mut serial = SerialSettings::default();
+
+    let baud_rate = &config_json["baud-rate"];
+    if baud_rate.as_str() == Some("auto") {
+        serial.baud_rate = None;
+    } else if !baud_rate.is_null() {
+        serial.baud_rate = match baud_rate.as_u32() {
+            Some(b) if b > 0 => Some(b),
+            _ => return Err(ConfigurationError::new("baud-rate must be a number or \"auto\""))
+        };
+    }
+
+    let data_bits = &config_json["data-bits"];
+    if !data_bits.is_null() {
+        serial.data_bits = match data_bits.as_u8() {
+            Some(5) => DataBits::Five,
+            Some(6) => DataBits::Six,
+            Some(7) => DataBits::Seven,
+            Some(8) => DataBits::Eight,
+            _ => return Err(ConfigurationError::new("data-bits must be 5, 6, 7 or 8"))
+        };
+    }
+
+    let parity = &config_json["parity"];
+    if !parity.is_null() {
+        serial.parity = match parity.as_str() {
+            Some("none") => Parity::None,
+            Some("odd") => Parity::Odd,
+            Some("even") => Parity::Even,
+            _ => return Err(ConfigurationError::new("parity must be \"none\", \"odd\" or \"even\""))
+        };
+    }
+
+    let stop_bits = &config_json["stop-bits"];
+    if !stop_bits.is_null() {
+        serial.stop_bits = match stop_bits.as_u8() {
+            Some(1) => StopBits::One,
+            Some(2) => StopBits::Two,
+            _ => return Err(ConfigurationError::new("stop-bits must be 1 or 2"))
+        };
+    }
+
+    let flow_control = &config_json["flow-control"];
+    if !flow_control.is_null() {
+        serial.flow_control = match flow_control.as_str() {
+            Some("none") => FlowControl::None,
+            Some("software") => FlowControl::Software,
+            Some("hardware") => FlowControl::Hardware,
+            _ => return Err(ConfigurationError::new("flow-control must be \"none\", \"software\" or \"hardware\""))
+        };
+    }
+
+    let timeout = &config_json["serial-timeout"];
+    if !timeout.is_null() {
+        serial.timeout = match timeout.as_u64() {
+            Some(t) if t > 0 => Duration::from_millis(t),
+            _ => return Err(ConfigurationError::new("serial-timeout must be a number of milliseconds"))
+        };
+    }
+
+    Ok(serial)
+}
+
 pub struct ConfigurationError {
     description: String
 }
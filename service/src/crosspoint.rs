@@ -1,52 +1,142 @@
 use std::{
-    io::{Write, BufReader, BufRead, Error},
-    time::Duration,
+    fmt::Display,
+    io::{Error, ErrorKind},
     str::FromStr
 };
-use serialport::SerialPort;
-use crate::vextron::VirtualCrosspoint;
+use std::time::Duration;
+use crate::{
+    transport::{self,Transport,ConnectionSettings},
+    discovery::{self,AUTO_DETECT},
+    state::{self,DeviceState},
+    history::RoutingHistory,
+    logging::SERIAL_TARGET,
+    compare
+};
 
 const INFO_CMD: &[u8;3] = b"I\r\n";
 const CLEAR_PRESET_CMD: &[u8;9] = b"\x1B+00P0*!\r";
 const GET_PRESET_NAME_CMD: &[u8;6] = b"\x1B00NG\r";
 const SAVE_CURRENT_CONFIG_CMD: &[u8;5] = b"00,\r\n";
 const LOAD_PRESET_CMD: &[u8;5] = b"00.\r\n";
+const LOCKOUT_STATUS_CMD: &[u8;3] = b"X\r\n";
+const SIGNAL_PRESENCE_CMD: &[u8;5] = b"0LS\r\n";
+/// Name the switcher reports for an empty preset slot
+pub const UNASSIGNED_PRESET: &str = "[unassigned]";
+/// Outputs a preset tie read covers
+const TIE_READ_OUTPUTS: i32 = 16;
+/// Global presets most CrossPoint and MAV models store
+pub const DEFAULT_PRESET_COUNT: i32 = 32;
+/// Highest preset number the two digit preset commands can address
+pub const MAX_PRESET_COUNT: i32 = 99;
+/// How long to wait for unsolicited messages when none may be pending
+const UNSOLICITED_TIMEOUT: Duration = Duration::from_millis(10);
+/// Longest name the switcher stores for a channel or preset
+pub const MAX_NAME_LENGTH: usize = 12;
+/// Characters SIS reserves, which cannot appear in a stored name
+const RESERVED_NAME_CHARS: &str = "+~,@=`[]{}<>'\";:|\\?";
+/// Executive mode 0 leaves the front panel unlocked, 1 locks it completely and 2 leaves
+/// only the basic switching controls usable
+pub const MAX_LOCKOUT_LEVEL: i32 = 2;
 
 pub struct CrossPoint {
-    port: Box<dyn SerialPort>,
+    port: Option<Box<dyn Transport>>,
+    settings: Option<ConnectionSettings>,
+    link: LinkState,
 
     serial_port: String,
     input_count: i32,
     output_count: i32,
     audio_support: bool,
+    enforced_lockout: Option<i32>,
+    /// Preset capacity, None until it has been detected
+    preset_count: Option<i32>,
+    configured_preset_count: Option<i32>,
+    state: DeviceState,
+    /// Routing snapshots, None unless `keep_history` was called
+    history: Option<RoutingHistory>,
+    /// Set while a change made of several commands is running, so it is recorded once
+    history_held: bool
+}
+
+/// Whether commands can currently reach the switcher
+#[derive(Clone, Debug, PartialEq)]
+pub enum LinkState {
+    Connected,
+    Disconnected { error: String, attempts: u32 }
 }
 
 impl CrossPoint {
-    pub fn connect(port_name: &str) -> Result<CrossPoint, Error> {
-        
-        let mut port: Box<dyn SerialPort>;
-        
-        if port_name == "virtual" {
-            port = VirtualCrosspoint::load_or_new();
+    /// Opens the configured port, which may be a serial device, `tcp://host[:port]` for a
+    /// switcher on the network, `virtual` for the simulator or `replay:<file>` to play back
+    /// a transcript. With a transcript file set, all traffic is recorded to it.
+    pub fn connect(settings: &ConnectionSettings) -> Result<CrossPoint, Error> {
+        let (port, port_name) = CrossPoint::open_transport(settings)?;
+        let mut cp = CrossPoint::open(port, &port_name)?;
+        cp.settings = Some(settings.clone());
+        Ok(cp)
+    }
+
+    /// Opens the transport, first searching the serial ports if the port name is `auto`
+    /// and trying each baud rate if none is set
+    fn open_transport(settings: &ConnectionSettings) -> Result<(Box<dyn Transport>, String), Error> {
+        let resolved = if settings.port_name == AUTO_DETECT {
+            discovery::detect(settings)?
+        } else if settings.serial.baud_rate.is_none() && transport::is_serial(&settings.port_name) {
+            discovery::detect_baud_rate(settings)?
         } else {
-            port = serialport::new(port_name, 9600)
-                .data_bits(serialport::DataBits::Eight)
-                .flow_control(serialport::FlowControl::None)
-                .parity(serialport::Parity::None)
-                .stop_bits(serialport::StopBits::One)
-                .timeout(Duration::from_secs(1))
-                .open()?;
-        }
-
-        let serial_port = String::from(port_name);
-        
-        port.write(INFO_CMD)?;
-        port.flush()?;
+            settings.clone()
+        };
+        Ok((transport::open(&resolved)?, resolved.port_name))
+    }
 
-        let mut reader = BufReader::new(port);
-        let mut response = String::new();
-        reader.read_line(&mut response)?;
-        port = reader.into_inner(); //Reclaim ownership of port
+    /// A CrossPoint whose port could not be opened yet. Commands fail until `reconnect` succeeds.
+    pub fn disconnected(settings: &ConnectionSettings, error: &Error) -> CrossPoint {
+        CrossPoint {
+            port: None,
+            settings: Some(settings.clone()),
+            link: LinkState::Disconnected { error: error.to_string(), attempts: 0 },
+            serial_port: settings.port_name.clone(),
+            input_count: 0,
+            output_count: 0,
+            audio_support: false,
+            enforced_lockout: None,
+            preset_count: None,
+            configured_preset_count: None,
+            state: DeviceState::new(0, 0, 0),
+            history: None,
+            history_held: false
+        }
+    }
+
+    /// Runs the info handshake over an already opened transport
+    pub fn open(port: Box<dyn Transport>, port_name: &str) -> Result<CrossPoint, Error> {
+        let mut cp = CrossPoint {
+            port: Some(port),
+            settings: None,
+            link: LinkState::Connected,
+            serial_port: String::from(port_name),
+            input_count: 0,
+            output_count: 0,
+            audio_support: false,
+            enforced_lockout: None,
+            preset_count: None,
+            configured_preset_count: None,
+            state: DeviceState::new(0, 0, 0),
+            history: None,
+            history_held: false
+        };
+
+        let (input_count, output_count, audio_support) = cp.identify()?;
+        cp.input_count = input_count;
+        cp.output_count = output_count;
+        cp.audio_support = audio_support;
+        cp.state = DeviceState::new(0, input_count as usize, output_count as usize);
+        Ok(cp)
+    }
+
+    /// Sends the info command and parses the reported sizes
+    fn identify(&mut self) -> Result<(i32, i32, bool), Error> {
+        let response = self.send_command(INFO_CMD)?;
 
         let parts = response.trim().split(' ');
         let mut input_count = 0;
@@ -55,15 +145,18 @@ impl CrossPoint {
         for part in parts {
             if part.starts_with('V') {
                 let mut port_counts = part.trim_start_matches('V').split('X');
-                input_count = match port_counts.next().unwrap().parse() { Ok(incount) => incount, _ => 0 };
-                output_count = match port_counts.next().unwrap().parse() { Ok(outcount) => outcount, _ => 0 };
+                input_count = port_counts.next().unwrap_or_default().parse().unwrap_or_default();
+                output_count = port_counts.next().unwrap_or_default().parse().unwrap_or_default();
             }
             else if part.starts_with('A') {
-                audio_support = true;
+                audio_support = part.trim_start_matches('A').split('X').any(|c| c.parse().unwrap_or(0) > 0);
             }
         }
 
-        Ok(CrossPoint { port, serial_port, input_count, output_count, audio_support })
+        if input_count == 0 || output_count == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Unrecognized info reply \"{}\"", response)));
+        }
+        Ok((input_count, output_count, audio_support))
     }
 
     pub fn input_port_count(&self) -> i32 { self.input_count }
@@ -72,48 +165,368 @@ impl CrossPoint {
 
     pub fn audio_is_supported(&self) -> bool { self.audio_support }
 
+    /// Level ranges for the audio board, or None if the model has no audio. Every CrossPoint
+    /// and MAV model with audio has the same ranges, so the model is not asked which it is.
+    pub fn audio_limits(&self) -> Option<AudioLimits> {
+        if self.audio_support { Some(AudioLimits::CROSSPOINT) } else { None }
+    }
+
     pub fn port_name(&self) -> &str { &self.serial_port }
 
+    pub fn link_state(&self) -> &LinkState { &self.link }
+
+    pub fn is_connected(&self) -> bool { self.link == LinkState::Connected }
+
+    /// Reopens the port, repeats the info handshake and checks the switcher still reports
+    /// the sizes it had when first connected. Reapplies the enforced lockout, if any.
+    pub fn reconnect(&mut self) -> Result<(), Error> {
+        let attempts = self.attempts();
+        let result = self.reopen();
+        self.link = match &result {
+            Ok(_) => LinkState::Connected,
+            Err(e) => LinkState::Disconnected { error: e.to_string(), attempts: attempts + 1 }
+        };
+        result?;
+
+        match self.enforced_lockout {
+            Some(level) => self.set_lockout(level).map(|_| ()),
+            None => Ok(())
+        }
+    }
+
+    /// Locks the front panel at `level` now, if connected, and every time the link is reestablished
+    pub fn enforce_lockout(&mut self, level: i32) -> Result<(), Error> {
+        if !(0..=MAX_LOCKOUT_LEVEL).contains(&level) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Lockout level must be between 0 and {}", MAX_LOCKOUT_LEVEL)));
+        }
+        self.enforced_lockout = Some(level);
+        if !self.is_connected() { return Ok(()); }
+        self.set_lockout(level).map(|_| ())
+    }
+
+    fn attempts(&self) -> u32 {
+        match &self.link {
+            LinkState::Connected => 0,
+            LinkState::Disconnected { attempts, .. } => *attempts
+        }
+    }
+
+    fn reopen(&mut self) -> Result<(), Error> {
+        let reopened = match self.port.as_mut() {
+            Some(port) => port.reconnect(),
+            None => Err(Error::new(ErrorKind::NotConnected, "Port was never opened"))
+        };
+        if let Err(e) = reopened {
+            match self.settings.as_ref() {
+                Some(settings) => {
+                    let (port, port_name) = CrossPoint::open_transport(settings)?;
+                    self.port = Some(port);
+                    self.serial_port = port_name;
+                }
+                None => return Err(e)
+            }
+        }
+
+        self.link = LinkState::Connected;
+        let (input_count, output_count, audio_support) = self.identify()?;
+        if self.input_count > 0 && (input_count, output_count) != (self.input_count, self.output_count) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Device reports {}x{}, expected {}x{}",
+                input_count, output_count, self.input_count, self.output_count)));
+        }
+        self.input_count = input_count;
+        self.output_count = output_count;
+        self.audio_support = audio_support;
+        self.preset_count = self.configured_preset_count;
+        let preset_count = self.preset_count.unwrap_or(0) as usize;
+        self.state = DeviceState::new(preset_count, input_count as usize, output_count as usize);
+        Ok(())
+    }
+
+    fn disconnect(&mut self, error: &Error) {
+        if self.is_connected() {
+            self.link = LinkState::Disconnected { error: error.to_string(), attempts: 0 };
+        }
+    }
+
+    /// Gives the transport a chance to keep an idle link open
+    pub fn keep_alive(&mut self) -> Result<(), Error> {
+        let result = match self.port.as_mut() {
+            Some(port) => port.keep_alive(),
+            None => Ok(())
+        };
+        if let Err(e) = &result { self.disconnect(e); }
+        result
+    }
+
+    /// Sets the number of presets the switcher stores, instead of asking it. None goes
+    /// back to detecting the count.
+    pub fn set_preset_count(&mut self, preset_count: Option<i32>) {
+        self.configured_preset_count = preset_count;
+        self.preset_count = preset_count;
+        if let Some(count) = preset_count { self.state.set_preset_count(count as usize); }
+    }
+
+    /// Number of global presets the switcher stores, found the first time it is needed
+    pub fn preset_count(&mut self) -> Result<i32, Error> {
+        if let Some(count) = self.preset_count { return Ok(count); }
+
+        let count = self.detect_preset_count()?;
+        self.preset_count = Some(count);
+        self.state.set_preset_count(count as usize);
+        Ok(count)
+    }
+
+    /// Finds the highest preset number the switcher accepts. Presets beyond its capacity
+    /// are rejected with E11. A read that times out without a reply fails the detection,
+    /// so it is tried again the next time the count is needed.
+    fn detect_preset_count(&mut self) -> Result<i32, Error> {
+        let mut accepted = 0;
+        let mut rejected = MAX_PRESET_COUNT + 1;
+        while rejected - accepted > 1 {
+            let preset_number = (accepted + rejected) / 2;
+            match self.read_preset_name(preset_number) {
+                Ok(name) if !name.is_empty() => accepted = preset_number,
+                Ok(_) => return Err(Error::new(ErrorKind::TimedOut, "No reply while detecting the preset count")),
+                Err(e) if e.kind() == ErrorKind::InvalidInput => rejected = preset_number,
+                Err(e) => return Err(e)
+            }
+        }
+        // Models that cannot read preset names back at all still have the usual presets
+        Ok(if accepted == 0 { DEFAULT_PRESET_COUNT } else { accepted })
+    }
+
+    /// Preset name from the state cache, read from the switcher if it is not known yet
     pub fn get_preset_name(&mut self, preset_number: i32) -> Result<String, Error> {
-        let mut cmd = GET_PRESET_NAME_CMD.clone();
+        if let Some(name) = self.state.preset_name(preset_number) { return Ok(name.to_string()); }
+
+        let name = self.read_preset_name(preset_number)?;
+        self.state.set_preset_name(preset_number, Some(name.clone()));
+        Ok(name)
+    }
+
+    fn read_preset_name(&mut self, preset_number: i32) -> Result<String, Error> {
+        let mut cmd = *GET_PRESET_NAME_CMD;
         cmd[1] = ((preset_number / 10) + 0x30) as u8;
         cmd[2] = ((preset_number % 10) + 0x30) as u8;
 
-        self.send_command(&cmd)
+        Ok(self.send_command(&cmd)?.trim().to_string())
+    }
+
+    /// Names of every preset, `[unassigned]` for empty slots
+    pub fn preset_names(&mut self) -> Result<Vec<String>, Error> {
+        let preset_count = self.preset_count()?;
+        (1..=preset_count).map(|i| self.get_preset_name(i)).collect()
+    }
+
+    /// Reads a stored preset's name and ties, or None if the slot is unassigned
+    pub fn get_preset(&mut self, preset_number: i32) -> Result<Option<CrossPointPreset>, Error> {
+        let name = self.get_preset_name(preset_number)?;
+        if name == UNASSIGNED_PRESET { return Ok(None); }
+
+        let video = self.read_preset_ties(preset_number, 1)?;
+        let audio = if self.audio_support { self.read_preset_ties(preset_number, 2)? } else { video.clone() };
+        Ok(Some(CrossPointPreset { number: preset_number, name, ties: state::merge_ties(&video, &audio) }))
+    }
+
+    /// `Esc nn*out*1VC` lists the inputs a preset ties to 16 outputs from `out`, with 1 for
+    /// video and 2 for audio
+    fn read_preset_ties(&mut self, preset_number: i32, plane: i32) -> Result<Vec<u8>, Error> {
+        let mut inputs = Vec::new();
+        for first_output in (1..=self.output_count).step_by(TIE_READ_OUTPUTS as usize) {
+            let response = self.send_command(format!("\x1B{}*{}*{}VC\r", preset_number, first_output, plane).as_bytes())?;
+            for input in response.split_whitespace() {
+                match input.parse() {
+                    Ok(i) => inputs.push(i),
+                    Err(_) => return Err(Error::new(ErrorKind::InvalidData, format!("Unrecognized tie reply \"{}\"", response.trim())))
+                }
+            }
+        }
+        inputs.truncate(self.output_count as usize);
+        Ok(inputs)
+    }
+
+    /// Ties currently applied on the switcher, read from it if they are not known
+    pub fn current_ties(&mut self) -> Result<Vec<CrossPointTie>, Error> {
+        if let Some(ties) = self.state.ties() { return Ok(ties); }
+
+        let mut video = Vec::new();
+        let mut audio = Vec::new();
+        for output in 1..=self.output_count {
+            let response = self.send_command(format!("{}%\r\n", output).as_bytes())?;
+            video.push(parse_level(&response)? as u8);
+            if self.audio_support {
+                let response = self.send_command(format!("{}$\r\n", output).as_bytes())?;
+                audio.push(parse_level(&response)? as u8);
+            }
+        }
+        if !self.audio_support { audio = video.clone(); }
+
+        self.state.set_ties(video, audio);
+        Ok(self.state.ties().unwrap_or_default())
+    }
+
+    /// Forgets everything cached about the switcher and reads it all again
+    pub fn refresh_state(&mut self) -> Result<(), Error> {
+        let preset_count = self.preset_count()?;
+        self.state = DeviceState::new(preset_count as usize, self.input_count as usize, self.output_count as usize);
+        self.preset_names()?;
+        for i in 1..=self.input_count { self.get_input_name(i)?; }
+        for i in 1..=self.output_count { self.get_output_name(i)?; }
+        self.current_ties()?;
+        Ok(())
+    }
+
+    /// Starts keeping a snapshot of the ties before each routing change, whether made through
+    /// this connection or on the front panel
+    pub fn keep_history(&mut self) {
+        if self.history.is_none() { self.history = Some(RoutingHistory::new()); }
+    }
+
+    pub fn history(&self) -> Option<&RoutingHistory> { self.history.as_ref() }
+
+    /// Runs a change made of several commands as a single entry in the routing history
+    pub fn as_one_change<T>(&mut self, cause: &str, change: impl FnOnce(&mut CrossPoint) -> T) -> T {
+        let held = self.begin_change(cause);
+        let result = change(self);
+        self.end_change(held);
+        result
+    }
+
+    /// Starts a change that the history keeps as one entry. Pass what it returns to
+    /// `end_change` before letting go of the port, so changes made by others are still kept.
+    pub fn begin_change(&mut self, cause: &str) -> bool {
+        self.snapshot_routing(cause);
+        std::mem::replace(&mut self.history_held, true)
+    }
+
+    pub fn end_change(&mut self, held: bool) {
+        self.history_held = held;
+    }
+
+    fn snapshot_routing(&mut self, cause: &str) {
+        if self.history.is_none() || self.history_held { return; }
+        if let Ok(ties) = self.current_ties() {
+            if let Some(history) = self.history.as_mut() { history.record(cause, ties); }
+        }
+    }
+
+    /// Puts back the ties of a history entry
+    pub fn restore_history(&mut self, id: u64) -> Result<(), Error> {
+        let ties = match self.history.as_ref().and_then(|h| h.get(id)) {
+            Some(entry) => entry.ties.clone(),
+            None => return Err(Error::new(ErrorKind::NotFound, "No such history entry"))
+        };
+        self.as_one_change(&format!("Restore {}", id), |cp| cp.apply_ties(&ties))
+    }
+
+    /// Makes the switcher's ties match `ties`, tying only the outputs that differ
+    pub fn apply_ties(&mut self, ties: &[CrossPointTie]) -> Result<(), Error> {
+        let current = self.current_ties()?;
+        for change in compare::compare_ties(&current, ties) {
+            let input_channel = change.to_input.unwrap_or(0);
+            self.tie(CrossPointTie { input_channel, output_channel: change.output_channel, io_type: change.io_type })?;
+        }
+        Ok(())
+    }
+
+    /// Reads any messages the switcher sent on its own, such as ties made on the front
+    /// panel, and applies them to the state cache. Returns how many were understood.
+    pub fn poll_unsolicited(&mut self) -> Result<usize, Error> {
+        let port = match (self.port.as_mut(), &self.link) {
+            (Some(port), LinkState::Connected) => port,
+            _ => return Ok(0)
+        };
+
+        let timeout = port.timeout();
+        port.set_timeout(UNSOLICITED_TIMEOUT)?;
+        let mut received = Vec::new();
+        let mut buffer = [0_u8; 64];
+        let result = loop {
+            match port.read(&mut buffer) {
+                Ok(0) => break Ok(()),
+                Ok(count) => received.extend_from_slice(&buffer[..count]),
+                Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e)
+            }
+        };
+        let restored = port.set_timeout(timeout);
+        if let Err(e) = result.and(restored) {
+            self.disconnect(&e);
+            return Err(e);
+        }
+
+        let mut understood = 0;
+        let mut routing_changed = false;
+        for line in String::from_utf8_lossy(&received).split(['\r', '\n']) {
+            if !line.is_empty() { log::trace!(target: SERIAL_TARGET, "< {}", line.escape_default()); }
+            if !routing_changed && state::changes_routing(line) {
+                routing_changed = true;
+                if let (Some(history), Some(ties)) = (self.history.as_mut(), self.state.ties()) {
+                    history.record("Front panel", ties);
+                }
+            }
+            if self.state.observe(line) { understood += 1; }
+        }
+        // A recall leaves the ties unknown, and the next snapshot needs them
+        if routing_changed && self.history.is_some() { self.current_ties()?; }
+        Ok(understood)
     }
 
+    /// Sends a command and reads its reply. Write failures and read errors other than a
+    /// timeout mark the link as disconnected.
     fn send_command(&mut self, cmd: &[u8]) -> Result<String, Error> {
+        let result = self.exchange(cmd);
+        if let Err(e) = &result { self.disconnect(e); }
+
+        let response = result?;
+        match sis_error(&response) {
+            Some(description) => Err(Error::new(ErrorKind::InvalidInput, description)),
+            None => {
+                self.state.observe(&response);
+                Ok(response)
+            }
+        }
+    }
+
+    fn exchange(&mut self, cmd: &[u8]) -> Result<String, Error> {
+        let port = match (self.port.as_mut(), &self.link) {
+            (Some(port), LinkState::Connected) => port,
+            _ => return Err(Error::new(ErrorKind::NotConnected, "CrossPoint is not connected"))
+        };
+
         //Write command
-        self.port.write(&cmd)?;
-        self.port.flush()?;
+        log::trace!(target: SERIAL_TARGET, "> {}", cmd.escape_ascii());
+        port.write_all(cmd)?;
+        port.flush()?;
 
         //Read response
         let mut response: Vec<u8> = Vec::new();
         let mut read_count: usize;
-        let mut buffer = [0 as u8; 8];
+        let mut buffer = [0_u8; 8];
         'bufferloop: loop  {
-            read_count = match self.port.read(&mut buffer) {
-                Ok(count) => { 
-                    for idx in 0..count {
-                        let ch = buffer[idx];
+            read_count = match port.read(&mut buffer) {
+                Ok(count) => {
+                    for &ch in &buffer[..count] {
                         if ch == 0x0D {
                             break 'bufferloop
-                        } else {
+                        } else if ch != 0x0A {
                             response.push(ch);
                         }
                     }
                     count
                 }
-                Err(_) => 0
+                Err(e) if e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock => 0,
+                Err(e) => return Err(e)
             };
             if read_count == 0 { break; }
         }
 
-        Ok(String::from_utf8(response).unwrap())
+        log::trace!(target: SERIAL_TARGET, "< {}", response.escape_ascii());
+        Ok(String::from_utf8_lossy(&response).to_string())
     }
 
     pub fn save_current_config(&mut self, preset_number: i32) -> Result<String, Error> {
-        let mut cmd= SAVE_CURRENT_CONFIG_CMD.clone();
+        let mut cmd = *SAVE_CURRENT_CONFIG_CMD;
         cmd[0] = ((preset_number / 10) + 0x30) as u8;
         cmd[1] = ((preset_number % 10) + 0x30) as u8;
 
@@ -121,84 +534,380 @@ impl CrossPoint {
     }
 
     pub fn load_preset(&mut self, preset_number: i32) -> Result<String, Error> {
-        let mut cmd = LOAD_PRESET_CMD.clone();
+        let mut cmd = *LOAD_PRESET_CMD;
         cmd[0] = ((preset_number / 10) + 0x30) as u8;
         cmd[1] = ((preset_number % 10) + 0x30) as u8;
 
-        self.send_command(&cmd)
+        self.snapshot_routing(&format!("Recall preset {}", preset_number));
+        let response = self.send_command(&cmd)?;
+        if self.history.is_some() { self.current_ties()?; }
+        Ok(response)
     }
 
     pub fn clear_preset(&mut self, preset_number: i32) -> Result<String, Error> {
-        let mut cmd = CLEAR_PRESET_CMD.clone();
+        let mut cmd = *CLEAR_PRESET_CMD;
         cmd[2] = ((preset_number / 10) + 0x30) as u8;
         cmd[3] = ((preset_number % 10) + 0x30) as u8;
 
         self.send_command(&cmd)
     }
 
+    /// Ties an input to an output right away
+    pub fn tie(&mut self, tie: CrossPointTie) -> Result<String, Error> {
+        self.snapshot_routing(&describe_ties(&[tie]));
+        self.send_command(format!("{}\r\n", tie).as_bytes())
+    }
+
+    /// Stores a preset's name on the switcher without touching its ties
+    pub fn set_preset_name(&mut self, preset_number: i32, name: &str) -> Result<String, Error> {
+        validate_name(name)?;
+        self.send_command(format!("\x1B{},{}NG\r", preset_number, name).as_bytes())
+    }
+
+    /// Replaces a preset's ties and, if the preset has one, its name
     pub fn create_preset(&mut self, new_preset: CrossPointPreset) -> Result<String, Error> {
+        if !new_preset.name.is_empty() { validate_name(&new_preset.name)?; }
+
         //Clear preset first
         self.clear_preset(new_preset.number)?;
 
         let mut cmd: Vec<u8> = Vec::new();
-        cmd.push(0x1B as u8); //Esc
-        cmd.push('+' as u8);
-        if new_preset.number > 10 {
+        cmd.push(0x1B); //Esc
+        cmd.push(b'+');
+        if new_preset.number >= 10 {
             cmd.push(((new_preset.number / 10) + 0x30) as u8);
         }
         cmd.push(((new_preset.number % 10) + 0x30) as u8);
-        cmd.push('P' as u8);
+        cmd.push(b'P');
         for tie in new_preset.ties {
             let in_channel = tie.input_channel;
-            if in_channel > 10 {
-                cmd.push(((in_channel / 10) + 0x30) as u8);
+            if in_channel >= 10 {
+                cmd.push((in_channel / 10) + 0x30);
             }
-            cmd.push(((in_channel % 10) + 0x30) as u8);
+            cmd.push((in_channel % 10) + 0x30);
 
-            cmd.push('*' as u8);
+            cmd.push(b'*');
 
             let out_channel = tie.output_channel;
-            if out_channel > 10 {
-                cmd.push(((out_channel / 10) + 0x30) as u8);
+            if out_channel >= 10 {
+                cmd.push((out_channel / 10) + 0x30);
             }
-            cmd.push(((out_channel % 10) + 0x30) as u8);
+            cmd.push((out_channel % 10) + 0x30);
             cmd.push(tie.io_type.to_char() as u8);
         }
-        cmd.push('\r' as u8);
+        cmd.push(b'\r');
 
-        self.send_command(&cmd)
+        let response = self.send_command(&cmd)?;
+        if new_preset.name.is_empty() { return Ok(response); }
+        self.set_preset_name(new_preset.number, &new_preset.name)
     }
 
+    /// Output volume, from 0 (muted) to the model's maximum
+    pub fn get_volume(&mut self, output: i32) -> Result<i32, Error> {
+        self.audio_channel(output, self.output_count, "Invalid output number")?;
+        let response = self.send_command(format!("{}V\r\n", output).as_bytes())?;
+        parse_level(&response)
+    }
+
+    pub fn set_volume(&mut self, output: i32, level: i32) -> Result<String, Error> {
+        let limits = self.audio_channel(output, self.output_count, "Invalid output number")?;
+        if !(0..=limits.max_volume).contains(&level) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Volume must be between 0 and {}", limits.max_volume)));
+        }
+
+        self.send_command(format!("{}*{}V\r\n", output, level).as_bytes())
+    }
+
+    /// Input level in dB. Positive values are gain, negative values attenuation.
+    pub fn get_gain(&mut self, input: i32) -> Result<i32, Error> {
+        self.audio_channel(input, self.input_count, "Invalid input channel number")?;
+        let response = self.send_command(format!("{}G\r\n", input).as_bytes())?;
+        parse_level(&response)
+    }
+
+    /// Sets the input level in dB, sending `G` for gain and `A` for attenuation
+    pub fn set_gain(&mut self, input: i32, level: i32) -> Result<String, Error> {
+        let limits = self.audio_channel(input, self.input_count, "Invalid input channel number")?;
+        if !(-limits.max_attenuation..=limits.max_gain).contains(&level) {
+            return Err(Error::new(ErrorKind::InvalidInput,
+                format!("Gain must be between -{} and {} dB", limits.max_attenuation, limits.max_gain)));
+        }
+
+        let cmd = if level < 0 { format!("{}*{}A\r\n", input, -level) } else { format!("{}*{}G\r\n", input, level) };
+        self.send_command(cmd.as_bytes())
+    }
+
+    /// Blanks an output's video without breaking its tie
+    pub fn set_video_mute(&mut self, output: i32, muted: bool) -> Result<String, Error> {
+        self.output_channel(output)?;
+        self.send_command(format!("{}*{}B\r\n", output, muted as i32).as_bytes())
+    }
+
+    pub fn set_audio_mute(&mut self, output: i32, muted: bool) -> Result<String, Error> {
+        self.audio_channel(output, self.output_count, "Invalid output number")?;
+        self.send_command(format!("{}*{}Z\r\n", output, muted as i32).as_bytes())
+    }
+
+    /// Mutes or unmutes the video on every output
+    pub fn set_global_video_mute(&mut self, muted: bool) -> Result<String, Error> {
+        self.send_command(format!("{}*B\r\n", muted as i32).as_bytes())
+    }
+
+    pub fn set_global_audio_mute(&mut self, muted: bool) -> Result<String, Error> {
+        if self.audio_limits().is_none() {
+            return Err(Error::new(ErrorKind::Unsupported, "CrossPoint has no audio"));
+        }
+        self.send_command(format!("{}*Z\r\n", muted as i32).as_bytes())
+    }
+
+    pub fn video_mute_status(&mut self, output: i32) -> Result<bool, Error> {
+        self.output_channel(output)?;
+        let response = self.send_command(format!("{}B\r\n", output).as_bytes())?;
+        Ok(parse_level(&response)? != 0)
+    }
+
+    pub fn audio_mute_status(&mut self, output: i32) -> Result<bool, Error> {
+        self.audio_channel(output, self.output_count, "Invalid output number")?;
+        let response = self.send_command(format!("{}Z\r\n", output).as_bytes())?;
+        Ok(parse_level(&response)? != 0)
+    }
+
+    /// Sets the executive mode, locking the front panel buttons
+    pub fn set_lockout(&mut self, level: i32) -> Result<String, Error> {
+        if !(0..=MAX_LOCKOUT_LEVEL).contains(&level) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Lockout level must be between 0 and {}", MAX_LOCKOUT_LEVEL)));
+        }
+        self.send_command(format!("{}X\r\n", level).as_bytes())
+    }
+
+    /// Current executive mode, 0 when the front panel is unlocked
+    pub fn lockout(&mut self) -> Result<i32, Error> {
+        let response = self.send_command(LOCKOUT_STATUS_CMD)?;
+        parse_level(&response)
+    }
+
+    pub fn get_input_name(&mut self, input: i32) -> Result<String, Error> {
+        self.input_channel(input)?;
+        if let Some(name) = self.state.input_name(input) { return Ok(name.to_string()); }
+
+        let name = self.send_command(format!("\x1B{}NI\r", input).as_bytes())?.trim().to_string();
+        self.state.set_input_name(input, Some(name.clone()));
+        Ok(name)
+    }
+
+    /// Stores an input name on the switcher, where Extron's own tools can see it
+    pub fn set_input_name(&mut self, input: i32, name: &str) -> Result<String, Error> {
+        self.input_channel(input)?;
+        validate_name(name)?;
+        self.send_command(format!("\x1B{},{}NI\r", input, name).as_bytes())
+    }
+
+    pub fn get_output_name(&mut self, output: i32) -> Result<String, Error> {
+        self.output_channel(output)?;
+        if let Some(name) = self.state.output_name(output) { return Ok(name.to_string()); }
+
+        let name = self.send_command(format!("\x1B{}NO\r", output).as_bytes())?.trim().to_string();
+        self.state.set_output_name(output, Some(name.clone()));
+        Ok(name)
+    }
+
+    pub fn set_output_name(&mut self, output: i32, name: &str) -> Result<String, Error> {
+        self.output_channel(output)?;
+        validate_name(name)?;
+        self.send_command(format!("\x1B{},{}NO\r", output, name).as_bytes())
+    }
+
+    /// Whether each input currently has a signal, indexed from input 1. Models that do not
+    /// report signal presence fail with `ErrorKind::Unsupported`.
+    pub fn signal_presence(&mut self) -> Result<Vec<bool>, Error> {
+        let response = match self.send_command(SIGNAL_PRESENCE_CMD) {
+            Ok(r) => r,
+            Err(e) if e.kind() == ErrorKind::InvalidInput =>
+                return Err(Error::new(ErrorKind::Unsupported, "CrossPoint does not report signal presence")),
+            Err(e) => return Err(e)
+        };
+
+        let flags = match response.trim().strip_prefix("Frq00") {
+            Some(f) => f,
+            None => return Err(Error::new(ErrorKind::InvalidData, format!("Unrecognized signal reply \"{}\"", response)))
+        };
+        let presence: Vec<bool> = flags.chars().filter(|c| !c.is_whitespace()).map(|c| c == '1').collect();
+        if presence.len() != self.input_count as usize {
+            return Err(Error::new(ErrorKind::InvalidData, format!("Signal reply covers {} inputs, expected {}", presence.len(), self.input_count)));
+        }
+        Ok(presence)
+    }
+
+    fn input_channel(&self, input: i32) -> Result<(), Error> {
+        if !(1..=self.input_count).contains(&input) {
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid input channel number"));
+        }
+        Ok(())
+    }
+
+    fn output_channel(&self, output: i32) -> Result<(), Error> {
+        if !(1..=self.output_count).contains(&output) {
+            return Err(Error::new(ErrorKind::InvalidInput, "Invalid output number"));
+        }
+        Ok(())
+    }
+
+    /// Checks the model has audio and `channel` is one of its `count` channels
+    fn audio_channel(&self, channel: i32, count: i32, invalid: &str) -> Result<AudioLimits, Error> {
+        let limits = match self.audio_limits() {
+            Some(l) => l,
+            None => return Err(Error::new(ErrorKind::Unsupported, "CrossPoint has no audio"))
+        };
+        if !(1..=count).contains(&channel) {
+            return Err(Error::new(ErrorKind::InvalidInput, invalid));
+        }
+        Ok(limits)
+    }
+
+}
+
+/// Level ranges accepted by a model's audio board
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AudioLimits {
+    /// Highest volume step; 0 mutes the output
+    pub max_volume: i32,
+    /// Highest input gain in dB
+    pub max_gain: i32,
+    /// Deepest input attenuation in dB
+    pub max_attenuation: i32
+}
+
+impl AudioLimits {
+    /// CrossPoint and MAV audio, the same on every size and series: 64 volume steps, inputs
+    /// from -18 to +24 dB
+    pub const CROSSPOINT: AudioLimits = AudioLimits { max_volume: 64, max_gain: 24, max_attenuation: 18 };
+}
+
+/// Checks a name fits the switcher's storage: 1 to 12 printable ASCII characters, none of them reserved by SIS
+pub fn validate_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Name must be 1 to {} characters", MAX_NAME_LENGTH)));
+    }
+    if let Some(c) = name.chars().find(|c| !(' '..='~').contains(c) || RESERVED_NAME_CHARS.contains(*c)) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Name cannot contain '{}'", c)));
+    }
+    Ok(())
+}
+
+/// Reads the signed number at the end of a level reply such as `Vol32`, `In03 Aud-06` or `+4`
+fn parse_level(response: &str) -> Result<i32, Error> {
+    let level = response.trim().rsplit(|c: char| c.is_ascii_alphabetic() || c == ' ').next().unwrap_or_default();
+    match level.trim_start_matches('+').parse() {
+        Ok(l) => Ok(l),
+        Err(_) => Err(Error::new(ErrorKind::InvalidData, format!("Unrecognized level reply \"{}\"", response)))
+    }
+}
+
+/// Maps an `Enn` error reply to its description
+fn sis_error(response: &str) -> Option<&'static str> {
+    match response {
+        "E01" => Some("Invalid input channel number"),
+        "E10" => Some("Invalid command"),
+        "E11" => Some("Invalid preset number"),
+        "E12" => Some("Invalid output number"),
+        "E13" => Some("Invalid value"),
+        _ => None
+    }
 }
 
 impl CrossPointPreset {
-    pub fn from(json_request: json::JsonValue) -> CrossPointPreset {
-        let name = json_request["PresetName"].as_str().unwrap().to_string();
-        let number = json_request["PresetNumber"].as_i32().unwrap();
-        let ties = json_request["Inputs"].entries().map(|i| { 
-            CrossPointTie {
-                input_channel: i.1["InputChannel"].as_u8().unwrap(),
-                output_channel: i.1["OutputChannels"].as_u8().unwrap(),
-                io_type: CrossPointIO::from_str(i.1["IOType"].as_str().unwrap_or_default()).unwrap()
+    /// The preset in the createPreset request body format, one entry per input and tie type
+    pub fn to_json(&self) -> json::JsonValue {
+        let mut inputs: Vec<(u8, CrossPointIO, Vec<u8>)> = Vec::new();
+        for tie in &self.ties {
+            match inputs.iter_mut().find(|(i, t, _)| *i == tie.input_channel && *t == tie.io_type) {
+                Some((_, _, outputs)) => outputs.push(tie.output_channel),
+                None => inputs.push((tie.input_channel, tie.io_type, vec![tie.output_channel]))
+            }
+        }
+
+        let mut o = json::object::Object::new();
+        o.insert("PresetNumber", json::JsonValue::from(self.number));
+        o.insert("PresetName", json::JsonValue::from(self.name.as_str()));
+        o.insert("Inputs", json::JsonValue::Array(inputs.into_iter().map(|(input_channel, io_type, outputs)| {
+            let mut input = json::object::Object::new();
+            input.insert("InputChannel", json::JsonValue::from(input_channel));
+            input.insert("OutputChannels", json::JsonValue::from(outputs));
+            input.insert("IOType", json::JsonValue::from(format!("{:?}", io_type)));
+            json::JsonValue::Object(input)
+        }).collect()));
+        json::JsonValue::Object(o)
+    }
+
+    /// Builds a preset from the createPreset request body. Each entry of `Inputs` ties
+    /// `InputChannel` to one output or an array of `OutputChannels`.
+    pub fn from(json_request: json::JsonValue) -> Result<CrossPointPreset, String> {
+        let name = json_request["PresetName"].as_str().unwrap_or_default().to_string();
+        let number = match json_request["PresetNumber"].as_i32() {
+            Some(n) => n,
+            None => return Err(String::from("Missing preset number"))
+        };
+
+        let mut ties = Vec::new();
+        for input in json_request["Inputs"].members() {
+            let input_channel = match input["InputChannel"].as_u8() {
+                Some(i) => i,
+                None => return Err(String::from("Missing input channel"))
+            };
+            let io_type = match input["IOType"].as_str().map(CrossPointIO::from_str) {
+                None => CrossPointIO::All,
+                Some(Ok(io_type)) => io_type,
+                Some(Err(_)) => return Err(String::from("Invalid IO type"))
+            };
+
+            let outputs = &input["OutputChannels"];
+            let output_channels: Vec<Option<u8>> = if outputs.is_array() {
+                outputs.members().map(|o| o.as_u8()).collect()
+            } else {
+                vec![outputs.as_u8()]
+            };
+
+            for output_channel in output_channels {
+                match output_channel {
+                    Some(output_channel) => ties.push(CrossPointTie { input_channel, output_channel, io_type }),
+                    None => return Err(String::from("Invalid output channel"))
+                }
             }
-        }).collect();
+        }
 
-        CrossPointPreset { number, name, ties }
+        Ok(CrossPointPreset { number, name, ties })
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct CrossPointPreset {
     pub number: i32,
     pub name: String,
     pub ties: Vec<CrossPointTie>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct  CrossPointTie {
     pub input_channel: u8,
     pub output_channel: u8,
     pub io_type: CrossPointIO
 }
 
+/// Written the way SIS ties are, such as `7*2%`
+impl Display for CrossPointTie {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}*{}{}", self.input_channel, self.output_channel, self.io_type.to_char())
+    }
+}
+
+/// Describes ties for logs, events and history, such as `Tie 7*2% 7*3%`
+pub fn describe_ties(ties: &[CrossPointTie]) -> String {
+    let ties: Vec<String> = ties.iter().map(|t| t.to_string()).collect();
+    format!("Tie {}", ties.join(" "))
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum CrossPointIO {
     All,
     RGB,
@@ -207,7 +916,7 @@ pub enum CrossPointIO {
 }
 
 impl CrossPointIO {
-    fn to_char(&self) -> char {
+    pub fn to_char(self) -> char {
         match self {
             CrossPointIO::All => '!',
             CrossPointIO::RGB => '&',
@@ -215,8 +924,19 @@ impl CrossPointIO {
             CrossPointIO::Aud => '$'
         }
     }
+
+    pub fn from_char(c: char) -> Option<CrossPointIO> {
+        match c {
+            '!' => Some(CrossPointIO::All),
+            '&' => Some(CrossPointIO::RGB),
+            '%' => Some(CrossPointIO::Vid),
+            '$' => Some(CrossPointIO::Aud),
+            _ => None
+        }
+    }
 }
 
+/// Parses `All`, `RGB`, `Vid` or `Aud`, the tie types configuration files and requests use
 impl FromStr for CrossPointIO {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -225,7 +945,7 @@ impl FromStr for CrossPointIO {
             "RGB" => Ok(CrossPointIO::RGB),
             "Vid" => Ok(CrossPointIO::Vid),
             "Aud" => Ok(CrossPointIO::Aud),
-            _ => Ok(CrossPointIO::All)
+            _ => Err(())
         }
     }
-}
\ No newline at end of file
+}
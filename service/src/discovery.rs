@@ -0,0 +1,57 @@
+use std::io::{Error,ErrorKind};
+use serialport::SerialPortInfo;
+use crate::{
+    crosspoint::CrossPoint,
+    transport::{ConnectionSettings,SerialSettings,SUPPORTED_BAUD_RATES}
+};
+
+/// Port name that asks the service to find the CrossPoint itself
+pub const AUTO_DETECT: &str = "auto";
+
+/// Tries the info handshake on every serial port and returns settings for the first one
+/// that answers like a CrossPoint
+pub fn detect(settings: &ConnectionSettings) -> Result<ConnectionSettings, Error> {
+    for port in serialport::available_ports()? {
+        let candidate = ConnectionSettings {
+            port_name: port.port_name.clone(),
+            transcript: None,
+            ..settings.clone()
+        };
+        match CrossPoint::connect(&candidate) {
+            Ok(cp) => {
+                log::info!("Found CrossPoint {}x{} on port {}", cp.input_port_count(), cp.output_port_count(), port.port_name);
+                return Ok(ConnectionSettings { transcript: settings.transcript.clone(), ..candidate });
+            }
+            Err(e) => log::debug!("No CrossPoint on port {}: {}", port.port_name, e)
+        }
+    }
+
+    Err(Error::new(ErrorKind::NotFound, "No CrossPoint found on any serial port"))
+}
+
+/// Tries the info handshake at each supported baud rate and returns settings for the
+/// first rate the switcher answers at
+pub fn detect_baud_rate(settings: &ConnectionSettings) -> Result<ConnectionSettings, Error> {
+    for baud_rate in SUPPORTED_BAUD_RATES {
+        let candidate = ConnectionSettings {
+            transcript: None,
+            serial: SerialSettings { baud_rate: Some(baud_rate), ..settings.serial },
+            ..settings.clone()
+        };
+        match CrossPoint::connect(&candidate) {
+            Ok(_) => {
+                log::info!("CrossPoint on port {} answers at {} baud", settings.port_name, baud_rate);
+                return Ok(ConnectionSettings { transcript: settings.transcript.clone(), ..candidate });
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound || e.kind() == ErrorKind::PermissionDenied => return Err(e),
+            Err(e) => log::debug!("No answer on port {} at {} baud: {}", settings.port_name, baud_rate, e)
+        }
+    }
+
+    Err(Error::new(ErrorKind::NotFound, format!("No CrossPoint answered on port {} at any baud rate", settings.port_name)))
+}
+
+/// Serial ports present on this machine, with USB details where available
+pub fn list_ports() -> Result<Vec<SerialPortInfo>, Error> {
+    Ok(serialport::available_ports()?)
+}
@@ -0,0 +1,121 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc,Mutex,MutexGuard}
+};
+use json::{JsonValue,object::Object,number::Number};
+
+/// Events kept for clients that poll; older ones are dropped
+const EVENT_CAPACITY: usize = 256;
+
+/// Something that changed on the switcher
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventKind {
+    /// `output` is None when every output was muted or unmuted at once
+    VideoMute { output: Option<i32>, muted: bool },
+    AudioMute { output: Option<i32>, muted: bool },
+    /// Front panel executive mode changed
+    Lockout { level: i32 },
+    /// A source on an input was powered on or off
+    SignalPresence { input: i32, present: bool },
+    /// An auto-routing rule fired. `error` is set if the switcher refused the action.
+    AutoRoute { rule: String, action: String, error: Option<String> },
+    /// A macro ran. `error` is set if a step failed and the macro was rolled back.
+    Macro { name: String, error: Option<String> },
+    /// A schedule came due. `error` is set if its action failed.
+    Schedule { name: String, action: String, error: Option<String> }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    pub sequence: u64,
+    pub kind: EventKind
+}
+
+impl Event {
+    pub fn to_json(&self) -> JsonValue {
+        let mut o = Object::new();
+        o.insert("Sequence", JsonValue::Number(Number::from(self.sequence)));
+        match &self.kind {
+            EventKind::VideoMute { output, muted } => {
+                o.insert("Type", JsonValue::String(String::from("VideoMute")));
+                o.insert("Output", output.map_or(JsonValue::Null, |n| JsonValue::Number(Number::from(n))));
+                o.insert("Muted", JsonValue::Boolean(*muted));
+            }
+            EventKind::AudioMute { output, muted } => {
+                o.insert("Type", JsonValue::String(String::from("AudioMute")));
+                o.insert("Output", output.map_or(JsonValue::Null, |n| JsonValue::Number(Number::from(n))));
+                o.insert("Muted", JsonValue::Boolean(*muted));
+            }
+            EventKind::Lockout { level } => {
+                o.insert("Type", JsonValue::String(String::from("Lockout")));
+                o.insert("Level", JsonValue::Number(Number::from(*level)));
+            }
+            EventKind::SignalPresence { input, present } => {
+                o.insert("Type", JsonValue::String(String::from("SignalPresence")));
+                o.insert("Input", JsonValue::Number(Number::from(*input)));
+                o.insert("Present", JsonValue::Boolean(*present));
+            }
+            EventKind::AutoRoute { rule, action, error } => {
+                o.insert("Type", JsonValue::String(String::from("AutoRoute")));
+                o.insert("Rule", JsonValue::String(rule.clone()));
+                o.insert("Action", JsonValue::String(action.clone()));
+                o.insert("Error", error.as_ref().map_or(JsonValue::Null, |e| JsonValue::String(e.clone())));
+            }
+            EventKind::Macro { name, error } => {
+                o.insert("Type", JsonValue::String(String::from("Macro")));
+                o.insert("Name", JsonValue::String(name.clone()));
+                o.insert("Error", error.as_ref().map_or(JsonValue::Null, |e| JsonValue::String(e.clone())));
+            }
+            EventKind::Schedule { name, action, error } => {
+                o.insert("Type", JsonValue::String(String::from("Schedule")));
+                o.insert("Name", JsonValue::String(name.clone()));
+                o.insert("Action", JsonValue::String(action.clone()));
+                o.insert("Error", error.as_ref().map_or(JsonValue::Null, |e| JsonValue::String(e.clone())));
+            }
+        }
+        JsonValue::Object(o)
+    }
+}
+
+/// Bounded log of switcher events numbered from 1. Clones share the same log, so the
+/// HTTP thread and background workers can publish to it.
+#[derive(Clone, Default)]
+pub struct EventLog {
+    inner: Arc<Mutex<EventBuffer>>
+}
+
+#[derive(Default)]
+struct EventBuffer {
+    events: VecDeque<Event>,
+    latest: u64
+}
+
+impl EventLog {
+    pub fn new() -> EventLog {
+        EventLog::default()
+    }
+
+    fn buffer(&self) -> MutexGuard<'_, EventBuffer> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Appends an event and returns its sequence number
+    pub fn publish(&self, kind: EventKind) -> u64 {
+        let mut buffer = self.buffer();
+        buffer.latest += 1;
+        let sequence = buffer.latest;
+        if buffer.events.len() == EVENT_CAPACITY { buffer.events.pop_front(); }
+        buffer.events.push_back(Event { sequence, kind });
+        sequence
+    }
+
+    /// Retained events with a sequence number above `sequence`
+    pub fn since(&self, sequence: u64) -> Vec<Event> {
+        self.buffer().events.iter().filter(|e| e.sequence > sequence).cloned().collect()
+    }
+
+    /// Sequence number of the most recent event, 0 if there has been none
+    pub fn latest(&self) -> u64 {
+        self.buffer().latest
+    }
+}
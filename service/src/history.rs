@@ -0,0 +1,70 @@
+use std::{
+    collections::VecDeque,
+    time::{SystemTime,UNIX_EPOCH}
+};
+use json::{JsonValue,object::Object,number::Number};
+use crate::crosspoint::CrossPointTie;
+
+/// Snapshots kept before the oldest are dropped
+const HISTORY_CAPACITY: usize = 50;
+
+/// The ties in use just before a routing change
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    /// Numbered from 1 in the order the snapshots were taken
+    pub id: u64,
+    pub time: SystemTime,
+    /// What was about to change the routing
+    pub cause: String,
+    pub ties: Vec<CrossPointTie>
+}
+
+impl HistoryEntry {
+    pub fn to_json(&self) -> JsonValue {
+        let mut o = Object::new();
+        o.insert("Id", JsonValue::Number(Number::from(self.id)));
+        o.insert("Time", JsonValue::Number(Number::from(self.time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()))));
+        o.insert("Cause", JsonValue::String(self.cause.clone()));
+        o.insert("Ties", JsonValue::Array(self.ties.iter().map(|t| {
+            let mut tie = Object::new();
+            tie.insert("InputChannel", JsonValue::Number(Number::from(t.input_channel)));
+            tie.insert("OutputChannel", JsonValue::Number(Number::from(t.output_channel)));
+            tie.insert("IOType", JsonValue::String(format!("{:?}", t.io_type)));
+            JsonValue::Object(tie)
+        }).collect()));
+        JsonValue::Object(o)
+    }
+}
+
+/// Bounded list of routing snapshots, newest last
+#[derive(Default)]
+pub struct RoutingHistory {
+    entries: VecDeque<HistoryEntry>,
+    latest: u64
+}
+
+impl RoutingHistory {
+    pub fn new() -> RoutingHistory {
+        RoutingHistory::default()
+    }
+
+    /// Keeps a snapshot and returns its id
+    pub fn record(&mut self, cause: &str, ties: Vec<CrossPointTie>) -> u64 {
+        self.latest += 1;
+        if self.entries.len() == HISTORY_CAPACITY { self.entries.pop_front(); }
+        self.entries.push_back(HistoryEntry { id: self.latest, time: SystemTime::now(), cause: cause.to_string(), ties });
+        self.latest
+    }
+
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    pub fn get(&self, id: u64) -> Option<&HistoryEntry> {
+        self.entries.iter().find(|e| e.id == id)
+    }
+
+    pub fn last(&self) -> Option<&HistoryEntry> {
+        self.entries.back()
+    }
+}
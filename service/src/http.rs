@@ -1,57 +1,363 @@
 use std::{
     collections::HashMap,
-    net::{TcpListener, SocketAddrV4, TcpStream},
+    net::{TcpListener, SocketAddr, SocketAddrV4, TcpStream},
     str::FromStr,
-    io::{Read,Write},
-    fmt::Debug
+    io::{Read,Write,Error,ErrorKind},
+    fmt::Debug,
+    cell::OnceCell,
+    sync::{Arc,atomic::{AtomicUsize,Ordering},mpsc::{self,Receiver}},
+    thread,
+    time::{Duration,Instant,SystemTime}
 };
+use rustls::{ServerConfig,ServerConnection,StreamOwned};
+use rustls_pki_types::{CertificateDer,PrivateKeyDer,pem::PemObject};
+use crate::logging::{self,ACCESS_TARGET};
+
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// Connections read at the same time. Others are closed until a worker is free.
+const MAX_CONNECTION_WORKERS: usize = 32;
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+const MAX_CONTENT_LENGTH: usize = 1024 * 1024;
+const HTTPS_PORT: u16 = 443;
+const ALLOWED_METHODS: &str = "GET, HEAD, POST, OPTIONS";
+const DEFAULT_CORS_HEADERS: &str = "Authorization, Content-Type";
 
 
 pub struct HttpListener {
-    pub listener: TcpListener
+    pub listener: TcpListener,
+    /// Set when requests are served over HTTPS
+    tls: Option<Arc<ServerConfig>>,
+    cors: Option<Arc<CorsPolicy>>,
+    /// Requests read by the connection workers, started by the first `receive`
+    requests: OnceCell<Receiver<ReceivedRequest>>
+}
+
+/// A request a connection worker has read, waiting to be answered
+struct ReceivedRequest {
+    request: HttpRequest,
+    stream: HttpStream,
+    client: SocketAddr,
+    received: Instant
 }
 
 impl HttpListener {
     pub fn bind(address: SocketAddrV4) -> std::io::Result<HttpListener> {
         Ok(HttpListener {
-            listener: TcpListener::bind(address)?
+            listener: TcpListener::bind(address)?,
+            tls: None,
+            cors: None,
+            requests: OnceCell::new()
         })
     }
 
+    /// Serves HTTPS with the PEM encoded certificate chain and private key in the given files
+    pub fn bind_tls(address: SocketAddrV4, certificate_file: &str, key_file: &str) -> std::io::Result<HttpListener> {
+        let invalid = |file: &str, e: &dyn std::fmt::Display| Error::new(ErrorKind::InvalidInput, format!("Cannot read {}: {}", file, e));
+
+        let certificates = match CertificateDer::pem_file_iter(certificate_file).and_then(|c| c.collect::<Result<Vec<_>, _>>()) {
+            Ok(c) if !c.is_empty() => c,
+            Ok(_) => return Err(invalid(certificate_file, &"no certificate found")),
+            Err(e) => return Err(invalid(certificate_file, &e))
+        };
+        let key = match PrivateKeyDer::from_pem_file(key_file) {
+            Ok(k) => k,
+            Err(e) => return Err(invalid(key_file, &e))
+        };
+        let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .and_then(|b| b.with_no_client_auth().with_single_cert(certificates, key));
+        let config = match config {
+            Ok(c) => c,
+            Err(e) => return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid certificate or key: {}", e)))
+        };
+
+        Ok(HttpListener {
+            listener: TcpListener::bind(address)?,
+            tls: Some(Arc::new(config)),
+            cors: None,
+            requests: OnceCell::new()
+        })
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+
+    /// Lets browser pages from other origins call the API
+    pub fn set_cors(&mut self, cors: CorsPolicy) {
+        self.cors = Some(Arc::new(cors));
+    }
+
+    /// Waits for the next request. Connections are accepted in the background and each is
+    /// read, TLS handshake included, on its own worker, so a client that stalls holds up
+    /// only itself.
     pub fn receive(&self) -> Result<HttpContext, HttpError> {
-        let mut stream = match self.listener.accept() {
-            Ok(s) => {
-                println!("Request from {}", s.1);
-                s.0
-            }
-            Err(e) => {
-                println!("Error receiving stream: {:?}", e);
-                return Err(HttpError::new(500, "Bad stream"))
+        let requests = match self.requests.get() {
+            Some(r) => r,
+            None => {
+                let listener = match self.listener.try_clone() {
+                    Ok(l) => l,
+                    Err(e) => return Err(HttpError::new(500, &e.to_string()))
+                };
+                self.requests.get_or_init(|| accept_in_background(listener, self.tls.clone()))
             }
         };
+        match requests.recv() {
+            Ok(r) => Ok(HttpContext { request: r.request, stream: r.stream, cors: self.cors.clone(), client: r.client, received: r.received }),
+            Err(_) => Err(HttpError::new(500, "The listener has stopped"))
+        }
+    }
+}
 
-        Ok(HttpContext {
-            request: HttpRequest::read(&mut stream)?,
-            stream
-        })
+/// Accepts connections and reads each one's request on a worker of its own. Workers wait
+/// until their request is taken, so a bounded number of requests is held at a time.
+fn accept_in_background(listener: TcpListener, tls: Option<Arc<ServerConfig>>) -> Receiver<ReceivedRequest> {
+    let (sender, receiver) = mpsc::sync_channel(MAX_CONNECTION_WORKERS);
+    let busy = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        loop {
+            let (stream, client) = match listener.accept() {
+                Ok(s) => s,
+                Err(e) => {
+                    log::warn!("Error receiving stream: {:?}", e);
+                    continue;
+                }
+            };
+            if busy.load(Ordering::SeqCst) >= MAX_CONNECTION_WORKERS {
+                log::warn!("Closing the connection from {}, {} are already being read", client, MAX_CONNECTION_WORKERS);
+                continue;
+            }
+            busy.fetch_add(1, Ordering::SeqCst);
+            let sender = sender.clone();
+            let tls = tls.clone();
+            let busy = busy.clone();
+            thread::spawn(move || {
+                if let Some(request) = read_request(stream, client, tls) {
+                    _ = sender.send(request);
+                }
+                busy.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+    receiver
+}
+
+/// Reads a connection's request, answering it with an error if it cannot be read
+fn read_request(stream: TcpStream, client: SocketAddr, tls: Option<Arc<ServerConfig>>) -> Option<ReceivedRequest> {
+    let received = Instant::now();
+
+    _ = stream.set_read_timeout(Some(REQUEST_READ_TIMEOUT));
+    let mut stream = match tls {
+        Some(config) => match ServerConnection::new(config) {
+            Ok(connection) => HttpStream::Tls(Box::new(StreamOwned::new(connection, stream))),
+            Err(e) => {
+                log::warn!("Error starting TLS for {}: {}", client, e);
+                return None;
+            }
+        },
+        None => HttpStream::Plain(stream)
+    };
+    match HttpRequest::read(&mut stream) {
+        Ok(request) => Some(ReceivedRequest { request, stream, client, received }),
+        Err(e) => {
+            _ = stream.write_all(HttpResponse::from_error(&e).compose().as_bytes());
+            log_access(client, "-", e.code.code, e.message.len(), received);
+            None
+        }
     }
 }
 
 
 pub struct HttpContext {
     pub request: HttpRequest,
-    stream: TcpStream,
+    stream: HttpStream,
+    cors: Option<Arc<CorsPolicy>>,
+    client: SocketAddr,
+    received: Instant
 }
 
 impl HttpContext {
+    /// Sends the response with the CORS headers the request calls for. The content is left
+    /// out for HEAD requests, OPTIONS requests are told which methods are allowed, and 401
+    /// responses challenge for the kind of credential the request sent.
     pub fn send_response(&mut self, response: HttpResponse) {
-        _ = self.stream.write_all(&mut response.compose().as_bytes());
+        let mut headers = match self.cors.as_ref() {
+            Some(cors) => cors.headers(&self.request),
+            None => Vec::new()
+        };
+        if self.request.method == HttpMethod::OPTIONS {
+            headers.push((String::from("Allow"), String::from(ALLOWED_METHODS)));
+        }
+        if response.status_code.code == 401 {
+            // Token clients are challenged for a token, so browsers only prompt for a password otherwise
+            let used_token = self.request.header("Authorization")
+                .is_some_and(|a| a.trim_start().get(..7).is_some_and(|s| s.eq_ignore_ascii_case("Bearer ")));
+            let scheme = if used_token { "Bearer" } else { "Basic" };
+            headers.push((String::from("WWW-Authenticate"), format!("{} realm=\"CrossPoint\"", scheme)));
+        }
+        let with_content = self.request.method != HttpMethod::HEAD;
+        let composed = response.compose_with(&headers, with_content);
+        _ = self.stream.write_all(composed.as_bytes());
+        self.stream.finish();
+
+        let size = if with_content { response.content.as_ref().map_or(0, String::len) } else { 0 };
+        self.log_request(response.status_code.code, size);
+    }
+
+    /// Sends a permanent redirect to `location`
+    fn send_redirect(&mut self, location: &str) {
+        let response = HttpResponse { status_code: HttpResponseCode::new(301), content: None, mime: None };
+        _ = self.stream.write_all(response.compose_with(&[(String::from("Location"), location.to_string())], true).as_bytes());
+        self.log_request(301, 0);
+    }
+
+    fn log_request(&self, status: i32, size: usize) {
+        let request_line = format!("{:?} {} {}", self.request.method, self.request.target, self.request.http_version);
+        log_access(self.client, &request_line, status, size, self.received);
+    }
+}
+
+/// Logs a request in the common log format, followed by how long it took to answer:
+/// `192.168.1.20 - - [29/Feb/2024:21:30:00 +0000] "GET /presetNames HTTP/1.1" 200 512 3ms`
+fn log_access(client: SocketAddr, request_line: &str, status: i32, size: usize, received: Instant) {
+    log::info!(target: ACCESS_TARGET, "{} - - [{}] \"{}\" {} {} {}ms", client.ip(), logging::access_timestamp(SystemTime::now()),
+        request_line, status, size, received.elapsed().as_millis());
+}
+
+/// Which other origins may call the API from a browser, and with what
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorsPolicy {
+    /// `*` allows every origin
+    pub origins: Vec<String>,
+    pub methods: Vec<String>,
+    pub headers: Vec<String>,
+    /// How long browsers may cache a preflight answer
+    pub max_age: Option<Duration>
+}
+
+impl Default for CorsPolicy {
+    fn default() -> Self {
+        CorsPolicy {
+            origins: Vec::new(),
+            methods: ALLOWED_METHODS.split(", ").map(String::from).collect(),
+            headers: DEFAULT_CORS_HEADERS.split(", ").map(String::from).collect(),
+            max_age: None
+        }
+    }
+}
+
+impl CorsPolicy {
+    /// Headers for a request from an allowed origin; none for other requests
+    pub fn headers(&self, request: &HttpRequest) -> Vec<(String, String)> {
+        let origin = match request.header("Origin") {
+            Some(o) => o,
+            None => return Vec::new()
+        };
+        let allowed = if self.origins.iter().any(|o| o == "*") {
+            String::from("*")
+        } else if self.origins.iter().any(|o| o.eq_ignore_ascii_case(origin)) {
+            origin.to_string()
+        } else {
+            return Vec::new();
+        };
+
+        let mut headers = vec![
+            (String::from("Access-Control-Allow-Origin"), allowed),
+            (String::from("Vary"), String::from("Origin"))
+        ];
+        if request.method == HttpMethod::OPTIONS {
+            headers.push((String::from("Access-Control-Allow-Methods"), self.methods.join(", ")));
+            headers.push((String::from("Access-Control-Allow-Headers"), self.headers.join(", ")));
+            if let Some(max_age) = self.max_age {
+                headers.push((String::from("Access-Control-Max-Age"), max_age.as_secs().to_string()));
+            }
+        }
+        headers
+    }
+}
+
+/// A client connection, encrypted when the listener serves HTTPS
+enum HttpStream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>)
+}
+
+impl HttpStream {
+    /// The address the client connected to
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        match self {
+            HttpStream::Plain(s) => s.local_addr(),
+            HttpStream::Tls(s) => s.sock.local_addr()
+        }
+    }
+
+    /// Tells a TLS client the response is complete before the connection closes
+    fn finish(&mut self) {
+        if let HttpStream::Tls(stream) = self {
+            stream.conn.send_close_notify();
+            _ = stream.flush();
+        }
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            HttpStream::Plain(s) => s.read(buf),
+            HttpStream::Tls(s) => s.read(buf)
+        }
     }
 }
 
+impl Write for HttpStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            HttpStream::Plain(s) => s.write(buf),
+            HttpStream::Tls(s) => s.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            HttpStream::Plain(s) => s.flush(),
+            HttpStream::Tls(s) => s.flush()
+        }
+    }
+}
+
+/// Answers every plain HTTP request on `address` with a redirect to the same path on
+/// `https_address`, for browsers that were pointed at the old address. The Host header is
+/// not trusted, so when the HTTPS listener is bound to every interface the redirect goes to
+/// the address the client reached.
+pub fn spawn_redirect(address: SocketAddrV4, https_address: SocketAddr) -> std::io::Result<std::net::SocketAddr> {
+    let listener = HttpListener::bind(address)?;
+    let local_address = listener.listener.local_addr()?;
+    thread::spawn(move || {
+        loop {
+            let mut context = match listener.receive() {
+                Ok(c) => c,
+                Err(_) => continue
+            };
+            let ip = match https_address.ip() {
+                ip if ip.is_unspecified() => context.stream.local_addr().map_or(ip, |a| a.ip()),
+                ip => ip
+            };
+            let mut location = match https_address.port() {
+                HTTPS_PORT => format!("https://{}", ip),
+                port => format!("https://{}", SocketAddr::new(ip, port))
+            };
+            location.push_str(if context.request.target.starts_with('/') { &context.request.target } else { "/" });
+            context.send_redirect(&location);
+        }
+    });
+    Ok(local_address)
+}
+
 pub struct HttpRequest {
     pub method: HttpMethod,
     pub path: String,
+    /// The path with the query string, as the client sent it
+    pub target: String,
     pub http_version: String,
     pub headers: HashMap<String,String>,
     pub query_params: HashMap<String,String>,
@@ -59,20 +365,45 @@ pub struct HttpRequest {
 }
 
 impl HttpRequest {
-    pub fn read(stream: &mut TcpStream) -> Result<HttpRequest, HttpError> {
-        let mut buffer = [0 as u8; 512];
-        let length = match stream.read(&mut buffer) {
-            Ok(b) => b,
-            Err(_) => return Err(HttpError::new(500, "Unable to read request stream"))
+    /// A header's value, whatever the case of its name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// Reads the header block and exactly as much content as Content-Length announces.
+    /// Headers over 16 KiB are answered with 431 and content over 1 MiB with 413.
+    pub fn read(stream: &mut impl Read) -> Result<HttpRequest, HttpError> {
+        let mut data: Vec<u8> = Vec::new();
+        let mut buffer = [0_u8; 512];
+        let header_end = loop {
+            if let Some(end) = HttpRequest::header_end(&data) { break end; }
+            if data.len() > MAX_HEADER_SIZE { return Err(HttpError::new(431, "Request headers too large")); }
+            let length = match stream.read(&mut buffer) {
+                Ok(b) => b,
+                Err(_) => return Err(HttpError::new(500, "Unable to read request stream"))
+            };
+            if length == 0 { break data.len(); }
+            data.extend_from_slice(&buffer[..length]);
         };
 
-        let request_data  = match String::from_utf8(buffer[..length].to_vec()) {
+        let content_length = HttpRequest::announced_length(&data[..header_end]);
+        if content_length > MAX_CONTENT_LENGTH { return Err(HttpError::new(413, "Request content too large")); }
+        let request_end = header_end + content_length;
+        if data.len() < request_end {
+            let missing = (request_end - data.len()) as u64;
+            if Read::take(&mut *stream, missing).read_to_end(&mut data).is_err() {
+                return Err(HttpError::new(500, "Unable to read request stream"));
+            }
+        }
+        data.truncate(request_end);
+
+        let request_data  = match String::from_utf8(data) {
             Ok(s) => s,
             Err(_) => return Err(HttpError::new(400, "Request content unreadable"))
         };
 
         let mut lines = request_data.split('\n');
-        let mut firstline = lines.next().unwrap().split(' ');
+        let mut firstline = lines.next().unwrap_or_default().split(' ');
         let method = match firstline.next() {
             Some(s) => match HttpMethod::from_str(s) {
                 Ok(m) => m,
@@ -80,12 +411,13 @@ impl HttpRequest {
             }
             None => HttpMethod::GET
         };
-        let mut url = firstline.next().unwrap().split('?');
-        let path = url.next().unwrap().to_string();
+        let target = firstline.next().unwrap_or_default().to_string();
+        let mut url = target.split('?');
+        let path = url.next().unwrap_or_default().to_string();
         let query_string = url.next();
         let mut query_params:HashMap<String, String> = HashMap::new();
-        if query_string.is_some() {
-            for pair in query_string.unwrap().split('&') {
+        if let Some(query_string) = query_string {
+            for pair in query_string.split('&') {
                 if pair.contains('=') {
                     let mut pair_split = pair.split('=');
                     let key = pair_split.next().unwrap();
@@ -96,35 +428,53 @@ impl HttpRequest {
             }
         }
         
-        let http_version = firstline.next().unwrap().to_string();
+        let http_version = firstline.next().unwrap_or_default().trim().to_string();
 
         let mut headers: HashMap<String,String> = HashMap::new();
-        loop {
-            let line = match lines.next() {
-                Some(l) => l,
+        for line in lines {
+            match line.split_once(':') {
+                Some((name, value)) => { headers.insert(name.trim().to_string(), value.trim().to_string()); }
                 None => break
-            };
-
-            if line.contains(':') { 
-                let mut pair = line.split(':');
-                headers.insert(pair.next().unwrap().trim().to_string(), pair.next().unwrap().trim().to_string());
-            } else {
-                break;
             }
         }
 
-        let content_length = match headers.get("Content-Length") {
-            Some(h) => h.parse().unwrap(),
-            None => 0
-        };
+        let content_length = HttpRequest::content_length(&headers);
 
-        let content:Option<String> = if content_length > 0 { 
-            Some(request_data.split_at(content_length).1.to_string())
+        let content:Option<String> = if content_length > 0 {
+            request_data.split_once("\r\n\r\n")
+                .or_else(|| request_data.split_once("\n\n"))
+                .map(|(_, body)| body.to_string())
         } else {
             None
         };
-        
-        Ok(HttpRequest { method, path, http_version, headers, query_params, content })
+
+        Ok(HttpRequest { method, path, target, http_version, headers, query_params, content })
+    }
+
+    fn content_length(headers: &HashMap<String,String>) -> usize {
+        headers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Where the header block ends, once it has arrived
+    fn header_end(data: &[u8]) -> Option<usize> {
+        match data.windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(p) => Some(p + 4),
+            None => data.windows(2).position(|w| w == b"\n\n").map(|p| p + 2)
+        }
+    }
+
+    /// The Content-Length given in a header block
+    fn announced_length(header_block: &[u8]) -> usize {
+        let mut headers: HashMap<String,String> = HashMap::new();
+        for line in String::from_utf8_lossy(header_block).lines().skip(1) {
+            if let Some((name, value)) = line.split_once(':') {
+                headers.insert(name.trim().to_string(), value.trim().to_string());
+            }
+        }
+        HttpRequest::content_length(&headers)
     }
 }
 
@@ -137,21 +487,39 @@ pub struct HttpResponse {
 }
 
 impl HttpResponse {
+    pub fn from_error(error: &HttpError) -> HttpResponse {
+        HttpResponse {
+            content: Some(error.message.clone()),
+            mime: Some(String::from("text/plain")),
+            status_code: HttpResponseCode::new(error.code.code)
+        }
+    }
+
     pub fn compose(&self) -> String {
+        self.compose_with(&[], true)
+    }
+
+    /// Composes the response with extra headers, leaving out the content itself but not its
+    /// length when `with_content` is false
+    pub fn compose_with(&self, headers: &[(String, String)], with_content: bool) -> String {
         let mut response = String::from("HTTP/1.1 ");
         response.push_str(&self.status_code.code.to_string());
-        response.push_str(" ");
+        response.push(' ');
         response.push_str(&self.status_code.description());
         response.push_str("\nServer:CrossPointApi");
-        if self.content.is_some() {
-            let content = self.content.as_ref().unwrap();
+        for (name, value) in headers {
+            response.push_str(&format!("\n{}: {}", name, value));
+        }
+        if let Some(content) = self.content.as_ref() {
             response.push_str("\nContent-Length: ");
-            response.push_str(&content.bytes().len().to_string());
+            response.push_str(&content.len().to_string());
             response.push_str("\nContent-Type: ");
-            response.push_str(match self.mime.as_ref() { Some(m) => &m, None => "" });
+            response.push_str(match self.mime.as_ref() { Some(m) => m, None => "" });
             response.push('\n');
             response.push('\n');
-            response.push_str(&content);
+            if with_content { response.push_str(content); }
+        } else {
+            response.push_str("\nContent-Length: 0\n\n");
         }
 
         response
@@ -160,9 +528,10 @@ impl HttpResponse {
 
 
 
-#[derive(PartialEq,Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(PartialEq,Clone,Debug)]
 pub enum HttpMethod {
-    GET, POST, PUT, PATCH, DELETE
+    GET, HEAD, POST, PUT, PATCH, DELETE, OPTIONS
 }
 
 impl Copy for HttpMethod { }
@@ -173,6 +542,8 @@ impl FromStr for HttpMethod {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_uppercase().as_str() {
             "GET" => Ok(HttpMethod::GET),
+            "HEAD" => Ok(HttpMethod::HEAD),
+            "OPTIONS" => Ok(HttpMethod::OPTIONS),
             "POST" => Ok(HttpMethod::POST),
             "PUT" => Ok(HttpMethod::PUT),
             "PATCH" => Ok(HttpMethod::PATCH),
@@ -195,8 +566,17 @@ impl HttpResponseCode {
     pub fn description(&self) -> String {
         String::from(match self.code {
             200 => "OK",
+            204 => "No Content",
+            301 => "Moved Permanently",
             400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
             404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Content Too Large",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            503 => "Service Unavailable",
             _ => "Bad Request"
         })
     }
@@ -0,0 +1,22 @@
+pub mod crosspoint;
+pub mod service;
+pub mod config;
+pub mod http;
+pub mod vextron;
+pub mod transcript;
+pub mod transport;
+pub mod telnet;
+pub mod supervisor;
+pub mod discovery;
+pub mod events;
+pub mod signal;
+pub mod routing;
+pub mod state;
+pub mod backup;
+pub mod compare;
+pub mod macros;
+pub mod history;
+pub mod calendar;
+pub mod scheduler;
+pub mod auth;
+pub mod logging;
\ No newline at end of file
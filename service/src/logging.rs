@@ -0,0 +1,132 @@
+use std::{
+    collections::HashMap,
+    fs::{File,OpenOptions},
+    io::{Write,LineWriter,Error,ErrorKind},
+    str::FromStr,
+    sync::Mutex,
+    time::{SystemTime,UNIX_EPOCH}
+};
+use log::{Log,LevelFilter,Metadata,Record};
+use crate::calendar::civil_date;
+
+/// Target of the Apache style access log written by the HTTP listener
+pub const ACCESS_TARGET: &str = "access";
+/// Target of every command sent to the switcher and every reply, at trace level
+pub const SERIAL_TARGET: &str = "serial";
+const MONTH_NAMES: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Read from `log-level`, `log-levels`, `log-file` and `access-log`
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogSettings {
+    pub level: LevelFilter,
+    /// Levels for single modules, such as `http` or `supervisor`, and for `serial` and `access`
+    pub levels: HashMap<String, LevelFilter>,
+    /// Where to write the log instead of standard output
+    pub file: Option<String>,
+    /// Where to write the access log instead of the main log
+    pub access_log: Option<String>
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings { level: LevelFilter::Info, levels: HashMap::new(), file: None, access_log: None }
+    }
+}
+
+impl LogSettings {
+    /// The level for a log target. Module paths such as `crosspoint_api::http` are matched
+    /// by their last part.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let module = target.rsplit("::").next().unwrap_or(target);
+        self.levels.get(module).copied().unwrap_or(self.level)
+    }
+
+    /// The most detailed level any target logs at
+    pub fn max_level(&self) -> LevelFilter {
+        self.levels.values().copied().fold(self.level, Ord::max)
+    }
+}
+
+/// Parses `off`, `error`, `warn`, `info`, `debug` or `trace`
+pub fn parse_level(level: &str) -> Option<LevelFilter> {
+    LevelFilter::from_str(level).ok()
+}
+
+/// Writes log lines with a timestamp, level and module to standard output or a file, and
+/// access log lines as they are to their own file if one is set
+pub struct Logger {
+    settings: LogSettings,
+    output: Option<Mutex<LineWriter<File>>>,
+    access: Option<Mutex<LineWriter<File>>>
+}
+
+impl Logger {
+    pub fn new(settings: LogSettings) -> Result<Logger, Error> {
+        let open = |path: &String| -> Result<Mutex<LineWriter<File>>, Error> {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            Ok(Mutex::new(LineWriter::new(file)))
+        };
+        let output = settings.file.as_ref().map(open).transpose()?;
+        let access = settings.access_log.as_ref().map(open).transpose()?;
+        Ok(Logger { settings, output, access })
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.settings.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) { return; }
+
+        if let (ACCESS_TARGET, Some(access)) = (record.target(), self.access.as_ref()) {
+            _ = writeln!(access.lock().unwrap_or_else(|e| e.into_inner()), "{}", record.args());
+            return;
+        }
+
+        let module = record.target().rsplit("::").next().unwrap_or_default();
+        let line = format!("{} {:<5} {}: {}", timestamp(SystemTime::now()), record.level(), module, record.args());
+        match self.output.as_ref() {
+            Some(output) => _ = writeln!(output.lock().unwrap_or_else(|e| e.into_inner()), "{}", line),
+            None => println!("{}", line)
+        }
+    }
+
+    fn flush(&self) {
+        for writer in [self.output.as_ref(), self.access.as_ref()].into_iter().flatten() {
+            _ = writer.lock().unwrap_or_else(|e| e.into_inner()).flush();
+        }
+    }
+}
+
+/// Installs the logger for the whole process. Only the first call takes effect.
+pub fn init(settings: &LogSettings) -> Result<(), Error> {
+    let logger = Logger::new(settings.clone())?;
+    match log::set_boxed_logger(Box::new(logger)) {
+        Ok(_) => {
+            log::set_max_level(settings.max_level());
+            Ok(())
+        }
+        Err(_) => Err(Error::new(ErrorKind::AlreadyExists, "A logger is already installed"))
+    }
+}
+
+/// UTC time in ISO 8601 with milliseconds, such as `2024-02-29T21:30:00.123Z`
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() as i64;
+    let (year, month, day) = civil_date(seconds.div_euclid(86_400));
+    let second_of_day = seconds.rem_euclid(86_400);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", year, month, day,
+        second_of_day / 3600, second_of_day / 60 % 60, second_of_day % 60, since_epoch.subsec_millis())
+}
+
+/// UTC time as the common log format writes it, such as `29/Feb/2024:21:30:00 +0000`
+pub fn access_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let (year, month, day) = civil_date(seconds.div_euclid(86_400));
+    let second_of_day = seconds.rem_euclid(86_400);
+    format!("{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000", day, MONTH_NAMES[month as usize - 1], year,
+        second_of_day / 3600, second_of_day / 60 % 60, second_of_day % 60)
+}
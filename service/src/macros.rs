@@ -0,0 +1,249 @@
+use std::{
+    collections::HashMap,
+    io::Error,
+    str::FromStr,
+    sync::Mutex,
+    thread,
+    time::Duration
+};
+use json::{JsonValue,object::Object,number::Number};
+use crate::{
+    config::ConfigurationError,
+    crosspoint::{CrossPoint,CrossPointTie,CrossPointIO},
+    routing::channel,
+    supervisor::lock
+};
+
+/// Longest pause a delay step may make
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// A named list of steps run one after another, configured under `macros`:
+///
+/// `{ "name": "Movie", "steps": [ { "action": "recall", "preset": 4 },
+///    { "action": "tie", "input": 8, "type": "Aud" }, { "action": "delay", "ms": 500 },
+///    { "action": "mute", "output": 2, "type": "Vid" }, { "action": "volume", "output": 1, "level": 40 } ] }`
+///
+/// Inputs and outputs can be given by number or by their configured description. A tie or
+/// mute without an output applies to every output.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub steps: Vec<MacroStep>
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MacroStep {
+    /// `output` is None to tie the input to every output
+    Tie { input: i32, output: Option<i32>, io_type: CrossPointIO },
+    Recall(i32),
+    /// `output` is None to mute or unmute every output
+    Mute { output: Option<i32>, audio: bool, muted: bool },
+    Volume { output: i32, level: i32 },
+    Delay(Duration)
+}
+
+/// How a macro run went. When a step fails, the steps before it are undone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroReport {
+    pub name: String,
+    pub steps_completed: usize,
+    /// Index of the step that failed, from 1, with its error
+    pub failure: Option<(usize, String)>,
+    pub rolled_back: bool,
+    pub rollback_errors: Vec<String>
+}
+
+impl MacroReport {
+    pub fn to_json(&self) -> JsonValue {
+        let mut o = Object::new();
+        o.insert("Macro", JsonValue::String(self.name.clone()));
+        o.insert("StepsCompleted", JsonValue::Number(Number::from(self.steps_completed)));
+        o.insert("FailedStep", self.failure.as_ref().map_or(JsonValue::Null, |(step, _)| JsonValue::Number(Number::from(*step))));
+        o.insert("Error", self.failure.as_ref().map_or(JsonValue::Null, |(_, e)| JsonValue::String(e.clone())));
+        o.insert("RolledBack", JsonValue::Boolean(self.rolled_back));
+        o.insert("RollbackErrors", JsonValue::Array(self.rollback_errors.iter().map(|e| JsonValue::String(e.clone())).collect()));
+        JsonValue::Object(o)
+    }
+}
+
+impl Macro {
+    pub fn parse(definition: &JsonValue, inputs: &HashMap<i32, String>, outputs: &HashMap<i32, String>) -> Result<Macro, ConfigurationError> {
+        let name = match definition["name"].as_str() {
+            Some(n) if !n.is_empty() => n.to_string(),
+            _ => return Err(ConfigurationError::new("Every macro needs a name"))
+        };
+        let mut steps = Vec::new();
+        for (index, step) in definition["steps"].members().enumerate() {
+            let invalid = |problem: &str| ConfigurationError::new(&format!("Macro \"{}\" step {}: {}", name, index + 1, problem));
+            steps.push(match step["action"].as_str() {
+                Some("tie") => MacroStep::Tie {
+                    input: match channel(&step["input"], inputs) {
+                        Some(i) => i,
+                        None => return Err(invalid("unknown input"))
+                    },
+                    output: optional_output(&step["output"], outputs).map_err(|_| invalid("unknown output"))?,
+                    io_type: match step["type"].as_str().map(CrossPointIO::from_str) {
+                        None => CrossPointIO::All,
+                        Some(Ok(io_type)) => io_type,
+                        Some(Err(_)) => return Err(invalid("type must be All, RGB, Vid or Aud"))
+                    }
+                },
+                Some("recall") => match step["preset"].as_i32() {
+                    Some(p) if p > 0 => MacroStep::Recall(p),
+                    _ => return Err(invalid("recall needs a preset number"))
+                },
+                Some("mute") => MacroStep::Mute {
+                    output: optional_output(&step["output"], outputs).map_err(|_| invalid("unknown output"))?,
+                    audio: match step["type"].as_str() {
+                        Some("Vid") | None => false,
+                        Some("Aud") => true,
+                        Some(_) => return Err(invalid("type must be Vid or Aud"))
+                    },
+                    muted: step["mute"].as_bool().unwrap_or(true)
+                },
+                Some("volume") => match (channel(&step["output"], outputs), step["level"].as_i32()) {
+                    (Some(output), Some(level)) => MacroStep::Volume { output, level },
+                    _ => return Err(invalid("volume needs an output and a level"))
+                },
+                Some("delay") => match step["ms"].as_u64().map(Duration::from_millis) {
+                    Some(d) if d <= MAX_DELAY => MacroStep::Delay(d),
+                    _ => return Err(invalid(&format!("delay needs \"ms\" of at most {}", MAX_DELAY.as_millis())))
+                },
+                _ => return Err(invalid("action must be tie, recall, mute, volume or delay"))
+            });
+        }
+        if steps.is_empty() { return Err(ConfigurationError::new(&format!("Macro \"{}\" has no steps", name))); }
+
+        Ok(Macro { name, steps })
+    }
+
+    /// Runs the steps one after another while holding the switcher, so nothing else can
+    /// change it between two steps. Delays let go of it, and of the routing history, until
+    /// they are over. If a step fails, what the earlier steps changed is put back as it was.
+    pub fn run(&self, port: &Mutex<CrossPoint>) -> MacroReport {
+        self.run_as(port, &format!("Macro {}", self.name))
+    }
+
+    /// Runs the macro, keeping `cause` in the routing history for each stretch between delays
+    pub fn run_as(&self, port: &Mutex<CrossPoint>, cause: &str) -> MacroReport {
+        let mut report = MacroReport {
+            name: self.name.clone(), steps_completed: 0, failure: None, rolled_back: false, rollback_errors: Vec::new()
+        };
+        let mut cp = lock(port);
+        let mut held = cp.begin_change(cause);
+        let snapshot = match Snapshot::take(self, &mut cp) {
+            Ok(s) => s,
+            Err(e) => {
+                report.failure = Some((1, format!("Could not read the state to restore on failure: {}", e)));
+                cp.end_change(held);
+                return report;
+            }
+        };
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let result = match *step {
+                MacroStep::Delay(duration) => {
+                    cp.end_change(held);
+                    drop(cp);
+                    thread::sleep(duration);
+                    cp = lock(port);
+                    held = cp.begin_change(cause);
+                    Ok(())
+                }
+                _ => run_step(step, &mut cp)
+            };
+            if let Err(e) = result {
+                report.failure = Some((index + 1, e.to_string()));
+                report.rollback_errors = snapshot.restore(&mut cp);
+                report.rolled_back = report.rollback_errors.is_empty();
+                break;
+            }
+            report.steps_completed += 1;
+        }
+        cp.end_change(held);
+        report
+    }
+}
+
+/// An output given by number or description, or None when it is left out or `all`
+fn optional_output(value: &JsonValue, outputs: &HashMap<i32, String>) -> Result<Option<i32>, ()> {
+    if value.is_null() || value.as_str() == Some("all") { return Ok(None); }
+    channel(value, outputs).map(Some).ok_or(())
+}
+
+fn run_step(step: &MacroStep, cp: &mut CrossPoint) -> Result<(), Error> {
+    match *step {
+        MacroStep::Tie { input, output: Some(output), io_type } => tie(cp, input, output, io_type),
+        MacroStep::Tie { input, output: None, io_type } => {
+            (1..=cp.output_port_count()).try_for_each(|output| tie(cp, input, output, io_type))
+        }
+        MacroStep::Recall(preset) => cp.load_preset(preset).map(|_| ()),
+        MacroStep::Mute { output: Some(output), audio: false, muted } => cp.set_video_mute(output, muted).map(|_| ()),
+        MacroStep::Mute { output: Some(output), audio: true, muted } => cp.set_audio_mute(output, muted).map(|_| ()),
+        MacroStep::Mute { output: None, audio: false, muted } => cp.set_global_video_mute(muted).map(|_| ()),
+        MacroStep::Mute { output: None, audio: true, muted } => cp.set_global_audio_mute(muted).map(|_| ()),
+        MacroStep::Volume { output, level } => cp.set_volume(output, level).map(|_| ()),
+        // Waited out by run_as without holding the port
+        MacroStep::Delay(_) => Ok(())
+    }
+}
+
+fn tie(cp: &mut CrossPoint, input: i32, output: i32, io_type: CrossPointIO) -> Result<(), Error> {
+    let (input_channel, output_channel) = match (u8::try_from(input), u8::try_from(output)) {
+        (Ok(i), Ok(o)) => (i, o),
+        _ => return Err(Error::new(std::io::ErrorKind::InvalidInput, "Invalid channel number"))
+    };
+    cp.tie(CrossPointTie { input_channel, output_channel, io_type }).map(|_| ())
+}
+
+/// What a macro is about to change, read before it runs
+struct Snapshot {
+    ties: Option<Vec<CrossPointTie>>,
+    /// Output, whether it is the audio mute, and whether it was muted
+    mutes: Vec<(i32, bool, bool)>,
+    volumes: Vec<(i32, i32)>
+}
+
+impl Snapshot {
+    fn take(definition: &Macro, cp: &mut CrossPoint) -> Result<Snapshot, Error> {
+        let mut snapshot = Snapshot { ties: None, mutes: Vec::new(), volumes: Vec::new() };
+        for step in &definition.steps {
+            match *step {
+                MacroStep::Tie { .. } | MacroStep::Recall(_) if snapshot.ties.is_none() => snapshot.ties = Some(cp.current_ties()?),
+                MacroStep::Mute { output, audio, .. } => {
+                    let outputs = match output {
+                        Some(o) => o..=o,
+                        None => 1..=cp.output_port_count()
+                    };
+                    for output in outputs {
+                        if snapshot.mutes.iter().any(|(o, a, _)| *o == output && *a == audio) { continue; }
+                        let muted = if audio { cp.audio_mute_status(output) } else { cp.video_mute_status(output) };
+                        // A mute the switcher refuses fails the step itself; there is nothing to restore
+                        if let Ok(muted) = muted { snapshot.mutes.push((output, audio, muted)); }
+                    }
+                }
+                MacroStep::Volume { output, .. } if !snapshot.volumes.iter().any(|(o, _)| *o == output) => {
+                    if let Ok(level) = cp.get_volume(output) { snapshot.volumes.push((output, level)); }
+                }
+                _ => ()
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Puts everything back, returning the errors for whatever could not be restored
+    fn restore(&self, cp: &mut CrossPoint) -> Vec<String> {
+        let mut errors = Vec::new();
+        for &(output, level) in &self.volumes {
+            if let Err(e) = cp.set_volume(output, level) { errors.push(format!("Volume of output {}: {}", output, e)); }
+        }
+        for &(output, audio, muted) in &self.mutes {
+            let result = if audio { cp.set_audio_mute(output, muted) } else { cp.set_video_mute(output, muted) };
+            if let Err(e) = result { errors.push(format!("Mute of output {}: {}", output, e)); }
+        }
+        if let Some(ties) = &self.ties {
+            if let Err(e) = cp.apply_ties(ties) { errors.push(format!("Ties: {}", e)); }
+        }
+        errors
+    }
+}
@@ -1,20 +1,34 @@
-mod crosspoint;
-mod service;
-mod config;
-mod http;
-mod vextron;
-
 use std::{
+    env,
+    fs,
     io::stdin,
     error::Error,
 };
-use config::ServiceConfiguration;
+use crosspoint_api::{
+    service,
+    logging,
+    backup::PresetBackup,
+    config::{ServiceConfiguration,ConfigurationError},
+    crosspoint::CrossPoint
+};
+
+const USAGE: &str = "Usage: crosspoint_api [export-presets <file> | import-presets <file> [--dry-run]]";
 
 fn main() -> Result<(), Box<dyn Error>> {
 
     let config = ServiceConfiguration::load()?;
+    logging::init(&config.logging)?;
 
-    service::start(config);
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<&str>>().as_slice() {
+        [] => (),
+        ["export-presets", path] => return export_presets(&config, path),
+        ["import-presets", path] => return import_presets(&config, path, false),
+        ["import-presets", path, "--dry-run"] => return import_presets(&config, path, true),
+        _ => return Err(Box::new(ConfigurationError::new(USAGE)))
+    }
+
+    service::start(config)?;
 
     println!("Press enter to exit");
     _ = stdin().read_line(&mut String::new());
@@ -22,32 +36,35 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-/*
-fn select_port() -> Result<String, Box<dyn Error>> {
-    let ports = serialport::available_ports()?;
-    if ports.len() == 0 { return Err(Box::new(IoError::from(std::io::ErrorKind::NotFound))); }
-
-    let mut port_num: usize;
-    let mut input_buffer = String::new();
-
-    loop {
-        //[2J
-        print!("{}", 27 as char);
-        println!("Available ports:");
-
-        let mut index = 1;
-        for port_info in &ports {
-            println!("{}) {}", index, port_info.port_name);
-            index += 1;
-        }
-    
-        println!("Select port: ");
-        stdin().read_line(&mut input_buffer)?;
-        port_num = input_buffer.trim().parse()?;
-
-        if port_num > 0 { break; } 
-    }
+fn connect(config: &ServiceConfiguration) -> Result<CrossPoint, Box<dyn Error>> {
+    let settings = match config.connection_settings() {
+        Some(s) => s,
+        None => return Err(Box::new(ConfigurationError::new("Serial port required")))
+    };
+    let mut cp = CrossPoint::connect(&settings)?;
+    cp.set_preset_count(config.preset_count);
+    Ok(cp)
+}
 
-    Ok(ports[port_num - 1].port_name.clone())
+fn export_presets(config: &ServiceConfiguration, path: &str) -> Result<(), Box<dyn Error>> {
+    let backup = PresetBackup::read(&mut connect(config)?)?;
+    fs::write(path, json::stringify_pretty(backup.to_json(), 4))?;
+    println!("Saved {} presets to {}", backup.presets.len(), path);
+    Ok(())
+}
+
+fn import_presets(config: &ServiceConfiguration, path: &str, dry_run: bool) -> Result<(), Box<dyn Error>> {
+    let backup = PresetBackup::parse(&fs::read_to_string(path)?).map_err(|e| ConfigurationError::new(&e))?;
+    let mut cp = connect(config)?;
+    let changes = if dry_run { backup.plan(&mut cp)? } else { backup.restore(&mut cp)? };
+
+    for change in &changes {
+        println!("{:?} preset {} \"{}\"", change.kind, change.preset_number, change.name);
+    }
+    match (dry_run, changes.is_empty()) {
+        (_, true) => println!("Presets already match {}", path),
+        (true, false) => println!("{} presets would change; run without --dry-run to write them", changes.len()),
+        (false, false) => println!("Restored {} presets from {}", changes.len(), path)
+    }
+    Ok(())
 }
-*/
\ No newline at end of file
@@ -0,0 +1,226 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc,Mutex},
+    thread,
+    time::{Duration,Instant}
+};
+use json::JsonValue;
+use crate::{
+    config::ConfigurationError,
+    crosspoint::{self,CrossPoint,CrossPointTie,CrossPointIO},
+    events::{EventLog,EventKind},
+    supervisor::lock
+};
+
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+/// Claims on this key cover every output, for rules that recall a preset
+const ALL_OUTPUTS: i32 = 0;
+
+/// Routes a source when its input gains or loses a signal, configured under `auto-routing`:
+///
+/// `{ "name": "N64", "input": "Nintendo 64", "when": "signal", "priority": 10, "debounce": 500,
+///    "ties": [ { "output": "Framemeister", "type": "RGB" } ] }`
+///
+/// Inputs and outputs can be given by number or by their configured description. A rule
+/// either `ties` the input to outputs or recalls a `preset`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoutingRule {
+    pub name: String,
+    pub input: i32,
+    /// Fire when the input gains a signal, or when it loses it
+    pub on_signal: bool,
+    /// Higher priority rules keep their outputs until their own input changes
+    pub priority: i32,
+    /// How long the input has to stay in the new state before the rule fires
+    pub debounce: Duration,
+    pub action: RoutingAction
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoutingAction {
+    Ties(Vec<CrossPointTie>),
+    Preset(i32)
+}
+
+impl RoutingRule {
+    /// Presets are checked against `preset_count`, the configured count or the most a switcher can have
+    pub fn parse(rule: &JsonValue, inputs: &HashMap<i32, String>, outputs: &HashMap<i32, String>, preset_count: i32) -> Result<RoutingRule, ConfigurationError> {
+        let name = rule["name"].as_str().unwrap_or_default().to_string();
+        let invalid = |problem: &str| ConfigurationError::new(&format!("Auto-routing rule \"{}\": {}", name, problem));
+
+        let input = match channel(&rule["input"], inputs) {
+            Some(i) => i,
+            None => return Err(invalid("unknown input"))
+        };
+        let input_channel = match u8::try_from(input) {
+            Ok(i) if i > 0 => i,
+            _ => return Err(invalid("input must be between 1 and 255"))
+        };
+        let on_signal = match rule["when"].as_str() {
+            Some("signal") | None => true,
+            Some("no-signal") => false,
+            Some(_) => return Err(invalid("when must be \"signal\" or \"no-signal\""))
+        };
+        let priority = rule["priority"].as_i32().unwrap_or(0);
+        let debounce = match &rule["debounce"] {
+            JsonValue::Null => Duration::ZERO,
+            d => match d.as_u64() {
+                Some(ms) => Duration::from_millis(ms),
+                None => return Err(invalid("debounce must be a number of milliseconds"))
+            }
+        };
+
+        let action = if let Some(preset) = rule["preset"].as_i32() {
+            if !(1..=preset_count).contains(&preset) {
+                return Err(invalid(&format!("preset must be between 1 and {}", preset_count)));
+            }
+            RoutingAction::Preset(preset)
+        } else if rule["ties"].is_array() && !rule["ties"].is_empty() {
+            let mut ties = Vec::new();
+            for tie in rule["ties"].members() {
+                let output_channel = match channel(&tie["output"], outputs).and_then(|o| u8::try_from(o).ok()) {
+                    Some(o) => o,
+                    None => return Err(invalid("unknown output"))
+                };
+                let io_type = match tie["type"].as_str().map(CrossPointIO::from_str) {
+                    None => CrossPointIO::All,
+                    Some(Ok(io_type)) => io_type,
+                    Some(Err(_)) => return Err(invalid("type must be All, RGB, Vid or Aud"))
+                };
+                ties.push(CrossPointTie { input_channel, output_channel, io_type });
+            }
+            RoutingAction::Ties(ties)
+        } else {
+            return Err(invalid("needs ties or a preset"));
+        };
+
+        Ok(RoutingRule { name, input, on_signal, priority, debounce, action })
+    }
+
+    fn outputs(&self) -> Vec<i32> {
+        match &self.action {
+            RoutingAction::Ties(ties) => ties.iter().map(|t| t.output_channel as i32).collect(),
+            RoutingAction::Preset(_) => vec![ALL_OUTPUTS]
+        }
+    }
+
+    fn describe(&self) -> String {
+        match &self.action {
+            RoutingAction::Ties(ties) => crosspoint::describe_ties(ties),
+            RoutingAction::Preset(n) => format!("Recall preset {}", n)
+        }
+    }
+}
+
+/// A channel given either as a number or as a configured description
+pub(crate) fn channel(value: &JsonValue, names: &HashMap<i32, String>) -> Option<i32> {
+    if let Some(n) = value.as_i32() { return Some(n); }
+    let name = value.as_str()?;
+    names.iter().find(|(_, n)| n.as_str() == name).map(|(c, _)| *c)
+}
+
+/// Watches the event log for signal changes and applies the rules that match
+pub fn spawn(port: Arc<Mutex<CrossPoint>>, events: EventLog, rules: Vec<RoutingRule>) {
+    thread::spawn(move || {
+        let mut engine = RuleEngine::new(rules);
+        let mut seen = events.latest();
+        loop {
+            thread::sleep(TICK_INTERVAL);
+            for event in events.since(seen) {
+                seen = event.sequence;
+                if let EventKind::SignalPresence { input, present } = event.kind {
+                    engine.signal_changed(input, present, Instant::now());
+                }
+            }
+
+            for rule in engine.due(Instant::now()) {
+                let action = rule.describe();
+                let result = lock(&port).as_one_change(&format!("Auto-routing rule {}", rule.name), |cp| match &rule.action {
+                    RoutingAction::Ties(ties) => ties.iter().try_for_each(|t| cp.tie(*t).map(|_| ())),
+                    RoutingAction::Preset(n) => cp.load_preset(*n).map(|_| ())
+                });
+                match &result {
+                    Ok(_) => log::info!("Auto-routing rule \"{}\": {}", rule.name, action),
+                    Err(e) => log::warn!("Auto-routing rule \"{}\" failed to {}: {}", rule.name, action.to_lowercase(), e)
+                }
+                events.publish(EventKind::AutoRoute { rule: rule.name.clone(), action, error: result.err().map(|e| e.to_string()) });
+            }
+        }
+    });
+}
+
+/// Decides which rules fire. A rule claims its outputs when it fires and holds them while
+/// its input stays in the triggering state; lower priority rules wait for the claim to go.
+struct RuleEngine {
+    rules: Vec<RoutingRule>,
+    signals: HashMap<i32, (bool, Instant)>,
+    fired: Vec<bool>,
+    claims: HashMap<i32, usize>
+}
+
+impl RuleEngine {
+    fn new(rules: Vec<RoutingRule>) -> RuleEngine {
+        let fired = vec![false; rules.len()];
+        RuleEngine { rules, signals: HashMap::new(), fired, claims: HashMap::new() }
+    }
+
+    fn matches(&self, index: usize) -> bool {
+        let rule = &self.rules[index];
+        matches!(self.signals.get(&rule.input), Some((present, _)) if *present == rule.on_signal)
+    }
+
+    fn signal_changed(&mut self, input: i32, present: bool, now: Instant) {
+        self.signals.insert(input, (present, now));
+
+        let mut released = Vec::new();
+        for index in 0..self.rules.len() {
+            if self.rules[index].input != input { continue; }
+            self.fired[index] = false;
+            if !self.matches(index) {
+                self.claims.retain(|output, claimant| {
+                    if *claimant != index { return true; }
+                    released.push(*output);
+                    false
+                });
+            }
+        }
+
+        // Rules that were overridden on a released output get to route it again
+        if released.is_empty() { return; }
+        for index in 0..self.rules.len() {
+            let outputs = self.rules[index].outputs();
+            let overridden = outputs.iter().any(|o| self.claims.get(o) != Some(&index));
+            let overlaps = outputs.iter().any(|o| released.contains(o) || *o == ALL_OUTPUTS) || released.contains(&ALL_OUTPUTS);
+            if overridden && overlaps && self.matches(index) { self.fired[index] = false; }
+        }
+    }
+
+    /// Rules whose input has been stable for their debounce, highest priority first
+    fn due(&mut self, now: Instant) -> Vec<RoutingRule> {
+        let mut candidates: Vec<usize> = (0..self.rules.len()).filter(|&i| {
+            let rule = &self.rules[i];
+            !self.fired[i] && match self.signals.get(&rule.input) {
+                Some((present, since)) => *present == rule.on_signal && now.duration_since(*since) >= rule.debounce,
+                None => false
+            }
+        }).collect();
+        candidates.sort_by_key(|&i| -self.rules[i].priority);
+
+        let mut due = Vec::new();
+        for index in candidates {
+            let outputs = self.rules[index].outputs();
+            let priority = self.rules[index].priority;
+            let blocked = self.claims.iter().any(|(output, &claimant)| {
+                claimant != index && self.rules[claimant].priority > priority
+                    && (outputs.contains(output) || *output == ALL_OUTPUTS || outputs.contains(&ALL_OUTPUTS))
+            });
+            if blocked { continue; }
+
+            self.fired[index] = true;
+            for output in outputs { self.claims.insert(output, index); }
+            due.push(self.rules[index].clone());
+        }
+        due
+    }
+}
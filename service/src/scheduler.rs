@@ -0,0 +1,352 @@
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    str::FromStr,
+    sync::{Arc,Mutex,MutexGuard},
+    thread,
+    time::{Duration,SystemTime,UNIX_EPOCH}
+};
+use json::{JsonValue,object::Object,number::Number};
+use crate::{
+    calendar::civil_date,
+    config::ConfigurationError,
+    crosspoint::{self,CrossPoint,CrossPointTie,CrossPointIO},
+    events::{EventLog,EventKind},
+    macros::Macro,
+    routing::channel,
+    supervisor::lock
+};
+
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// Most minutes run late after the switcher was held or the clock jumped forward
+const MAX_CATCH_UP_MINUTES: u64 = 10;
+/// How far ahead to look for the next run before giving up on a schedule
+const LOOKAHEAD_MINUTES: u64 = 366 * 24 * 60;
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const WEEKDAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// A routing change made at set times, configured under `schedules`:
+///
+/// `{ "name": "Attract mode", "cron": "0 22 * * *", "preset": 5 }`
+///
+/// The action is a `preset` to recall, `ties` in the same form as the auto-routing rules with
+/// an `input` on each, or the name of a `macro`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    pub name: String,
+    pub cron: CronExpression,
+    pub action: ScheduleAction
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScheduleAction {
+    Preset(i32),
+    Ties(Vec<CrossPointTie>),
+    Macro(String)
+}
+
+impl Schedule {
+    /// Presets are checked against `preset_count`, the switcher's count when it is known,
+    /// otherwise the configured count or the most a switcher can have
+    pub fn parse(schedule: &JsonValue, inputs: &HashMap<i32, String>, outputs: &HashMap<i32, String>, macros: &[Macro], preset_count: i32)
+        -> Result<Schedule, ConfigurationError> {
+        let name = match schedule["name"].as_str() {
+            Some(n) if !n.is_empty() => n.to_string(),
+            _ => return Err(ConfigurationError::new("Every schedule needs a name"))
+        };
+        let invalid = |problem: &str| ConfigurationError::new(&format!("Schedule \"{}\": {}", name, problem));
+
+        let cron = match schedule["cron"].as_str().map(CronExpression::parse) {
+            Some(Ok(c)) => c,
+            Some(Err(e)) => return Err(invalid(&e)),
+            None => return Err(invalid("needs a cron expression"))
+        };
+
+        let action = if !schedule["preset"].is_null() {
+            match schedule["preset"].as_i32() {
+                Some(p) if (1..=preset_count).contains(&p) => ScheduleAction::Preset(p),
+                _ => return Err(invalid(&format!("preset must be between 1 and {}", preset_count)))
+            }
+        } else if let Some(name) = schedule["macro"].as_str() {
+            if !macros.iter().any(|m| m.name == name) { return Err(invalid(&format!("no macro is named \"{}\"", name))); }
+            ScheduleAction::Macro(name.to_string())
+        } else if schedule["ties"].is_array() && !schedule["ties"].is_empty() {
+            let mut ties = Vec::new();
+            for tie in schedule["ties"].members() {
+                let input_channel = match channel(&tie["input"], inputs).and_then(|i| u8::try_from(i).ok()) {
+                    Some(i) => i,
+                    None => return Err(invalid("unknown input"))
+                };
+                let output_channel = match channel(&tie["output"], outputs).and_then(|o| u8::try_from(o).ok()) {
+                    Some(o) => o,
+                    None => return Err(invalid("unknown output"))
+                };
+                let io_type = match tie["type"].as_str().map(CrossPointIO::from_str) {
+                    None => CrossPointIO::All,
+                    Some(Ok(io_type)) => io_type,
+                    Some(Err(_)) => return Err(invalid("type must be All, RGB, Vid or Aud"))
+                };
+                ties.push(CrossPointTie { input_channel, output_channel, io_type });
+            }
+            ScheduleAction::Ties(ties)
+        } else {
+            return Err(invalid("needs a preset, ties or a macro"));
+        };
+
+        Ok(Schedule { name, cron, action })
+    }
+
+    /// The schedule in the form it is configured in, with channels by number
+    pub fn to_json(&self) -> JsonValue {
+        let mut o = Object::new();
+        o.insert("name", JsonValue::String(self.name.clone()));
+        o.insert("cron", JsonValue::String(self.cron.text.clone()));
+        match &self.action {
+            ScheduleAction::Preset(n) => o.insert("preset", JsonValue::Number(Number::from(*n))),
+            ScheduleAction::Macro(name) => o.insert("macro", JsonValue::String(name.clone())),
+            ScheduleAction::Ties(ties) => o.insert("ties", JsonValue::Array(ties.iter().map(|t| {
+                let mut tie = Object::new();
+                tie.insert("input", JsonValue::Number(Number::from(t.input_channel)));
+                tie.insert("output", JsonValue::Number(Number::from(t.output_channel)));
+                tie.insert("type", JsonValue::String(format!("{:?}", t.io_type)));
+                JsonValue::Object(tie)
+            }).collect()))
+        }
+        JsonValue::Object(o)
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.action {
+            ScheduleAction::Preset(n) => format!("Recall preset {}", n),
+            ScheduleAction::Ties(ties) => crosspoint::describe_ties(ties),
+            ScheduleAction::Macro(name) => format!("Run macro {}", name)
+        }
+    }
+
+    /// Carries out the action as one entry in the routing history. Macros take the port
+    /// themselves so their delays do not hold it.
+    pub fn run(&self, port: &Mutex<CrossPoint>, macros: &[Macro]) -> Result<(), String> {
+        let cause = format!("Schedule {}", self.name);
+        match &self.action {
+            ScheduleAction::Preset(n) => lock(port).as_one_change(&cause, |cp| cp.load_preset(*n).map(|_| ()))
+                .map_err(|e| e.to_string()),
+            ScheduleAction::Ties(ties) => lock(port).as_one_change(&cause, |cp| ties.iter().try_for_each(|t| cp.tie(*t).map(|_| ())))
+                .map_err(|e| e.to_string()),
+            ScheduleAction::Macro(name) => match macros.iter().find(|m| &m.name == name) {
+                Some(definition) => match definition.run_as(port, &cause).failure {
+                    None => Ok(()),
+                    Some((step, e)) => Err(format!("step {} failed: {}", step, e))
+                },
+                None => Err(format!("no macro is named \"{}\"", name))
+            }
+        }
+    }
+}
+
+/// A standard five field cron expression: minute, hour, day of month, month and day of week.
+/// Fields take `*`, numbers, ranges, lists and steps such as `*/15` or `mon-fri`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CronExpression {
+    pub text: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month and day of week fields start with `*`, as in `*` or `*/2`
+    any_day: bool,
+    any_weekday: bool
+}
+
+impl CronExpression {
+    pub fn parse(text: &str) -> Result<CronExpression, String> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(String::from("cron expression must have minute, hour, day of month, month and day of week fields"));
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7, &WEEKDAY_NAMES)?;
+        // Sunday is both 0 and 7
+        if weekdays & (1 << 7) != 0 { weekdays = (weekdays | 1) & !(1 << 7); }
+
+        Ok(CronExpression {
+            text: fields.join(" "),
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, &MONTH_NAMES)?,
+            weekdays,
+            any_day: fields[2].starts_with('*'),
+            any_weekday: fields[4].starts_with('*')
+        })
+    }
+
+    /// When both the day of month and the day of week are restricted, either one matching is
+    /// enough, as in cron. Otherwise both have to match.
+    pub fn matches(&self, time: &ScheduleTime) -> bool {
+        let day = self.days & (1 << time.day) != 0;
+        let weekday = self.weekdays & (1 << time.weekday) != 0;
+        let day_matches = if self.any_day || self.any_weekday { day && weekday } else { day || weekday };
+        self.minutes & (1 << time.minute) != 0 && self.hours & (1 << time.hour) != 0
+            && self.months & (1 << time.month) != 0 && day_matches
+    }
+
+    /// The first whole minute after `after` that matches, if there is one within a year
+    pub fn next_run(&self, after: SystemTime, utc_offset: i32) -> Option<SystemTime> {
+        let first = after.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 60 + 1);
+        (first..first + LOOKAHEAD_MINUTES)
+            .map(|minute| UNIX_EPOCH + Duration::from_secs(minute * 60))
+            .find(|&time| self.matches(&ScheduleTime::at(time, utc_offset)))
+    }
+}
+
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let invalid = || format!("\"{}\" is not a valid cron field", field);
+    let value = |text: &str| -> Result<u32, String> {
+        let lower = text.to_lowercase();
+        let value = match names.iter().position(|n| *n == lower) {
+            Some(index) => min + index as u32,
+            None => text.parse().map_err(|_| invalid())?
+        };
+        if (min..=max).contains(&value) { Ok(value) } else { Err(format!("{} is out of range in \"{}\"", value, field)) }
+    };
+
+    let mut bits = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<usize>() {
+                Ok(s) if s > 0 => (range, s),
+                _ => return Err(invalid())
+            },
+            None => (item, 1)
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            None if step > 1 => (value(range)?, max),
+            None => {
+                let v = value(range)?;
+                (v, v)
+            }
+        };
+        if first > last { return Err(invalid()); }
+        for v in (first..=last).step_by(step) { bits |= 1 << v; }
+    }
+    Ok(bits)
+}
+
+/// A moment in the service's configured time zone, as far as schedules are concerned
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScheduleTime {
+    pub minute: u32,
+    pub hour: u32,
+    pub day: u32,
+    pub month: u32,
+    /// 0 is Sunday
+    pub weekday: u32
+}
+
+impl ScheduleTime {
+    /// `utc_offset` is in minutes east of UTC
+    pub fn at(time: SystemTime, utc_offset: i32) -> ScheduleTime {
+        let seconds = match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64)
+        };
+        let minutes = seconds.div_euclid(60) + utc_offset as i64;
+        let days = minutes.div_euclid(24 * 60);
+        let minute_of_day = minutes.rem_euclid(24 * 60);
+
+        let (_, month, day) = civil_date(days);
+
+        ScheduleTime {
+            minute: (minute_of_day % 60) as u32,
+            hour: (minute_of_day / 60) as u32,
+            day,
+            month,
+            // 1970-01-01 was a Thursday
+            weekday: (days + 4).rem_euclid(7) as u32
+        }
+    }
+}
+
+/// The schedules in use. Clones share the same list, so the API can change what the
+/// scheduler runs.
+#[derive(Clone, Default)]
+pub struct Schedules {
+    inner: Arc<Mutex<Vec<Schedule>>>
+}
+
+impl Schedules {
+    pub fn new(schedules: Vec<Schedule>) -> Schedules {
+        Schedules { inner: Arc::new(Mutex::new(schedules)) }
+    }
+
+    fn list(&self) -> MutexGuard<'_, Vec<Schedule>> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn all(&self) -> Vec<Schedule> {
+        self.list().clone()
+    }
+
+    /// Adds a schedule, replacing any with the same name
+    pub fn save(&self, schedule: Schedule) {
+        let mut list = self.list();
+        match list.iter_mut().find(|s| s.name == schedule.name) {
+            Some(existing) => *existing = schedule,
+            None => list.push(schedule)
+        }
+    }
+
+    /// Returns false if no schedule has the name
+    pub fn remove(&self, name: &str) -> bool {
+        let mut list = self.list();
+        let count = list.len();
+        list.retain(|s| s.name != name);
+        list.len() != count
+    }
+}
+
+/// Runs the schedules at the start of every minute they match. Minutes that passed while
+/// the switcher was busy are run late, up to `MAX_CATCH_UP_MINUTES` of them.
+pub fn spawn(port: Arc<Mutex<CrossPoint>>, events: EventLog, schedules: Schedules, macros: Vec<Macro>, utc_offset: i32) {
+    thread::spawn(move || {
+        let minute_of = |time: SystemTime| time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 60);
+        let mut last_minute = minute_of(SystemTime::now());
+        loop {
+            thread::sleep(TICK_INTERVAL);
+            let now_minute = minute_of(SystemTime::now());
+            if now_minute == last_minute { continue; }
+            for minute in minutes_to_run(last_minute, now_minute) {
+                let time = UNIX_EPOCH + Duration::from_secs(minute * 60);
+                run_due(&port, &events, &schedules, &macros, &ScheduleTime::at(time, utc_offset));
+            }
+            last_minute = now_minute;
+        }
+    });
+}
+
+/// The minutes, counted from the epoch, to run after `last_minute` now that it is `now_minute`.
+/// Runs at most `MAX_CATCH_UP_MINUTES`, the latest ones, and none if the clock was set back.
+pub fn minutes_to_run(last_minute: u64, now_minute: u64) -> RangeInclusive<u64> {
+    let first_minute = (last_minute + 1).max(now_minute.saturating_sub(MAX_CATCH_UP_MINUTES - 1));
+    if first_minute > last_minute + 1 && first_minute <= now_minute {
+        log::warn!("Skipped the schedules of {} minutes that passed while they could not run", first_minute - last_minute - 1);
+    }
+    first_minute..=now_minute
+}
+
+/// Runs every schedule that matches `time`, logging each run and publishing a Schedule event
+pub fn run_due(port: &Arc<Mutex<CrossPoint>>, events: &EventLog, schedules: &Schedules, macros: &[Macro], time: &ScheduleTime) {
+    for schedule in schedules.all().into_iter().filter(|s| s.cron.matches(time)) {
+        let action = schedule.describe();
+        let connected = lock(port).is_connected();
+        let result = if connected { schedule.run(port, macros) } else { Err(String::from("CrossPoint is not connected")) };
+        match &result {
+            Ok(_) => log::info!("Schedule \"{}\": {}", schedule.name, action),
+            Err(e) => log::warn!("Schedule \"{}\" failed to {}: {}", schedule.name, action.to_lowercase(), e)
+        }
+        events.publish(EventKind::Schedule { name: schedule.name.clone(), action, error: result.err() });
+    }
+}
@@ -1,14 +1,23 @@
 use std::{
     fs::File,
     path::{Path,PathBuf},
-    net::SocketAddrV4,
-    io::Read,
-    str::FromStr
+    net::{SocketAddr,SocketAddrV4},
+    io::{Read,Error,ErrorKind},
+    str::FromStr,
+    sync::{Arc,Mutex},
+    collections::HashMap
 };
 use json::{Array, JsonValue,object::Object,number::Number};
-use crate::crosspoint::{CrossPoint,CrossPointPreset};
+use crate::crosspoint::{CrossPoint,CrossPointPreset,CrossPointTie,LinkState,MAX_PRESET_COUNT,UNASSIGNED_PRESET,validate_name};
+use crate::supervisor::{self,lock};
+use crate::{auth,discovery,signal,routing};
+use crate::scheduler::{self,Schedule,Schedules};
+use crate::events::{EventLog,EventKind};
+use crate::backup::PresetBackup;
+use crate::compare::{self,TieChange};
+use serialport::SerialPortType;
 use crate::config::{ServiceConfiguration};
-use crate::http::{HttpContext,HttpListener,HttpMethod,HttpResponseCode,HttpError,HttpResponse};
+use crate::http::{self,HttpContext,HttpListener,HttpMethod,HttpRequest,HttpResponseCode,HttpError,HttpResponse};
 
 const DEFAULT_BINDING: &str = "127.0.0.1:1872";
 const DEFAULT_HTTP_ROOT: &str = "../site/";
@@ -16,53 +25,136 @@ const TEXT_PLAIN: &str = "text/plain";
 const APPLICATION_JSON: &str = "application/json";
 
 
-pub fn start(config: ServiceConfiguration) {
-    std::thread::spawn(move || {
+/// Connects to the CrossPoint named in the configuration and serves the API on a background thread.
+/// The service starts even if the switcher is unreachable; the supervisor keeps trying to connect.
+/// Returns the address the listener is bound to.
+pub fn start(config: ServiceConfiguration) -> std::io::Result<SocketAddr> {
+    let settings = match config.connection_settings() {
+        Some(s) => s,
+        None => return Err(Error::new(ErrorKind::InvalidInput, "Serial port required"))
+    };
+    let port = match CrossPoint::connect(&settings) {
+        Ok(p) => {
+            log::info!("Connected to CrossPoint on port {}", p.port_name());
+            p
+        }
+        Err(e) => {
+            log::warn!("Failed to connect to CrossPoint on port {}: {}. Will keep retrying.", settings.port_name, e);
+            CrossPoint::disconnected(&settings, &e)
+        }
+    };
 
-        if config.serial_port.is_none() {
-            panic!("Serial port required")
+    start_with(config, port)
+}
+
+/// Serves the API for an already connected CrossPoint
+pub fn start_with(config: ServiceConfiguration, mut port: CrossPoint) -> std::io::Result<SocketAddr> {
+    if let Some(level) = config.front_panel_lockout {
+        match port.enforce_lockout(level) {
+            Ok(_) => log::info!("Front panel locked at level {}", level),
+            Err(e) => log::warn!("Failed to lock front panel: {}", e)
         }
+    }
+
+    port.set_preset_count(config.preset_count);
+    port.keep_history();
+
+    if port.is_connected() {
+        if let Err(e) = port.refresh_state() { log::warn!("Failed to read CrossPoint state: {}", e); }
+    }
 
-        let serial_port = config.serial_port.unwrap();
-        let mut port = CrossPoint::connect(&serial_port)
-            .expect(&(String::from("Failed to connect to CrossPoint on port ") + &serial_port));
-        println!("Connected to CrossPoint on port {}", port.port_name());
+    let binding = config.binding.unwrap_or(SocketAddrV4::from_str(DEFAULT_BINDING).unwrap());
 
-        let binding = config.binding.unwrap_or(SocketAddrV4::from_str(DEFAULT_BINDING).unwrap());
+    let mut listener = match (config.tls_certificate.as_ref(), config.tls_key.as_ref()) {
+        (Some(certificate), Some(key)) => HttpListener::bind_tls(binding, certificate, key)?,
+        _ => HttpListener::bind(binding)?
+    };
+    if let Some(cors) = config.cors.clone() { listener.set_cors(cors); }
+    let local_address = listener.listener.local_addr()?;
+    log::info!("Listening on: {}://{}", if listener.is_tls() { "https" } else { "http" }, local_address);
+
+    if let Some(redirect_binding) = config.redirect_binding {
+        let redirect_address = http::spawn_redirect(redirect_binding, local_address)?;
+        log::info!("Redirecting to HTTPS from: {}", redirect_address);
+    }
 
-        let listener = HttpListener::bind(binding)
-            .expect("Failed to bind");
-        println!("Listening on: {}", binding);
+    let port = Arc::new(Mutex::new(port));
+    let events = EventLog::new();
 
-        let http_root = config.http_root.unwrap_or(String::from(DEFAULT_HTTP_ROOT));
+    supervisor::spawn(port.clone());
+
+    let poll_interval = config.signal_poll_interval.unwrap_or(signal::DEFAULT_POLL_INTERVAL);
+    if !poll_interval.is_zero() {
+        signal::spawn(port.clone(), events.clone(), poll_interval);
+    }
+    if !config.routing_rules.is_empty() {
+        routing::spawn(port.clone(), events.clone(), config.routing_rules.clone());
+    }
+    let schedules = Schedules::new(config.schedules.clone());
+    scheduler::spawn(port.clone(), events.clone(), schedules.clone(), config.macros.clone(), config.utc_offset);
+
+    let mut config = config;
+    std::thread::spawn(move || {
         loop {
             let request = match listener.receive() {
                 Ok(r) => r,
                 Err(_) => continue
             };
 
-            handle_request(request, &mut port, &http_root);
+            handle_request(request, &port, &events, &schedules, &mut config);
         }
     });
+
+    Ok(local_address)
 }
 
-fn handle_request(mut context: HttpContext, cp: &mut CrossPoint, http_root: &str) {
+fn handle_request(mut context: HttpContext, port: &Mutex<CrossPoint>, events: &EventLog, schedules: &Schedules, config: &mut ServiceConfiguration) {
+    let http_root = config.http_root.clone().unwrap_or(String::from(DEFAULT_HTTP_ROOT));
 
     let body = match context.request.content.as_ref() {
         Some(c) => c,
         None => ""
     };
-    let method = context.request.method;
+    // HEAD is answered like GET without the content, and preflights without checking credentials
+    let method = match context.request.method {
+        HttpMethod::HEAD => HttpMethod::GET,
+        m => m
+    };
     let path = context.request.path.as_str();
 
+    if method == HttpMethod::OPTIONS {
+        // Other origins may only change something with POST
+        let requested = context.request.header("Access-Control-Request-Method").and_then(|m| HttpMethod::from_str(m).ok());
+        let response = match requested {
+            Some(HttpMethod::GET | HttpMethod::HEAD) if changes_through_get(path) =>
+                HttpResponse::from_error(&HttpError::new(405, "Only routes that read may be reached from other origins with GET")),
+            _ => HttpResponse { status_code: HttpResponseCode::new(204), content: None, mime: None }
+        };
+        context.send_response(response);
+        return;
+    }
+    if context.request.method == HttpMethod::HEAD && changes_through_get(path) {
+        context.send_response(HttpResponse::from_error(&HttpError::new(405, "HEAD is only answered for routes that read")));
+        return;
+    }
+
+    if !config.users.is_empty() {
+        if let Err(e) = auth::authorize(method, path, context.request.header("Authorization"), &config.users) {
+            context.send_response(HttpResponse::from_error(&e));
+            return;
+        }
+    }
+
     let response = match (method, path) {
-        //Look for ajax function
-        (HttpMethod::GET, "/activePresets") => get_presets_names(cp, true),
-        (HttpMethod::GET, "/saveCurrentToPreset") => save_current_config(cp, context.request.query_params.get("preset")),
-        (HttpMethod::GET, "/loadPreset") => load_preset(cp, context.request.query_params.get("preset")),
-        (HttpMethod::POST, "/createPreset") => create_preset(cp, &body),
-        (HttpMethod::GET, "/presetNames") => get_presets_names(cp, false),
-        (HttpMethod::GET, _) => match get_page(&path, http_root) {
+        (HttpMethod::GET, "/channelNames") => get_channel_names(config),
+        (HttpMethod::GET, "/events") => get_events(events, context.request.query_params.get("since")),
+        (HttpMethod::GET, "/schedules") => get_schedules(schedules, config),
+        (HttpMethod::POST, "/saveSchedule") => save_schedule(port, schedules, config, body),
+        (HttpMethod::POST, "/removeSchedule") => remove_schedule(schedules, config, body),
+        (HttpMethod::GET, "/macros") => get_macros(config),
+        // The macro takes the port itself, so its delays do not hold it
+        (HttpMethod::POST, "/runMacro") => run_macro(port, events, config, body),
+        (HttpMethod::GET, _) if is_static_file(method, path) => match get_page(path, &http_root) {
             Ok(p) => Ok(HttpResponse {
                 status_code: HttpResponseCode::new(200),
                 content: Some(p.0),
@@ -70,7 +162,7 @@ fn handle_request(mut context: HttpContext, cp: &mut CrossPoint, http_root: &str
             }),
             Err(e) => Err(e)
         },
-        (_, _) => Err(HttpError::new(404, "Resource does not exist"))
+        (_, _) => handle_switcher_request(&mut lock(port), method, &context.request, events, config)
     };
 
     context.send_response(match response {
@@ -83,6 +175,118 @@ fn handle_request(mut context: HttpContext, cp: &mut CrossPoint, http_root: &str
     });
 }
 
+/// Answers the routes that talk to the switcher, holding it until the answer is ready
+fn handle_switcher_request(cp: &mut CrossPoint, method: HttpMethod, request: &HttpRequest, events: &EventLog, config: &mut ServiceConfiguration)
+    -> Result<HttpResponse, HttpError> {
+    let body = match request.content.as_ref() {
+        Some(c) => c,
+        None => ""
+    };
+    let params = &request.query_params;
+
+    match (method, request.path.as_str()) {
+        (HttpMethod::GET, "/linkState") => get_link_state(cp),
+        (HttpMethod::GET, "/serialPorts") => get_serial_ports(cp),
+        (_, _) if !cp.is_connected() => Err(HttpError::new(503, "CrossPoint is not connected")),
+        //Look for ajax function
+        (HttpMethod::GET, "/activePresets") => get_presets_names(cp, true),
+        (HttpMethod::GET, "/saveCurrentToPreset") => save_current_config(cp, params.get("preset")),
+        (HttpMethod::GET, "/loadPreset") => load_preset(cp, params.get("preset")),
+        (HttpMethod::POST, "/createPreset") => create_preset(cp, body),
+        (HttpMethod::POST, "/renamePreset") => rename_preset(cp, body),
+        (HttpMethod::GET, "/presetNames") => get_presets_names(cp, false),
+        (HttpMethod::GET, "/comparePresets") => compare_presets(cp, params.get("from"), params.get("to")),
+        (HttpMethod::POST, "/comparePresets") => compare_new_preset(cp, body),
+        (HttpMethod::GET, "/exportPresets") => export_presets(cp),
+        (HttpMethod::POST, "/importPresets") => import_presets(cp, body, params.get("dryRun")),
+        (HttpMethod::GET, "/ties") => get_ties(cp),
+        (HttpMethod::GET, "/inputs") => get_inputs(cp, config),
+        (HttpMethod::GET, "/audioLevels") => get_audio_levels(cp),
+        (HttpMethod::POST, "/setVolume") => set_volume(cp, params.get("output"), params.get("level")),
+        (HttpMethod::POST, "/setGain") => set_gain(cp, params.get("input"), params.get("level")),
+        (HttpMethod::POST, "/videoMute") => set_mute(cp, events, false, params.get("output"), params.get("mute")),
+        (HttpMethod::POST, "/audioMute") => set_mute(cp, events, true, params.get("output"), params.get("mute")),
+        (HttpMethod::GET, "/muteStatus") => get_mute_status(cp),
+        (HttpMethod::POST, "/syncNames") => sync_names(cp, config, params.get("direction")),
+        (HttpMethod::GET, "/history") => get_history(cp),
+        (HttpMethod::POST, "/restoreHistory") => restore_history(cp, params.get("id")),
+        (HttpMethod::POST, "/undo") => undo(cp),
+        (HttpMethod::GET, "/lockout") => get_lockout(cp),
+        (HttpMethod::POST, "/setLockout") => set_lockout(cp, events, params.get("level")),
+        (_, _) => Err(HttpError::new(404, "Resource does not exist"))
+    }
+}
+
+/// Whether a GET to the path changes something, as loading and saving presets do
+fn changes_through_get(path: &str) -> bool {
+    auth::required_role(HttpMethod::GET, path) != auth::Role::Viewer
+}
+
+/// Site pages are served even while the switcher is unreachable
+fn is_static_file(method: HttpMethod, path: &str) -> bool {
+    method == HttpMethod::GET && (path == "/" || Path::new(path).extension().is_some())
+}
+
+fn get_link_state(cp: &CrossPoint) -> Result<HttpResponse, HttpError> {
+    let mut response = Object::new();
+    response.insert("Port", JsonValue::String(cp.port_name().to_string()));
+    response.insert("Inputs", JsonValue::Number(Number::from(cp.input_port_count())));
+    response.insert("Outputs", JsonValue::Number(Number::from(cp.output_port_count())));
+    response.insert("Audio", JsonValue::Boolean(cp.audio_is_supported()));
+    match cp.link_state() {
+        LinkState::Connected => {
+            response.insert("State", JsonValue::String(String::from("Connected")));
+        }
+        LinkState::Disconnected { error, attempts } => {
+            response.insert("State", JsonValue::String(String::from("Disconnected")));
+            response.insert("Error", JsonValue::String(error.clone()));
+            response.insert("Attempts", JsonValue::Number(Number::from(*attempts)));
+        }
+    }
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+fn get_serial_ports(cp: &CrossPoint) -> Result<HttpResponse, HttpError> {
+    let ports = match discovery::list_ports() {
+        Ok(p) => p,
+        Err(e) => return Err(HttpError::new(500, &e.to_string()))
+    };
+
+    let mut port_list = Array::new();
+    for port in ports {
+        let mut o = Object::new();
+        o.insert("Name", JsonValue::String(port.port_name.clone()));
+        o.insert("InUse", JsonValue::Boolean(port.port_name == cp.port_name()));
+        match port.port_type {
+            SerialPortType::UsbPort(usb) => {
+                o.insert("Type", JsonValue::String(String::from("USB")));
+                o.insert("VendorId", JsonValue::String(format!("{:04x}", usb.vid)));
+                o.insert("ProductId", JsonValue::String(format!("{:04x}", usb.pid)));
+                o.insert("Manufacturer", usb.manufacturer.map_or(JsonValue::Null, JsonValue::String));
+                o.insert("Product", usb.product.map_or(JsonValue::Null, JsonValue::String));
+                o.insert("SerialNumber", usb.serial_number.map_or(JsonValue::Null, JsonValue::String));
+            }
+            SerialPortType::PciPort => o.insert("Type", JsonValue::String(String::from("PCI"))),
+            SerialPortType::BluetoothPort => o.insert("Type", JsonValue::String(String::from("Bluetooth"))),
+            SerialPortType::Unknown => o.insert("Type", JsonValue::String(String::from("Unknown")))
+        }
+        port_list.push(JsonValue::Object(o));
+    }
+    let mut response = Object::new();
+    response.insert("Ports", JsonValue::Array(port_list));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
 fn get_page(relative_path: &str, http_root: &str) -> Result<(String, String), HttpError> {
     let relative_path = if relative_path == "/" { "index.html" } else { relative_path.trim_start_matches('/') };
 
@@ -116,21 +320,22 @@ fn get_page(relative_path: &str, http_root: &str) -> Result<(String, String), Ht
 }
 
 fn get_presets_names(cp: &mut CrossPoint, only_active: bool) -> Result<HttpResponse, HttpError> {
+    let names = match cp.preset_names() {
+        Ok(n) => n,
+        Err(e) => return Err(HttpError::new(500, e.to_string().as_str()))
+    };
+    let preset_count = names.len();
     let mut presets = Array::new();
-    for i in 1..=32 {
-        let name = match cp.get_preset_name(i) {
-            Ok(n) => n,
-            Err(e) => return Err(HttpError::new(500, e.to_string().as_str()))
-        };
-        let name = name.trim().to_string();
-        if !only_active || name != "[unassigned]" {
+    for (i, name) in names.into_iter().enumerate() {
+        if !only_active || name != UNASSIGNED_PRESET {
             let mut o = Object::new();
-            o.insert("Number", JsonValue::Number(Number::from(i)));
+            o.insert("Number", JsonValue::Number(Number::from(i + 1)));
             o.insert("Name", JsonValue::String(name));
             presets.push(JsonValue::Object(o));
         }
     }
     let mut response = Object::new();
+    response.insert("PresetCount", JsonValue::Number(Number::from(preset_count)));
     response.insert("Presets", JsonValue::Array(presets));
 
     Ok(HttpResponse {
@@ -146,9 +351,7 @@ fn save_current_config(cp: &mut CrossPoint, preset_number_param: Option<&String>
         Err(_) => return Err(HttpError::new(400, "Missing preset number argument"))
     };
 
-    if preset_number < 1 && preset_number > 32 {
-        return Err(HttpError::new(400, "Invalid preset number"));
-    }
+    check_preset_number(cp, preset_number)?;
 
     match cp.save_current_config(preset_number) {
         Ok(_) => Ok(HttpResponse { status_code: HttpResponseCode::new(200), content: None, mime: None }),
@@ -156,26 +359,33 @@ fn save_current_config(cp: &mut CrossPoint, preset_number_param: Option<&String>
     }
 }
 
+/// Rejects preset numbers beyond what the switcher stores
+fn check_preset_number(cp: &mut CrossPoint, preset_number: i32) -> Result<(), HttpError> {
+    let preset_count = match cp.preset_count() {
+        Ok(c) => c,
+        Err(e) => return Err(HttpError::new(500, &e.to_string()))
+    };
+    if !(1..=preset_count).contains(&preset_number) {
+        return Err(HttpError::new(400, "Invalid preset number"));
+    }
+    Ok(())
+}
+
 fn load_preset(port: &mut CrossPoint, preset_number_param: Option<&String>) -> Result<HttpResponse, HttpError> {
     let preset_number: i32 = match preset_number_param {
-        Some(p) => match p.parse() {
-            Ok(n) => n,
-            Err(_) => 0
-        }
+        Some(p) => p.parse().unwrap_or_default(),
         None => return Err(HttpError::new(400, "Missing preset number argument"))
     };
 
-    if preset_number < 1 && preset_number > 32 {
-        return Err(HttpError::new(400, "Invalid preset number"));
-    }
+    check_preset_number(port, preset_number)?;
 
     match port.load_preset(preset_number) {
-        Ok(_) => return Ok(HttpResponse {
+        Ok(_) => Ok(HttpResponse {
             content: None,
             mime: None,
             status_code: HttpResponseCode { code: 200 }
         }),
-        Err(_) => return Err(HttpError::new(400, "Missing or invalid preset"))
+        Err(_) => Err(HttpError::new(400, "Missing or invalid preset"))
     }
 }
 
@@ -185,7 +395,14 @@ fn create_preset(port: &mut CrossPoint, content: &str) -> Result<HttpResponse, H
         Err(_) => return Err(HttpError::new(400, "Unparseable content"))
      };
      
-    let preset = CrossPointPreset::from(json_obj);
+    let preset = match CrossPointPreset::from(json_obj) {
+        Ok(p) => p,
+        Err(e) => return Err(HttpError::new(400, &e))
+    };
+    check_preset_number(port, preset.number)?;
+    if !preset.name.is_empty() {
+        if let Err(e) = validate_name(&preset.name) { return Err(HttpError::new(400, &e.to_string())); }
+    }
     match port.create_preset(preset) {
         Ok(_) => Ok(HttpResponse {
             content: None,
@@ -194,4 +411,600 @@ fn create_preset(port: &mut CrossPoint, content: &str) -> Result<HttpResponse, H
         }),
         Err(e) => Err(HttpError::new(500, &e.to_string()))
     }
-}
\ No newline at end of file
+}
+
+/// Renames a stored preset from a body holding `PresetNumber` and `PresetName`
+fn rename_preset(port: &mut CrossPoint, content: &str) -> Result<HttpResponse, HttpError> {
+    let json_obj = match json::parse(content) {
+        Ok(j) => j,
+        Err(_) => return Err(HttpError::new(400, "Unparseable content"))
+    };
+
+    let preset_number = match json_obj["PresetNumber"].as_i32() {
+        Some(n) => n,
+        None => return Err(HttpError::new(400, "Invalid preset number"))
+    };
+    check_preset_number(port, preset_number)?;
+    let name = match json_obj["PresetName"].as_str() {
+        Some(n) => n,
+        None => return Err(HttpError::new(400, "Missing preset name"))
+    };
+    if let Err(e) = validate_name(name) {
+        return Err(HttpError::new(400, &e.to_string()));
+    }
+
+    match port.set_preset_name(preset_number, name) {
+        Ok(_) => Ok(HttpResponse { status_code: HttpResponseCode::new(200), content: None, mime: None }),
+        Err(e) if e.kind() == ErrorKind::InvalidInput => Err(HttpError::new(400, &e.to_string())),
+        Err(e) => Err(HttpError::new(500, &e.to_string()))
+    }
+}
+
+/// Lists how the ties of `to` differ from those of `from`, where each is a preset number or
+/// `current` for the routing in use. `from` defaults to the current routing.
+fn compare_presets(cp: &mut CrossPoint, from_param: Option<&String>, to_param: Option<&String>) -> Result<HttpResponse, HttpError> {
+    let to_param = match to_param {
+        Some(t) => t,
+        None => return Err(HttpError::new(400, "Missing preset to compare"))
+    };
+    let from = ties_of(cp, from_param.map_or("current", |f| f.as_str()))?;
+    let to = ties_of(cp, to_param)?;
+    Ok(tie_changes_response(compare::compare_ties(&from, &to)))
+}
+
+/// Previews what writing a createPreset body would change in the stored preset
+fn compare_new_preset(cp: &mut CrossPoint, content: &str) -> Result<HttpResponse, HttpError> {
+    let preset = match json::parse(content).map_err(|_| String::from("Unparseable content")).and_then(CrossPointPreset::from) {
+        Ok(p) => p,
+        Err(e) => return Err(HttpError::new(400, &e))
+    };
+    let stored = ties_of(cp, &preset.number.to_string())?;
+    Ok(tie_changes_response(compare::compare_ties(&stored, &preset.ties)))
+}
+
+/// Ties of a stored preset, empty for an unassigned one, or the current ties for `current`
+fn ties_of(cp: &mut CrossPoint, preset: &str) -> Result<Vec<CrossPointTie>, HttpError> {
+    let result = if preset == "current" {
+        cp.current_ties()
+    } else {
+        let preset_number = match preset.parse() {
+            Ok(n) => n,
+            Err(_) => return Err(HttpError::new(400, "Invalid preset number"))
+        };
+        check_preset_number(cp, preset_number)?;
+        cp.get_preset(preset_number).map(|p| p.map(|p| p.ties).unwrap_or_default())
+    };
+    match result {
+        Ok(t) => Ok(t),
+        Err(e) => Err(HttpError::new(500, &e.to_string()))
+    }
+}
+
+fn tie_changes_response(changes: Vec<TieChange>) -> HttpResponse {
+    let mut response = Object::new();
+    response.insert("Changes", JsonValue::Array(changes.iter().map(|c| c.to_json()).collect()));
+
+    HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    }
+}
+
+/// Every stored preset as a backup document
+fn export_presets(cp: &mut CrossPoint) -> Result<HttpResponse, HttpError> {
+    let backup = match PresetBackup::read(cp) {
+        Ok(b) => b,
+        Err(e) => return Err(HttpError::new(500, &e.to_string()))
+    };
+
+    Ok(HttpResponse {
+        content: Some(json::stringify_pretty(backup.to_json(), 4)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+/// Restores a backup document, or with `dryRun=1` only lists the presets it would change
+fn import_presets(cp: &mut CrossPoint, content: &str, dry_run_param: Option<&String>) -> Result<HttpResponse, HttpError> {
+    let backup = match PresetBackup::parse(content) {
+        Ok(b) => b,
+        Err(e) => return Err(HttpError::new(400, &e))
+    };
+    let dry_run = matches!(dry_run_param.map(String::as_str), Some("1" | "true"));
+
+    let changes = match if dry_run { backup.plan(cp) } else { backup.restore(cp) } {
+        Ok(c) => c,
+        Err(e) if e.kind() == ErrorKind::InvalidInput => return Err(HttpError::new(400, &e.to_string())),
+        Err(e) => return Err(HttpError::new(500, &e.to_string()))
+    };
+
+    let mut response = Object::new();
+    response.insert("DryRun", JsonValue::Boolean(dry_run));
+    response.insert("Changes", JsonValue::Array(changes.iter().map(|c| c.to_json()).collect()));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+fn get_audio_levels(cp: &mut CrossPoint) -> Result<HttpResponse, HttpError> {
+    let limits = match cp.audio_limits() {
+        Some(l) => l,
+        None => return Err(HttpError::new(400, "CrossPoint has no audio"))
+    };
+
+    let mut inputs = Array::new();
+    for i in 1..=cp.input_port_count() {
+        let gain = match cp.get_gain(i) {
+            Ok(g) => g,
+            Err(e) => return Err(HttpError::new(500, &e.to_string()))
+        };
+        let mut o = Object::new();
+        o.insert("Channel", JsonValue::Number(Number::from(i)));
+        o.insert("Gain", JsonValue::Number(Number::from(gain)));
+        inputs.push(JsonValue::Object(o));
+    }
+
+    let mut outputs = Array::new();
+    for i in 1..=cp.output_port_count() {
+        let volume = match cp.get_volume(i) {
+            Ok(v) => v,
+            Err(e) => return Err(HttpError::new(500, &e.to_string()))
+        };
+        let mut o = Object::new();
+        o.insert("Channel", JsonValue::Number(Number::from(i)));
+        o.insert("Volume", JsonValue::Number(Number::from(volume)));
+        outputs.push(JsonValue::Object(o));
+    }
+
+    let mut limit_object = Object::new();
+    limit_object.insert("MaxVolume", JsonValue::Number(Number::from(limits.max_volume)));
+    limit_object.insert("MaxGain", JsonValue::Number(Number::from(limits.max_gain)));
+    limit_object.insert("MaxAttenuation", JsonValue::Number(Number::from(limits.max_attenuation)));
+
+    let mut response = Object::new();
+    response.insert("Limits", JsonValue::Object(limit_object));
+    response.insert("Inputs", JsonValue::Array(inputs));
+    response.insert("Outputs", JsonValue::Array(outputs));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+fn set_volume(cp: &mut CrossPoint, output_param: Option<&String>, level_param: Option<&String>) -> Result<HttpResponse, HttpError> {
+    let output = match output_param.unwrap_or(&String::new()).parse() {
+        Ok(n) => n,
+        Err(_) => return Err(HttpError::new(400, "Missing output argument"))
+    };
+    let level = match level_param.unwrap_or(&String::new()).parse() {
+        Ok(n) => n,
+        Err(_) => return Err(HttpError::new(400, "Missing level argument"))
+    };
+
+    command_response(cp.set_volume(output, level))
+}
+
+fn set_gain(cp: &mut CrossPoint, input_param: Option<&String>, level_param: Option<&String>) -> Result<HttpResponse, HttpError> {
+    let input = match input_param.unwrap_or(&String::new()).parse() {
+        Ok(n) => n,
+        Err(_) => return Err(HttpError::new(400, "Missing input argument"))
+    };
+    let level = match level_param.unwrap_or(&String::new()).parse() {
+        Ok(n) => n,
+        Err(_) => return Err(HttpError::new(400, "Missing level argument"))
+    };
+
+    command_response(cp.set_gain(input, level))
+}
+
+/// Out of range values and models without audio are the caller's mistake; anything else is the device's
+fn command_response(result: Result<String, Error>) -> Result<HttpResponse, HttpError> {
+    match result {
+        Ok(_) => Ok(HttpResponse { status_code: HttpResponseCode::new(200), content: None, mime: None }),
+        Err(e) if e.kind() == ErrorKind::InvalidInput || e.kind() == ErrorKind::Unsupported => Err(HttpError::new(400, &e.to_string())),
+        Err(e) => Err(HttpError::new(500, &e.to_string()))
+    }
+}
+
+fn get_events(events: &EventLog, since_param: Option<&String>) -> Result<HttpResponse, HttpError> {
+    let since = match since_param {
+        Some(s) => match s.parse() {
+            Ok(n) => n,
+            Err(_) => return Err(HttpError::new(400, "Invalid since argument"))
+        },
+        None => 0
+    };
+
+    let mut event_list = Array::new();
+    for event in events.since(since) {
+        event_list.push(event.to_json());
+    }
+    let mut response = Object::new();
+    response.insert("Latest", JsonValue::Number(Number::from(events.latest())));
+    response.insert("Events", JsonValue::Array(event_list));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+/// Mutes one output, or every output when no output is given
+fn set_mute(cp: &mut CrossPoint, events: &EventLog, audio: bool, output_param: Option<&String>, mute_param: Option<&String>)
+    -> Result<HttpResponse, HttpError> {
+    let output = match output_param {
+        Some(o) => match o.parse() {
+            Ok(n) => Some(n),
+            Err(_) => return Err(HttpError::new(400, "Invalid output argument"))
+        },
+        None => None
+    };
+    let muted = match mute_param.map(String::as_str) {
+        Some("1") | Some("true") => true,
+        Some("0") | Some("false") => false,
+        _ => return Err(HttpError::new(400, "Missing mute argument"))
+    };
+
+    let result = match (audio, output) {
+        (false, Some(n)) => cp.set_video_mute(n, muted),
+        (false, None) => cp.set_global_video_mute(muted),
+        (true, Some(n)) => cp.set_audio_mute(n, muted),
+        (true, None) => cp.set_global_audio_mute(muted)
+    };
+    let response = command_response(result)?;
+
+    events.publish(if audio { EventKind::AudioMute { output, muted } } else { EventKind::VideoMute { output, muted } });
+    Ok(response)
+}
+
+fn get_mute_status(cp: &mut CrossPoint) -> Result<HttpResponse, HttpError> {
+    let mut outputs = Array::new();
+    for i in 1..=cp.output_port_count() {
+        let video = match cp.video_mute_status(i) {
+            Ok(m) => m,
+            Err(e) => return Err(HttpError::new(500, &e.to_string()))
+        };
+        let audio = if cp.audio_is_supported() {
+            match cp.audio_mute_status(i) {
+                Ok(m) => JsonValue::Boolean(m),
+                Err(e) => return Err(HttpError::new(500, &e.to_string()))
+            }
+        } else {
+            JsonValue::Null
+        };
+
+        let mut o = Object::new();
+        o.insert("Channel", JsonValue::Number(Number::from(i)));
+        o.insert("Video", JsonValue::Boolean(video));
+        o.insert("Audio", audio);
+        outputs.push(JsonValue::Object(o));
+    }
+    let mut response = Object::new();
+    response.insert("Outputs", JsonValue::Array(outputs));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+fn get_lockout(cp: &mut CrossPoint) -> Result<HttpResponse, HttpError> {
+    let level = match cp.lockout() {
+        Ok(l) => l,
+        Err(e) => return Err(HttpError::new(500, &e.to_string()))
+    };
+    let mut response = Object::new();
+    response.insert("Level", JsonValue::Number(Number::from(level)));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+fn set_lockout(cp: &mut CrossPoint, events: &EventLog, level_param: Option<&String>) -> Result<HttpResponse, HttpError> {
+    let level = match level_param.unwrap_or(&String::new()).parse() {
+        Ok(n) => n,
+        Err(_) => return Err(HttpError::new(400, "Missing level argument"))
+    };
+
+    let response = command_response(cp.set_lockout(level))?;
+    events.publish(EventKind::Lockout { level });
+    Ok(response)
+}
+
+/// Routing snapshots, newest first
+fn get_history(cp: &CrossPoint) -> Result<HttpResponse, HttpError> {
+    let entries = match cp.history() {
+        Some(h) => h.entries().rev().map(|e| e.to_json()).collect(),
+        None => Vec::new()
+    };
+    let mut response = Object::new();
+    response.insert("History", JsonValue::Array(entries));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+fn restore_history(cp: &mut CrossPoint, id_param: Option<&String>) -> Result<HttpResponse, HttpError> {
+    let id = match id_param.and_then(|i| i.parse().ok()) {
+        Some(i) => i,
+        None => return Err(HttpError::new(400, "Missing or invalid history id"))
+    };
+    history_response(cp.restore_history(id))
+}
+
+/// Puts back the routing from before the latest change. Undoing again redoes it.
+fn undo(cp: &mut CrossPoint) -> Result<HttpResponse, HttpError> {
+    let id = match cp.history().and_then(|h| h.last()) {
+        Some(e) => e.id,
+        None => return Err(HttpError::new(404, "Nothing to undo"))
+    };
+    history_response(cp.restore_history(id))
+}
+
+fn history_response(result: Result<(), Error>) -> Result<HttpResponse, HttpError> {
+    match result {
+        Ok(_) => Ok(HttpResponse { status_code: HttpResponseCode::new(200), content: None, mime: None }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(HttpError::new(404, &e.to_string())),
+        Err(e) => Err(HttpError::new(500, &e.to_string()))
+    }
+}
+
+fn get_macros(config: &ServiceConfiguration) -> Result<HttpResponse, HttpError> {
+    let mut macros = Array::new();
+    for definition in &config.macros {
+        let mut o = Object::new();
+        o.insert("Name", JsonValue::String(definition.name.clone()));
+        o.insert("Steps", JsonValue::Number(Number::from(definition.steps.len())));
+        macros.push(JsonValue::Object(o));
+    }
+    let mut response = Object::new();
+    response.insert("Macros", JsonValue::Array(macros));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+/// Runs the macro named by `Name` in the body. Answers with the run report, with a 500
+/// status if a step failed.
+fn run_macro(port: &Mutex<CrossPoint>, events: &EventLog, config: &ServiceConfiguration, content: &str) -> Result<HttpResponse, HttpError> {
+    let name = match json::parse(content) {
+        Ok(j) => j["Name"].as_str().map(String::from),
+        Err(_) => return Err(HttpError::new(400, "Unparseable content"))
+    };
+    let definition = match config.macros.iter().find(|m| Some(&m.name) == name.as_ref()) {
+        Some(m) => m,
+        None => return Err(HttpError::new(404, "No such macro"))
+    };
+    if !lock(port).is_connected() { return Err(HttpError::new(503, "CrossPoint is not connected")); }
+
+    let report = definition.run(port);
+    match &report.failure {
+        None => log::info!("Ran macro \"{}\"", report.name),
+        Some((step, e)) => log::warn!("Macro \"{}\" failed at step {}: {}", report.name, step, e)
+    }
+    events.publish(EventKind::Macro { name: report.name.clone(), error: report.failure.as_ref().map(|(_, e)| e.clone()) });
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(report.to_json())),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(if report.failure.is_none() { 200 } else { 500 })
+    })
+}
+
+/// Every schedule with its action and the next time it runs, in seconds since the epoch
+fn get_schedules(schedules: &Schedules, config: &ServiceConfiguration) -> Result<HttpResponse, HttpError> {
+    let now = std::time::SystemTime::now();
+    let mut schedule_list = Array::new();
+    for schedule in schedules.all() {
+        let mut o = Object::new();
+        o.insert("Name", JsonValue::String(schedule.name.clone()));
+        o.insert("Cron", JsonValue::String(schedule.cron.text.clone()));
+        o.insert("Action", JsonValue::String(schedule.describe()));
+        o.insert("NextRun", schedule.cron.next_run(now, config.utc_offset)
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map_or(JsonValue::Null, |d| JsonValue::Number(Number::from(d.as_secs()))));
+        schedule_list.push(JsonValue::Object(o));
+    }
+    let mut response = Object::new();
+    response.insert("Schedules", JsonValue::Array(schedule_list));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+/// Adds the schedule in the body, written as in service.json, or replaces the one with its
+/// name. The schedules are saved to the config file.
+fn save_schedule(port: &Mutex<CrossPoint>, schedules: &Schedules, config: &mut ServiceConfiguration, content: &str) -> Result<HttpResponse, HttpError> {
+    // The configured count, or the switcher's while it can be asked
+    let preset_count = lock(port).preset_count().unwrap_or(config.preset_count.unwrap_or(MAX_PRESET_COUNT));
+    let schedule = match json::parse(content) {
+        Ok(j) => match Schedule::parse(&j, &config.inputs, &config.outputs, &config.macros, preset_count) {
+            Ok(s) => s,
+            Err(e) => return Err(HttpError::new(400, &e.to_string()))
+        },
+        Err(_) => return Err(HttpError::new(400, "Unparseable content"))
+    };
+
+    log::info!("Saved schedule \"{}\": {} at \"{}\"", schedule.name, schedule.describe(), schedule.cron.text);
+    schedules.save(schedule);
+    store_schedules(schedules, config)?;
+    get_schedules(schedules, config)
+}
+
+/// Removes the schedule named by `Name` in the body
+fn remove_schedule(schedules: &Schedules, config: &mut ServiceConfiguration, content: &str) -> Result<HttpResponse, HttpError> {
+    let name = match json::parse(content) {
+        Ok(j) => j["Name"].as_str().map(String::from).unwrap_or_default(),
+        Err(_) => return Err(HttpError::new(400, "Unparseable content"))
+    };
+    if !schedules.remove(&name) {
+        return Err(HttpError::new(404, "No such schedule"));
+    }
+
+    log::info!("Removed schedule \"{}\"", name);
+    store_schedules(schedules, config)?;
+    get_schedules(schedules, config)
+}
+
+fn store_schedules(schedules: &Schedules, config: &mut ServiceConfiguration) -> Result<(), HttpError> {
+    config.schedules = schedules.all();
+    match config.save_schedules() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(HttpError::new(500, &e.to_string()))
+    }
+}
+
+/// Input and output names from the configuration
+fn get_channel_names(config: &ServiceConfiguration) -> Result<HttpResponse, HttpError> {
+    let mut response = Object::new();
+    response.insert("Inputs", channel_name_array(&config.inputs));
+    response.insert("Outputs", channel_name_array(&config.outputs));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+fn channel_name_array(names: &HashMap<i32, String>) -> JsonValue {
+    let mut channels: Vec<(&i32, &String)> = names.iter().collect();
+    channels.sort();
+
+    let mut channel_list = Array::new();
+    for (channel, name) in channels {
+        let mut o = Object::new();
+        o.insert("Channel", JsonValue::Number(Number::from(*channel)));
+        o.insert("Name", JsonValue::String(name.clone()));
+        channel_list.push(JsonValue::Object(o));
+    }
+    JsonValue::Array(channel_list)
+}
+
+/// `push` stores the configured names on the switcher; `pull` replaces the configured
+/// names with the ones stored on the switcher and saves them to the config file
+fn sync_names(cp: &mut CrossPoint, config: &mut ServiceConfiguration, direction_param: Option<&String>) -> Result<HttpResponse, HttpError> {
+    match direction_param.map(String::as_str) {
+        Some("push") => push_names(cp, config)?,
+        Some("pull") => pull_names(cp, config)?,
+        _ => return Err(HttpError::new(400, "Direction must be push or pull"))
+    }
+
+    get_channel_names(config)
+}
+
+fn push_names(cp: &mut CrossPoint, config: &ServiceConfiguration) -> Result<(), HttpError> {
+    for (channels, count, kind) in [(&config.inputs, cp.input_port_count(), "Input"), (&config.outputs, cp.output_port_count(), "Output")] {
+        for (channel, name) in channels {
+            if !(1..=count).contains(channel) {
+                return Err(HttpError::new(400, &format!("{} {} does not exist on the switcher", kind, channel)));
+            }
+            if let Err(e) = validate_name(name) {
+                return Err(HttpError::new(400, &format!("{} {}: {}", kind, channel, e)));
+            }
+        }
+    }
+
+    for (channel, name) in &config.inputs {
+        if let Err(e) = cp.set_input_name(*channel, name) { return Err(HttpError::new(500, &e.to_string())); }
+    }
+    for (channel, name) in &config.outputs {
+        if let Err(e) = cp.set_output_name(*channel, name) { return Err(HttpError::new(500, &e.to_string())); }
+    }
+    Ok(())
+}
+
+fn pull_names(cp: &mut CrossPoint, config: &mut ServiceConfiguration) -> Result<(), HttpError> {
+    let mut inputs = HashMap::new();
+    for i in 1..=cp.input_port_count() {
+        match cp.get_input_name(i) {
+            Ok(n) => inputs.insert(i, n),
+            Err(e) => return Err(HttpError::new(500, &e.to_string()))
+        };
+    }
+    let mut outputs = HashMap::new();
+    for i in 1..=cp.output_port_count() {
+        match cp.get_output_name(i) {
+            Ok(n) => outputs.insert(i, n),
+            Err(e) => return Err(HttpError::new(500, &e.to_string()))
+        };
+    }
+
+    config.inputs = inputs;
+    config.outputs = outputs;
+    match config.save_channel_names() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(HttpError::new(500, &e.to_string()))
+    }
+}
+
+/// Every input on the switcher with its configured name and whether it has a signal.
+/// `Signal` is null on models that do not report it.
+fn get_inputs(cp: &mut CrossPoint, config: &ServiceConfiguration) -> Result<HttpResponse, HttpError> {
+    let presence = match cp.signal_presence() {
+        Ok(p) => Some(p),
+        Err(e) if e.kind() == ErrorKind::Unsupported => None,
+        Err(e) => return Err(HttpError::new(500, &e.to_string()))
+    };
+
+    let mut inputs = Array::new();
+    for i in 1..=cp.input_port_count() {
+        let mut o = Object::new();
+        o.insert("Channel", JsonValue::Number(Number::from(i)));
+        o.insert("Name", config.inputs.get(&i).map_or(JsonValue::Null, |n| JsonValue::String(n.clone())));
+        o.insert("Signal", presence.as_ref().map_or(JsonValue::Null, |p| JsonValue::Boolean(p[i as usize - 1])));
+        inputs.push(JsonValue::Object(o));
+    }
+    let mut response = Object::new();
+    response.insert("Inputs", JsonValue::Array(inputs));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
+
+fn get_ties(cp: &mut CrossPoint) -> Result<HttpResponse, HttpError> {
+    let ties = match cp.current_ties() {
+        Ok(t) => t,
+        Err(e) => return Err(HttpError::new(500, &e.to_string()))
+    };
+
+    let mut tie_list = Array::new();
+    for tie in ties {
+        let mut o = Object::new();
+        o.insert("InputChannel", JsonValue::Number(Number::from(tie.input_channel)));
+        o.insert("OutputChannel", JsonValue::Number(Number::from(tie.output_channel)));
+        o.insert("IOType", JsonValue::String(format!("{:?}", tie.io_type)));
+        tie_list.push(JsonValue::Object(o));
+    }
+    let mut response = Object::new();
+    response.insert("Ties", JsonValue::Array(tie_list));
+
+    Ok(HttpResponse {
+        content: Some(json::stringify(response)),
+        mime: Some(String::from(APPLICATION_JSON)),
+        status_code: HttpResponseCode::new(200)
+    })
+}
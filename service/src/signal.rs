@@ -0,0 +1,46 @@
+use std::{
+    io::ErrorKind,
+    sync::{Arc,Mutex},
+    thread,
+    time::Duration
+};
+use crate::{
+    crosspoint::CrossPoint,
+    events::{EventLog,EventKind},
+    supervisor::lock
+};
+
+/// How often inputs are checked when the configuration does not say
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Polls the switcher for input signal presence and publishes an event whenever an input
+/// gains or loses its signal. Stops if the model does not report signal presence.
+pub fn spawn(port: Arc<Mutex<CrossPoint>>, events: EventLog, interval: Duration) {
+    thread::spawn(move || {
+        let mut previous: Option<Vec<bool>> = None;
+        loop {
+            thread::sleep(interval);
+            let mut cp = lock(&port);
+            if !cp.is_connected() { continue; }
+
+            let presence = match cp.signal_presence() {
+                Ok(p) => p,
+                Err(e) if e.kind() == ErrorKind::Unsupported => {
+                    log::info!("Not polling signal presence: {}", e);
+                    return;
+                }
+                Err(_) => continue
+            };
+            drop(cp);
+
+            if let Some(previous) = previous.as_ref().filter(|p| p.len() == presence.len()) {
+                for (index, (&was, &is)) in previous.iter().zip(presence.iter()).enumerate() {
+                    if was != is {
+                        events.publish(EventKind::SignalPresence { input: index as i32 + 1, present: is });
+                    }
+                }
+            }
+            previous = Some(presence);
+        }
+    });
+}
@@ -0,0 +1,165 @@
+use crate::crosspoint::{CrossPointTie,CrossPointIO};
+
+/// What the service knows about the switcher, so reads can be answered without a round
+/// trip. Entries are None until loaded and go back to None when a message says they changed
+/// in a way that has to be read again.
+pub struct DeviceState {
+    preset_names: Vec<Option<String>>,
+    input_names: Vec<Option<String>>,
+    output_names: Vec<Option<String>>,
+    /// Input feeding each output's video and audio, 0 when nothing is tied
+    video_ties: Vec<u8>,
+    audio_ties: Vec<u8>,
+    ties_known: bool
+}
+
+impl DeviceState {
+    pub fn new(preset_count: usize, input_count: usize, output_count: usize) -> DeviceState {
+        DeviceState {
+            preset_names: vec![None; preset_count],
+            input_names: vec![None; input_count],
+            output_names: vec![None; output_count],
+            video_ties: vec![0; output_count],
+            audio_ties: vec![0; output_count],
+            ties_known: false
+        }
+    }
+
+    /// Grows or shrinks the preset slots once the switcher's capacity is known
+    pub fn set_preset_count(&mut self, preset_count: usize) {
+        self.preset_names.resize(preset_count, None);
+    }
+
+    pub fn preset_name(&self, preset_number: i32) -> Option<&str> {
+        slot(&self.preset_names, preset_number).and_then(|n| n.as_deref())
+    }
+
+    pub fn set_preset_name(&mut self, preset_number: i32, name: Option<String>) {
+        if let Some(entry) = slot_mut(&mut self.preset_names, preset_number) { *entry = name; }
+    }
+
+    pub fn input_name(&self, input: i32) -> Option<&str> {
+        slot(&self.input_names, input).and_then(|n| n.as_deref())
+    }
+
+    pub fn set_input_name(&mut self, input: i32, name: Option<String>) {
+        if let Some(entry) = slot_mut(&mut self.input_names, input) { *entry = name; }
+    }
+
+    pub fn output_name(&self, output: i32) -> Option<&str> {
+        slot(&self.output_names, output).and_then(|n| n.as_deref())
+    }
+
+    pub fn set_output_name(&mut self, output: i32, name: Option<String>) {
+        if let Some(entry) = slot_mut(&mut self.output_names, output) { *entry = name; }
+    }
+
+    /// Current ties, one `All` tie per output when video and audio come from the same input
+    pub fn ties(&self) -> Option<Vec<CrossPointTie>> {
+        if !self.ties_known { return None; }
+        Some(merge_ties(&self.video_ties, &self.audio_ties))
+    }
+
+    /// Replaces the cached ties with the input feeding each output, indexed from output 1
+    pub fn set_ties(&mut self, video: Vec<u8>, audio: Vec<u8>) {
+        self.video_ties = video;
+        self.audio_ties = audio;
+        self.ties_known = true;
+    }
+
+    pub fn invalidate_ties(&mut self) {
+        self.ties_known = false;
+    }
+
+    fn apply_tie(&mut self, tie: CrossPointTie) {
+        let output_count = self.video_ties.len();
+        if tie.output_channel < 1 || tie.output_channel as usize > output_count { return self.invalidate_ties(); }
+        let mut ties = merge_ties(&self.video_ties, &self.audio_ties);
+        ties.push(tie);
+        (self.video_ties, self.audio_ties) = split_ties(&ties, output_count);
+    }
+
+    /// Updates the model from a message the switcher sent, either a command's acknowledgement
+    /// or an unsolicited report of a front panel change. Returns false for messages that say
+    /// nothing about the cached state.
+    pub fn observe(&mut self, message: &str) -> bool {
+        let message = message.trim();
+        if let Some(tie) = parse_tie_report(message) {
+            self.apply_tie(tie);
+        } else if message.starts_with("Rpr") || message.starts_with("Qik") {
+            self.invalidate_ties();
+        } else if let Some(number) = message.strip_prefix("Spr").and_then(|n| n.parse().ok()) {
+            self.set_preset_name(number, None);
+        } else if let Some((number, name)) = parse_name_report(message, "Nmg") {
+            self.set_preset_name(number, Some(name));
+        } else if let Some((number, name)) = parse_name_report(message, "Nmi") {
+            self.set_input_name(number, Some(name));
+        } else if let Some((number, name)) = parse_name_report(message, "Nmo") {
+            self.set_output_name(number, Some(name));
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+/// The input feeding each output's video and audio, indexed from output 1 with 0 for nothing
+/// tied. RGB ties count as video, and later ties replace earlier ones on the same output.
+pub fn split_ties(ties: &[CrossPointTie], output_count: usize) -> (Vec<u8>, Vec<u8>) {
+    let mut video = vec![0; output_count];
+    let mut audio = vec![0; output_count];
+    for tie in ties {
+        let index = tie.output_channel as usize;
+        if index < 1 || index > output_count { continue; }
+        if tie.io_type != CrossPointIO::Aud { video[index - 1] = tie.input_channel; }
+        if matches!(tie.io_type, CrossPointIO::All | CrossPointIO::Aud) { audio[index - 1] = tie.input_channel; }
+    }
+    (video, audio)
+}
+
+/// Turns the input feeding each output's video and audio, indexed from output 1 with 0 for
+/// nothing tied, into ties. Outputs taking both from the same input get one `All` tie.
+pub fn merge_ties(video: &[u8], audio: &[u8]) -> Vec<CrossPointTie> {
+    let mut ties = Vec::new();
+    for (index, (&video, &audio)) in video.iter().zip(audio.iter()).enumerate() {
+        let output_channel = index as u8 + 1;
+        if video == audio {
+            if video > 0 { ties.push(CrossPointTie { input_channel: video, output_channel, io_type: CrossPointIO::All }); }
+            continue;
+        }
+        if video > 0 { ties.push(CrossPointTie { input_channel: video, output_channel, io_type: CrossPointIO::Vid }); }
+        if audio > 0 { ties.push(CrossPointTie { input_channel: audio, output_channel, io_type: CrossPointIO::Aud }); }
+    }
+    ties
+}
+
+/// Whether a message from the switcher reports a change of ties
+pub fn changes_routing(message: &str) -> bool {
+    let message = message.trim();
+    parse_tie_report(message).is_some() || message.starts_with("Rpr") || message.starts_with("Qik")
+}
+
+fn slot<T>(entries: &[T], number: i32) -> Option<&T> {
+    if number < 1 { return None; }
+    entries.get(number as usize - 1)
+}
+
+fn slot_mut<T>(entries: &mut [T], number: i32) -> Option<&mut T> {
+    if number < 1 { return None; }
+    entries.get_mut(number as usize - 1)
+}
+
+/// `Out02 In03 All`
+fn parse_tie_report(message: &str) -> Option<CrossPointTie> {
+    let mut parts = message.split_whitespace();
+    let output_channel = parts.next()?.strip_prefix("Out")?.parse().ok()?;
+    let input_channel = parts.next()?.strip_prefix("In")?.parse().ok()?;
+    let io_type = parts.next()?.parse().ok()?;
+    Some(CrossPointTie { input_channel, output_channel, io_type })
+}
+
+/// `Nmg02,SNES`
+fn parse_name_report(message: &str, prefix: &str) -> Option<(i32, String)> {
+    let (number, name) = message.strip_prefix(prefix)?.split_once(',')?;
+    Some((number.parse().ok()?, name.to_string()))
+}
@@ -0,0 +1,71 @@
+use std::{
+    sync::{Arc,Mutex,MutexGuard},
+    thread,
+    time::{Duration,Instant}
+};
+use crate::crosspoint::CrossPoint;
+
+const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// How often the state cache is read again in full, in case a change went unreported
+const RESYNC_INTERVAL: Duration = Duration::from_secs(300);
+
+pub fn lock(port: &Mutex<CrossPoint>) -> MutexGuard<'_, CrossPoint> {
+    port.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Watches the link to the CrossPoint. While it is up the transport gets its keep-alive;
+/// once a command fails with an I/O error the port is reopened with exponential backoff.
+/// Also applies unsolicited messages to the state cache and reads the state again
+/// periodically and after every reconnect.
+pub fn spawn(port: Arc<Mutex<CrossPoint>>) {
+    thread::spawn(move || {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut next_attempt = Instant::now();
+        let mut next_keep_alive = Instant::now() + KEEP_ALIVE_INTERVAL;
+        let mut next_resync = Instant::now() + RESYNC_INTERVAL;
+        loop {
+            thread::sleep(CHECK_INTERVAL);
+            let mut cp = lock(&port);
+
+            if cp.is_connected() {
+                backoff = INITIAL_BACKOFF;
+                if let Err(e) = cp.poll_unsolicited() {
+                    log::warn!("Lost CrossPoint on port {}: {}", cp.port_name(), e);
+                    continue;
+                }
+                if Instant::now() >= next_resync {
+                    next_resync = Instant::now() + RESYNC_INTERVAL;
+                    if let Err(e) = cp.refresh_state() {
+                        log::warn!("Failed to read CrossPoint state: {}", e);
+                    }
+                }
+                if Instant::now() >= next_keep_alive {
+                    next_keep_alive = Instant::now() + KEEP_ALIVE_INTERVAL;
+                    if let Err(e) = cp.keep_alive() {
+                        log::warn!("Lost CrossPoint on port {}: {}", cp.port_name(), e);
+                    }
+                }
+                continue;
+            }
+
+            if Instant::now() < next_attempt { continue; }
+            match cp.reconnect() {
+                Ok(_) => {
+                    log::info!("Reconnected to CrossPoint on port {}", cp.port_name());
+                    next_resync = Instant::now() + RESYNC_INTERVAL;
+                    if let Err(e) = cp.refresh_state() {
+                        log::warn!("Failed to read CrossPoint state: {}", e);
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Reconnecting to CrossPoint on port {} failed: {}. Retrying in {:?}", cp.port_name(), e, backoff);
+                    next_attempt = Instant::now() + backoff;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    });
+}
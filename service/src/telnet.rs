@@ -0,0 +1,223 @@
+use std::{
+    io::{Read,Write,Error,ErrorKind},
+    net::{TcpStream,ToSocketAddrs,SocketAddr,IpAddr},
+    time::{Duration,Instant}
+};
+use crate::transport::Transport;
+
+const TELNET_PORT: u16 = 23;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+/// The banner is finished once the device stays quiet this long
+const BANNER_QUIET: Duration = Duration::from_millis(500);
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Extron units drop idle connections after a few minutes
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+enum TelnetState {
+    Data,
+    Iac,
+    Negotiate(u8),
+    Subnegotiation,
+    SubnegotiationIac
+}
+
+/// SIS over Telnet for Ethernet equipped switchers and IP-Link adapters. Skips the
+/// copyright banner, answers the password prompt, refuses every Telnet option the
+/// device offers and probes the link with `Q` when it has been idle.
+pub struct TelnetTransport {
+    stream: TcpStream,
+    address: SocketAddr,
+    password: Option<String>,
+    timeout: Duration,
+    keep_alive: Duration,
+    last_activity: Instant,
+    state: TelnetState
+}
+
+impl TelnetTransport {
+    /// Connects to `host` or `host:port` and logs in. The link is probed once it has been
+    /// idle for `keep_alive`.
+    pub fn connect(address: &str, password: Option<&str>, keep_alive: Duration) -> Result<TelnetTransport, Error> {
+        let address = match with_default_port(address).to_socket_addrs()?.next() {
+            Some(a) => a,
+            None => return Err(Error::new(ErrorKind::NotFound, "Address did not resolve"))
+        };
+
+        let mut transport = TelnetTransport {
+            stream: TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?,
+            address,
+            password: password.map(String::from),
+            timeout: DEFAULT_TIMEOUT,
+            keep_alive,
+            last_activity: Instant::now(),
+            state: TelnetState::Data
+        };
+        transport.login()?;
+        Ok(transport)
+    }
+
+    fn login(&mut self) -> Result<(), Error> {
+        self.stream.set_nodelay(true)?;
+        self.stream.set_read_timeout(Some(BANNER_QUIET))?;
+
+        let deadline = Instant::now() + LOGIN_TIMEOUT;
+        let mut password_sent = false;
+        let mut received = String::new();
+        let mut buffer = [0_u8; 256];
+        while Instant::now() < deadline {
+            let count = match self.read(&mut buffer) {
+                Ok(0) => return Err(Error::new(ErrorKind::ConnectionAborted, "Device closed the connection during login")),
+                Ok(c) => c,
+                Err(e) if is_timeout(&e) => break,
+                Err(e) => return Err(e)
+            };
+            received.push_str(&String::from_utf8_lossy(&buffer[..count]));
+
+            if received.trim_end().ends_with("Password:") {
+                if password_sent {
+                    return Err(Error::new(ErrorKind::PermissionDenied, "Device rejected the password"));
+                }
+                let password = match self.password.as_ref() {
+                    Some(p) => p.clone(),
+                    None => return Err(Error::new(ErrorKind::PermissionDenied, "Device requires a password"))
+                };
+                self.write_all(password.as_bytes())?;
+                self.write_all(b"\r\n")?;
+                password_sent = true;
+                received.clear();
+            } else if password_sent && received.contains("Login") && received.ends_with('\n') {
+                break;
+            }
+        }
+
+        self.stream.set_read_timeout(Some(self.timeout))?;
+        self.last_activity = Instant::now();
+        Ok(())
+    }
+
+    /// Strips Telnet commands out of `raw` into `buf`, queueing refusals for any negotiation
+    fn filter(&mut self, raw: &[u8], buf: &mut [u8], replies: &mut Vec<u8>) -> usize {
+        let mut count = 0;
+        for &b in raw {
+            self.state = match (&self.state, b) {
+                (TelnetState::Data, IAC) => TelnetState::Iac,
+                (TelnetState::Data, _) => {
+                    buf[count] = b;
+                    count += 1;
+                    TelnetState::Data
+                }
+                (TelnetState::Iac, IAC) => {
+                    buf[count] = b;
+                    count += 1;
+                    TelnetState::Data
+                }
+                (TelnetState::Iac, WILL | WONT | DO | DONT) => TelnetState::Negotiate(b),
+                (TelnetState::Iac, SB) => TelnetState::Subnegotiation,
+                (TelnetState::Iac, _) => TelnetState::Data,
+                (TelnetState::Negotiate(command), option) => {
+                    match *command {
+                        WILL => replies.extend_from_slice(&[IAC, DONT, option]),
+                        DO => replies.extend_from_slice(&[IAC, WONT, option]),
+                        _ => ()
+                    }
+                    TelnetState::Data
+                }
+                (TelnetState::Subnegotiation, IAC) => TelnetState::SubnegotiationIac,
+                (TelnetState::Subnegotiation, _) => TelnetState::Subnegotiation,
+                (TelnetState::SubnegotiationIac, SE) => TelnetState::Data,
+                (TelnetState::SubnegotiationIac, _) => TelnetState::Subnegotiation
+            };
+        }
+        count
+    }
+}
+
+/// Adds the Telnet port to an address given without one. IPv6 addresses may be written
+/// bare or in brackets, and take a port only in brackets, as in `[fe80::1]:2323`.
+pub fn with_default_port(address: &str) -> String {
+    if address.parse::<SocketAddr>().is_ok() { return address.to_string(); }
+    let unbracketed = address.strip_prefix('[').and_then(|a| a.strip_suffix(']')).unwrap_or(address);
+    if let Ok(ip) = unbracketed.parse::<IpAddr>() { return SocketAddr::new(ip, TELNET_PORT).to_string(); }
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') && port.parse::<u16>().is_ok() => address.to_string(),
+        _ => format!("{}:{}", address, TELNET_PORT)
+    }
+}
+
+fn is_timeout(e: &Error) -> bool {
+    e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut
+}
+
+impl Read for TelnetTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut raw = vec![0_u8; buf.len()];
+        loop {
+            let read_count = self.stream.read(&mut raw)?;
+            if read_count == 0 { return Ok(0); }
+            self.last_activity = Instant::now();
+
+            let mut replies = Vec::new();
+            let count = self.filter(&raw[..read_count], buf, &mut replies);
+            if !replies.is_empty() { self.stream.write_all(&replies)?; }
+            if count > 0 { return Ok(count); }
+        }
+    }
+}
+
+impl Write for TelnetTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.last_activity = Instant::now();
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for TelnetTransport {
+    fn timeout(&self) -> Duration { self.timeout }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        self.stream = TcpStream::connect_timeout(&self.address, CONNECT_TIMEOUT)?;
+        self.state = TelnetState::Data;
+        self.login()
+    }
+
+    /// Sends a firmware query once the link has been idle for the keep-alive interval,
+    /// logging in again if the device has dropped the connection
+    fn keep_alive(&mut self) -> Result<(), Error> {
+        if self.last_activity.elapsed() < self.keep_alive { return Ok(()); }
+
+        let probe = self.write_all(b"Q").and_then(|_| {
+            let mut reply = [0_u8; 64];
+            loop {
+                match self.read(&mut reply)? {
+                    0 => return Err(Error::new(ErrorKind::ConnectionAborted, "Device closed the connection")),
+                    count if reply[..count].contains(&b'\n') => return Ok(()),
+                    _ => continue
+                }
+            }
+        });
+
+        match probe {
+            Ok(_) => Ok(()),
+            Err(_) => self.reconnect()
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    io::{Write,Read,LineWriter,Error,ErrorKind},
+    fs::{self,File,OpenOptions},
+    time::{Duration,SystemTime,UNIX_EPOCH},
+    collections::VecDeque
+};
+use crate::transport::Transport;
+
+/// Transport wrapper that appends every byte sent and received to a transcript file.
+///
+/// Each line holds a unix timestamp in milliseconds, the direction and the bytes in hex,
+/// followed by a readable copy after `;`:
+/// `1697040000123 TX 49 0D 0A ; I\r\n`
+pub struct TranscriptRecorder {
+    port: Box<dyn Transport>,
+    log: LineWriter<File>
+}
+
+impl TranscriptRecorder {
+    pub fn new(port: Box<dyn Transport>, port_name: &str, path: &str) -> Result<TranscriptRecorder, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut log = LineWriter::new(file);
+        writeln!(log, "# CrossPoint transcript {}", port_name)?;
+        Ok(TranscriptRecorder { port, log })
+    }
+
+    fn record(&mut self, direction: TranscriptDirection, bytes: &[u8]) {
+        if bytes.is_empty() { return; }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        _ = writeln!(self.log, "{} {} {} ; {}", timestamp, direction.as_str(), hex.join(" "), bytes.escape_ascii());
+    }
+}
+
+impl Write for TranscriptRecorder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let count = self.port.write(buf)?;
+        self.record(TranscriptDirection::Tx, &buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Read for TranscriptRecorder {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.port.read(buf)?;
+        self.record(TranscriptDirection::Rx, &buf[..count]);
+        Ok(count)
+    }
+}
+
+impl Transport for TranscriptRecorder {
+    fn timeout(&self) -> Duration { self.port.timeout() }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> { self.port.set_timeout(timeout) }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        _ = writeln!(self.log, "# Reconnecting");
+        self.port.reconnect()
+    }
+
+    fn keep_alive(&mut self) -> Result<(), Error> { self.port.keep_alive() }
+}
+
+
+#[derive(Clone, Copy, PartialEq)]
+enum TranscriptDirection {
+    Tx,
+    Rx
+}
+
+impl TranscriptDirection {
+    fn as_str(self) -> &'static str {
+        match self {
+            TranscriptDirection::Tx => "TX",
+            TranscriptDirection::Rx => "RX"
+        }
+    }
+}
+
+/// Plays a recorded transcript back as a transport. Writes must match the recorded TX
+/// bytes in order; each one releases the RX bytes that followed it in the recording.
+pub struct TranscriptReplay {
+    entries: VecDeque<(TranscriptDirection, Vec<u8>)>,
+    expected: Vec<u8>,
+    buffer: Vec<u8>
+}
+
+impl TranscriptReplay {
+    pub fn load(path: &str) -> Result<TranscriptReplay, Error> {
+        let transcript = fs::read_to_string(path)?;
+        TranscriptReplay::parse(&transcript)
+    }
+
+    pub fn parse(transcript: &str) -> Result<TranscriptReplay, Error> {
+        let mut entries = VecDeque::new();
+        for (line_number, line) in transcript.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            let invalid = || Error::new(ErrorKind::InvalidData, format!("Invalid transcript line {}", line_number + 1));
+            let mut fields = line.split_whitespace();
+            _ = fields.next();
+            let direction = match fields.next() {
+                Some("TX") => TranscriptDirection::Tx,
+                Some("RX") => TranscriptDirection::Rx,
+                _ => return Err(invalid())
+            };
+            let mut bytes = Vec::new();
+            for hex in fields {
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) => bytes.push(b),
+                    Err(_) => return Err(invalid())
+                }
+            }
+            entries.push_back((direction, bytes));
+        }
+
+        Ok(TranscriptReplay { entries, expected: Vec::new(), buffer: Vec::new() })
+    }
+
+    /// True once every recorded exchange has been played back
+    pub fn is_finished(&self) -> bool {
+        self.entries.is_empty() && self.expected.is_empty()
+    }
+
+    fn release_replies(&mut self) {
+        while let Some((TranscriptDirection::Rx, _)) = self.entries.front() {
+            let (_, bytes) = self.entries.pop_front().unwrap();
+            self.buffer.extend_from_slice(&bytes);
+        }
+    }
+}
+
+impl Write for TranscriptReplay {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            if self.expected.is_empty() {
+                self.release_replies();
+                match self.entries.pop_front() {
+                    Some((_, bytes)) => self.expected = bytes,
+                    None => return Err(Error::new(ErrorKind::UnexpectedEof, "Transcript has no more commands"))
+                }
+                continue;
+            }
+
+            let count = self.expected.len().min(buf.len() - written);
+            if self.expected[..count] != buf[written..written + count] {
+                return Err(Error::new(ErrorKind::InvalidData, format!("Transcript expected \"{}\" but got \"{}\"",
+                    self.expected.escape_ascii(), buf[written..].escape_ascii())));
+            }
+            self.expected.drain(..count);
+            written += count;
+        }
+
+        if self.expected.is_empty() { self.release_replies(); }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for TranscriptReplay {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.buffer.is_empty() {
+            return Err(Error::new(ErrorKind::TimedOut, "No recorded reply"));
+        }
+
+        let read_count = buf.len().min(self.buffer.len());
+        for (dest, b) in buf.iter_mut().zip(self.buffer.drain(0..read_count)) {
+            *dest = b;
+        }
+        Ok(read_count)
+    }
+}
+
+impl Transport for TranscriptReplay {
+    fn timeout(&self) -> Duration { Duration::ZERO }
+
+    fn set_timeout(&mut self, _: Duration) -> Result<(), Error> { Ok(()) }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        self.buffer.clear();
+        Ok(())
+    }
+}
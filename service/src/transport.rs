@@ -0,0 +1,158 @@
+use std::{
+    io::{Read,Write,Error},
+    time::Duration
+};
+use serialport::{SerialPort,DataBits,Parity,StopBits,FlowControl};
+use crate::{
+    vextron::VirtualCrosspoint,
+    transcript::{TranscriptRecorder,TranscriptReplay},
+    telnet::{TelnetTransport,DEFAULT_KEEP_ALIVE}
+};
+
+const REPLAY_PREFIX: &str = "replay:";
+const TCP_PREFIX: &str = "tcp://";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const DEFAULT_BAUD_RATE: u32 = 9600;
+
+/// Rates a CrossPoint can be set to from the front panel, in the order auto-baud tries them
+pub const SUPPORTED_BAUD_RATES: [u32; 3] = [9600, 19200, 38400];
+
+/// Byte stream to a CrossPoint. Implemented for serial ports, Telnet connections,
+/// the simulator and transcript replays.
+pub trait Transport: Read + Write + Send {
+    /// How long a read waits for the device before giving up
+    fn timeout(&self) -> Duration;
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error>;
+
+    /// Drops the underlying connection and opens it again
+    fn reconnect(&mut self) -> Result<(), Error>;
+
+    /// Called periodically so links that time out when idle can be kept open
+    fn keep_alive(&mut self) -> Result<(), Error> { Ok(()) }
+}
+
+/// How the service reaches the CrossPoint
+#[derive(Clone, Default)]
+pub struct ConnectionSettings {
+    /// Serial device, `tcp://host[:port]`, `virtual` or `replay:<file>`
+    pub port_name: String,
+    /// File to record the session to
+    pub transcript: Option<String>,
+    /// Password for switchers reached over Telnet
+    pub password: Option<String>,
+    /// How long a Telnet link may sit idle before it is probed, if not the default
+    pub keep_alive: Option<Duration>,
+    /// Line settings used when the port is a serial device
+    pub serial: SerialSettings
+}
+
+impl ConnectionSettings {
+    pub fn new(port_name: &str) -> ConnectionSettings {
+        ConnectionSettings { port_name: String::from(port_name), ..Default::default() }
+    }
+}
+
+/// Serial line settings. Defaults to the CrossPoint factory setting of 9600 8N1 without flow control.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SerialSettings {
+    /// `None` tries each of `SUPPORTED_BAUD_RATES` until the switcher answers
+    pub baud_rate: Option<u32>,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    pub timeout: Duration
+}
+
+impl Default for SerialSettings {
+    fn default() -> SerialSettings {
+        SerialSettings {
+            baud_rate: Some(DEFAULT_BAUD_RATE),
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            timeout: DEFAULT_TIMEOUT
+        }
+    }
+}
+
+/// True if `port_name` names a serial device rather than one of the other transports
+pub fn is_serial(port_name: &str) -> bool {
+    port_name != "virtual" && !port_name.starts_with(REPLAY_PREFIX) && !port_name.starts_with(TCP_PREFIX)
+}
+
+/// Opens the transport named by `settings.port_name`, wrapped in a recorder if a transcript is set
+pub fn open(settings: &ConnectionSettings) -> Result<Box<dyn Transport>, Error> {
+    let port_name = settings.port_name.as_str();
+    let mut port: Box<dyn Transport> = if port_name == "virtual" {
+        Box::new(VirtualCrosspoint::load_or_new())
+    } else if let Some(path) = port_name.strip_prefix(REPLAY_PREFIX) {
+        Box::new(TranscriptReplay::load(path)?)
+    } else if let Some(address) = port_name.strip_prefix(TCP_PREFIX) {
+        Box::new(TelnetTransport::connect(address, settings.password.as_deref(), settings.keep_alive.unwrap_or(DEFAULT_KEEP_ALIVE))?)
+    } else {
+        Box::new(SerialTransport::open(port_name, settings.serial)?)
+    };
+
+    if let Some(path) = settings.transcript.as_ref() {
+        port = Box::new(TranscriptRecorder::new(port, port_name, path)?);
+    }
+
+    Ok(port)
+}
+
+pub struct SerialTransport {
+    port: Box<dyn SerialPort>,
+    path: String,
+    settings: SerialSettings
+}
+
+impl SerialTransport {
+    /// Opens `path` with the given line settings. An unset baud rate opens at 9600.
+    pub fn open(path: &str, settings: SerialSettings) -> Result<SerialTransport, Error> {
+        Ok(SerialTransport { port: SerialTransport::open_port(path, &settings)?, path: String::from(path), settings })
+    }
+
+    fn open_port(path: &str, settings: &SerialSettings) -> Result<Box<dyn SerialPort>, Error> {
+        Ok(serialport::new(path, settings.baud_rate.unwrap_or(DEFAULT_BAUD_RATE))
+            .data_bits(settings.data_bits)
+            .flow_control(settings.flow_control)
+            .parity(settings.parity)
+            .stop_bits(settings.stop_bits)
+            .timeout(settings.timeout)
+            .open()?)
+    }
+}
+
+impl Read for SerialTransport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for SerialTransport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Transport for SerialTransport {
+    fn timeout(&self) -> Duration { self.settings.timeout }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.port.set_timeout(timeout)?;
+        self.settings.timeout = timeout;
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Result<(), Error> {
+        self.port = SerialTransport::open_port(&self.path, &self.settings)?;
+        Ok(())
+    }
+}
@@ -2,58 +2,80 @@ use std::{
    io::{Write,Read},
    fs,
    time::Duration,
-   cmp::min_by
+   sync::{Arc,Mutex,MutexGuard}
 };
 
 use json::JsonValue;
-use serialport::{SerialPort,FlowControl,Parity,DataBits,StopBits};
 use crate::{
-    crosspoint::{CrossPointPreset,CrossPointTie,CrossPointIO},
-    config::ConfigurationError
+    crosspoint::{CrossPointPreset,CrossPointTie,CrossPointIO,MAX_NAME_LENGTH,DEFAULT_PRESET_COUNT,MAX_PRESET_COUNT},
+    config::ConfigurationError,
+    transport::Transport
 };
 
 const UNASSIGNED: &str = "[unassigned]";
-
+const FIRMWARE_VERSION: &str = "1.00";
+const VIRTUAL_TIMEOUT: Duration = Duration::from_secs(5);
+const INVALID_INPUT: &str = "E01";
+const INVALID_COMMAND: &str = "E10";
+const INVALID_PRESET: &str = "E11";
+const INVALID_OUTPUT: &str = "E12";
+const INVALID_VALUE: &str = "E13";
+const MAX_CHANNELS: usize = 16;
+const MAX_VOLUME: usize = 64;
+const MAX_GAIN: usize = 24;
+const MAX_ATTENUATION: usize = 18;
+
+/// Simulated CrossPoint. Clones share the same device state, so a test can keep a
+/// handle to inspect the switcher after handing another one to the service.
+#[derive(Clone)]
 pub struct VirtualCrosspoint {
+    state: Arc<Mutex<VirtualState>>
+}
+
+struct VirtualState {
     buffer: Vec<u8>,
+    timeout: Duration,
+    online: bool,
     in_channels: usize,
     out_channels: usize,
     audio: bool,
+    volumes: [usize;MAX_CHANNELS],
+    gains: [i32;MAX_CHANNELS],
+    video_mutes: [bool;MAX_CHANNELS],
+    audio_mutes: [bool;MAX_CHANNELS],
+    lockout: usize,
+    input_names: Vec<String>,
+    output_names: Vec<String>,
+    signal_support: bool,
+    signals: [bool;MAX_CHANNELS],
     current: CrossPointPreset,
-    presets: [Option<CrossPointPreset>;32]
+    presets: Vec<Option<CrossPointPreset>>
 }
 
 impl VirtualCrosspoint {
-    pub fn load_or_new() -> Box<dyn SerialPort> {
-
-        let newone: VirtualCrosspoint = match fs::read_to_string("virtual.json") {
-            Ok(mut f) => {
-                match VirtualCrosspoint::parse_json(&f) {
-                    Ok(p) => p,
-                    Err(_) => VirtualCrosspoint::new()
-                }
-            }
+    pub fn load_or_new() -> VirtualCrosspoint {
+        match fs::read_to_string("virtual.json") {
+            Ok(f) => VirtualCrosspoint::parse_json(&f).unwrap_or_default(),
             Err(_) => VirtualCrosspoint::new()
-        };
-
-        Box::new(newone)
+        }
     }
 
-    fn parse_json(data: &str) -> Result<VirtualCrosspoint, ConfigurationError> {
-        let jconfig = match json::parse(&data) {
+    pub fn parse_json(data: &str) -> Result<VirtualCrosspoint, ConfigurationError> {
+        let jconfig = match json::parse(data) {
             Ok(j) => j,
             Err(_) => return Err(ConfigurationError::new("Invalid JSON"))
         };
 
         let in_channels = jconfig["input_channels"].as_usize().unwrap_or(16);
-        if in_channels > 16  { return Err(ConfigurationError::new("Invalid input port count")); }
+        if in_channels > MAX_CHANNELS  { return Err(ConfigurationError::new("Invalid input port count")); }
 
         let out_channels = jconfig["output_channels"].as_usize().unwrap_or(16);
-        if out_channels > 16  { return Err(ConfigurationError::new("Invalid output port count")); }
+        if out_channels > MAX_CHANNELS  { return Err(ConfigurationError::new("Invalid output port count")); }
 
         let audio = jconfig["audio_support"].as_bool().unwrap_or(false);
+        let signal_support = jconfig["signal_support"].as_bool().unwrap_or(true);
 
-        let ties = Self::get_ties(&jconfig["current_ties"]).unwrap_or(Vec::new());
+        let ties = Self::get_ties(&jconfig["current_ties"]).unwrap_or_default();
 
         let current = CrossPointPreset {
             number: 0,
@@ -61,26 +83,26 @@ impl VirtualCrosspoint {
             ties
         };
 
-        let mut presets:[Option<CrossPointPreset>;32] = [
-            None, None, None, None, None, None, None, None,
-            None, None, None, None, None, None, None, None,
-            None, None, None, None, None, None, None, None,
-            None, None, None, None, None, None, None, None
-        ];
+        let preset_count = jconfig["preset_count"].as_usize().unwrap_or(DEFAULT_PRESET_COUNT as usize);
+        if preset_count > MAX_PRESET_COUNT as usize { return Err(ConfigurationError::new("Invalid preset count")); }
+        let mut presets = vec![None; preset_count];
 
         for preset in jconfig["presets"].members() {
             let number = match preset["number"].as_usize() { Some(n) => n, None => continue };
+            if number < 1 || number > presets.len() { continue; }
             let name = preset["name"].as_str().unwrap_or(UNASSIGNED);
-            let ties = Self::get_ties(&preset["ties"]).unwrap_or(Vec::new());
-            if ties.len() > 0 {
-                presets[number] = Some(CrossPointPreset { number: number as i32, name: String::from(name), ties });
+            let ties = Self::get_ties(&preset["ties"]).unwrap_or_default();
+            if !ties.is_empty() {
+                presets[number - 1] = Some(CrossPointPreset { number: number as i32, name: String::from(name), ties });
             }
         }
 
-        Ok(VirtualCrosspoint {
-            in_channels, out_channels, audio, buffer: Vec::new(), current,
-            presets
-        })
+        Ok(VirtualCrosspoint::from_state(VirtualState {
+            in_channels, out_channels, audio, buffer: Vec::new(), timeout: VIRTUAL_TIMEOUT, online: true, current,
+            volumes: [MAX_VOLUME;MAX_CHANNELS], gains: [0;MAX_CHANNELS], video_mutes: [false;MAX_CHANNELS],
+            audio_mutes: [false;MAX_CHANNELS], lockout: 0, input_names: default_names("Input"),
+            output_names: default_names("Output"), signal_support, signals: [false;MAX_CHANNELS], presets
+        }))
     }
 
     fn get_ties(tiesobj: &JsonValue) -> Result<Vec<CrossPointTie>, ConfigurationError> {
@@ -99,193 +121,553 @@ impl VirtualCrosspoint {
         Ok(ties)
     }
 
-    fn new() -> VirtualCrosspoint {
-        VirtualCrosspoint {
+    pub fn new() -> VirtualCrosspoint {
+        VirtualCrosspoint::from_state(VirtualState {
             buffer: Vec::new(),
+            timeout: VIRTUAL_TIMEOUT,
+            online: true,
             in_channels: 12,
             out_channels: 8,
             audio: true,
+            volumes: [MAX_VOLUME;MAX_CHANNELS],
+            gains: [0;MAX_CHANNELS],
+            video_mutes: [false;MAX_CHANNELS],
+            audio_mutes: [false;MAX_CHANNELS],
+            lockout: 0,
+            input_names: default_names("Input"),
+            output_names: default_names("Output"),
+            signal_support: true,
+            signals: [false;MAX_CHANNELS],
             current: CrossPointPreset {
                 number: 0,
                 name: String::from("Current"),
                 ties: Vec::new(),
             },
-            presets: [
-                None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None,
-                None, None, None, None, None, None, None, None
-            ]
+            presets: vec![None; DEFAULT_PRESET_COUNT as usize]
+        })
+    }
+
+    fn from_state(state: VirtualState) -> VirtualCrosspoint {
+        VirtualCrosspoint { state: Arc::new(Mutex::new(state)) }
+    }
+
+    fn state(&self) -> MutexGuard<'_, VirtualState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Ties currently applied on the simulated switcher
+    pub fn current_ties(&self) -> Vec<CrossPointTie> {
+        self.state().current.ties.clone()
+    }
+
+    /// Simulates unplugging the switcher. While offline every read, write and reconnect fails.
+    pub fn set_online(&self, online: bool) {
+        let mut state = self.state();
+        state.online = online;
+        state.buffer.clear();
+    }
+
+    /// Changes the sizes reported by the info command, as if another model had been plugged in
+    pub fn set_channel_counts(&self, in_channels: usize, out_channels: usize) {
+        let mut state = self.state();
+        state.in_channels = in_channels;
+        state.out_channels = out_channels;
+    }
+
+    /// Volume step of an output
+    pub fn volume(&self, output: usize) -> usize {
+        self.state().volumes[output - 1]
+    }
+
+    /// Input level in dB, negative when attenuated
+    pub fn gain(&self, input: usize) -> i32 {
+        self.state().gains[input - 1]
+    }
+
+    pub fn video_muted(&self, output: usize) -> bool {
+        self.state().video_mutes[output - 1]
+    }
+
+    pub fn audio_muted(&self, output: usize) -> bool {
+        self.state().audio_mutes[output - 1]
+    }
+
+    /// Executive mode set on the front panel
+    pub fn lockout(&self) -> usize {
+        self.state().lockout
+    }
+
+    pub fn input_name(&self, input: usize) -> String {
+        self.state().input_names[input - 1].clone()
+    }
+
+    pub fn output_name(&self, output: usize) -> String {
+        self.state().output_names[output - 1].clone()
+    }
+
+    /// Simulates a tie made on the front panel, which the switcher reports unprompted
+    pub fn front_panel_tie(&self, tie: CrossPointTie) {
+        let mut state = self.state();
+        if state.validate_tie(&tie).is_some() { return; }
+        apply_tie(&mut state.current.ties, tie);
+        state.report_tie(tie);
+    }
+
+    /// Simulates a source on `input` being powered on or off
+    pub fn set_signal(&self, input: usize, present: bool) {
+        self.state().signals[input - 1] = present;
+    }
+
+    /// Stored preset, or None if the slot is unassigned
+    pub fn preset(&self, preset_number: usize) -> Option<CrossPointPreset> {
+        match preset_number {
+            0 => None,
+            n => self.state().presets.get(n - 1).cloned().flatten()
         }
     }
+}
 
+impl Default for VirtualCrosspoint {
+    fn default() -> Self {
+        VirtualCrosspoint::new()
+    }
+}
+
+impl VirtualState {
     fn evaluate_command(&mut self, buf: &[u8]) {
-        match buf[0] {
-            0x49 => self.info(),
-            0x1B => self.escape_commands(&buf[1..]),
-            _ => todo!()
+        let buf = trim_line_end(buf);
+        match buf.first() {
+            Some(0x49) => self.info(),
+            Some(0x51) => self.respond(FIRMWARE_VERSION),
+            Some(b'X') if buf.len() == 1 => self.respond(&self.lockout.to_string()),
+            Some(0x1B) => self.escape_commands(&buf[1..]),
+            Some(b) if b.is_ascii_digit() => self.numeric_commands(buf),
+            _ => self.respond(INVALID_COMMAND)
         }
     }
 
+    fn respond(&mut self, response: &str) {
+        self.buffer.extend_from_slice(response.as_bytes());
+        self.buffer.extend_from_slice(b"\r\n");
+    }
+
     fn info(&mut self) {
-        let response = format!("V{:0>2}X{:0>2} A{:0>2}X{:0>2}\r\n", self.in_channels, self.out_channels,
+        let response = format!("V{:0>2}X{:0>2} A{:0>2}X{:0>2}", self.in_channels, self.out_channels,
             if self.audio { self.in_channels } else { 0 }, if self.audio { self.out_channels } else { 0 });
-        let bytes = response.bytes();
-        for b in bytes { self.buffer.push(b) }
+        self.respond(&response);
     }
 
     fn escape_commands(&mut self, buf: &[u8]) {
-        let mut byte = *buf.get(0).unwrap_or(&0);
-        let mut x9: usize = 0;
-        if byte.is_ascii_digit() { x9 = (byte - 0x30) as usize; }
-        byte = *buf.get(1).unwrap_or(&0);
-        if byte.is_ascii_digit() { x9 = (x9 * 10) + (byte - 0x30) as usize; }
-
-        if x9 > 0 {
-            let c1 = buf.get(2);
-            let c2 = buf.get(3);
-            if c1.is_some() && *c1.unwrap() == 'N' as u8 && c2.is_some() && *c2.unwrap() == 'G' as u8 {
-                self.preset_name(x9);
-            }
+        if buf.first() == Some(&b'+') {
+            return self.write_preset(&buf[1..]);
+        }
+
+        let (x9, rest) = read_number(buf);
+        match (x9, rest) {
+            (Some(n), b"NG") if n > 0 => self.preset_name(n),
+            (Some(n), b"NI" | b"NO") => self.channel_name(n, rest[1]),
+            (Some(n), [b',', name @ .., b'N', command @ (b'I' | b'O')]) => self.set_channel_name(n, name, *command),
+            (Some(n), [b',', name @ .., b'N', b'G']) => self.set_preset_name(n, name),
+            (Some(n), [b'*', rest @ ..]) => match read_number(rest) {
+                (Some(output), [b'*', rest @ ..]) => match read_number(rest) {
+                    (Some(plane), b"VC") => self.preset_ties(n, output, plane),
+                    _ => self.respond(INVALID_COMMAND)
+                },
+                _ => self.respond(INVALID_COMMAND)
+            },
+            _ => self.respond(INVALID_COMMAND)
         }
     }
 
-    fn preset_name(&mut self, preset_number: usize) {
-        let preset = &self.presets[preset_number - 1];
-         if preset.is_some() {
-            let preset = preset.as_ref().unwrap();
-            let name = &preset.name;
-            for c in name.bytes() {
-                self.buffer.push(c);
+    /// `Esc nn*out*1VC` lists the inputs preset nn ties to the video of 16 outputs starting at
+    /// `out`, and `*2VC` to their audio. Preset 0 is the current configuration.
+    fn preset_ties(&mut self, preset_number: usize, first_output: usize, plane: usize) {
+        let ties = match preset_number {
+            0 => self.current.ties.clone(),
+            n => match self.presets.get(n - 1) {
+                Some(Some(preset)) => preset.ties.clone(),
+                _ => return self.respond(INVALID_PRESET)
             }
+        };
+        if plane == 2 && !self.audio { return self.respond(INVALID_COMMAND); }
+        if plane != 1 && plane != 2 { return self.respond(INVALID_VALUE); }
+        if first_output < 1 || first_output > self.out_channels { return self.respond(INVALID_OUTPUT); }
+
+        let last_output = self.out_channels.min(first_output + 15);
+        let inputs: Vec<String> = (first_output..=last_output)
+            .map(|output| format!("{:0>2}", tied_input(&ties, output, plane == 2)))
+            .collect();
+        self.respond(&inputs.join(" "));
+    }
+
+    /// `Esc nNI` reads an input name and `Esc nNO` an output name
+    fn channel_name(&mut self, channel: usize, command: u8) {
+        let name = match (command, channel) {
+            (b'I', n) if n >= 1 && n <= self.in_channels => self.input_names[n - 1].clone(),
+            (b'I', _) => return self.respond(INVALID_INPUT),
+            (_, n) if n >= 1 && n <= self.out_channels => self.output_names[n - 1].clone(),
+            _ => return self.respond(INVALID_OUTPUT)
+        };
+        self.respond(&name);
+    }
+
+    /// `Esc n,nameNI` and `Esc n,nameNO` store a name
+    fn set_channel_name(&mut self, channel: usize, name: &[u8], command: u8) {
+        let name = String::from_utf8_lossy(name).to_string();
+        if name.is_empty() || name.len() > MAX_NAME_LENGTH { return self.respond(INVALID_VALUE); }
+
+        if command == b'I' {
+            if channel < 1 || channel > self.in_channels { return self.respond(INVALID_INPUT); }
+            self.input_names[channel - 1] = name.clone();
+            self.respond(&format!("Nmi{:0>2},{}", channel, name));
         } else {
-            for c in UNASSIGNED.bytes() {
-                self.buffer.push(c);
-            }
+            if channel < 1 || channel > self.out_channels { return self.respond(INVALID_OUTPUT); }
+            self.output_names[channel - 1] = name.clone();
+            self.respond(&format!("Nmo{:0>2},{}", channel, name));
         }
     }
-}
 
-impl Write for VirtualCrosspoint {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.evaluate_command(&buf);
-        Ok(buf.len())
+    /// `nn,` saves and `nn.` recalls a global preset, `in*out!` ties, `n*vvV`, `n*ddG` and
+    /// `n*ddA` set audio levels and `nV`/`nG` read them. `n*1B` and `n*1Z` mute an output's
+    /// video and audio, `1*B` and `1*Z` every output, and `nB`/`nZ` read the mute. `nX` sets the
+    /// executive mode, `0LS` reads signal presence and `n%`/`n$` read the input tied to an output's
+    /// video and audio.
+    fn numeric_commands(&mut self, buf: &[u8]) {
+        let (number, rest) = read_number(buf);
+        let number = number.unwrap_or_default();
+        match rest.first() {
+            Some(b',') => self.save_preset(number),
+            Some(b'.') => self.recall_preset(number),
+            Some(b'V' | b'G') if rest.len() == 1 => self.read_level(number, rest[0]),
+            Some(b'B' | b'Z') if rest.len() == 1 => self.read_mute(number, rest[0]),
+            Some(b'X') if rest.len() == 1 => self.set_lockout(number),
+            Some(b'%' | b'$') if rest.len() == 1 => self.read_tie(number, rest[0]),
+            Some(b'L') if rest == b"LS" && number == 0 => self.signal_presence(),
+            Some(b'*') if matches!(&rest[1..], b"B" | b"Z") => self.global_mute(number, rest[1]),
+            Some(b'*') if matches!(rest.last(), Some(b'B' | b'Z')) => match read_number(&rest[1..]) {
+                (Some(muted), [command]) => self.mute(number, muted, *command),
+                _ => self.respond(INVALID_COMMAND)
+            },
+            Some(b'*') if matches!(rest.last(), Some(b'V' | b'G' | b'A')) => match read_number(&rest[1..]) {
+                (Some(level), [command]) => self.set_level(number, level, *command),
+                _ => self.respond(INVALID_COMMAND)
+            },
+            Some(b'*') => match parse_ties(buf) {
+                Some(ties) if ties.len() == 1 => self.tie(ties[0]),
+                _ => self.respond(INVALID_COMMAND)
+            },
+            _ => self.respond(INVALID_COMMAND)
+        }
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
+    fn preset_name(&mut self, preset_number: usize) {
+        let name = match self.presets.get(preset_number - 1) {
+            Some(Some(preset)) => preset.name.clone(),
+            Some(None) => String::from(UNASSIGNED),
+            None => return self.respond(INVALID_PRESET)
+        };
+        self.respond(&name);
     }
-}
 
-impl Read for VirtualCrosspoint {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let read_count = min_by(buf.len(), self.buffer.len(), |b, q| b.cmp(q));
-        let mut read_range = self.buffer.drain(0..read_count);
-        for i in 0..read_count {
-            buf[i] = read_range.next().unwrap();
+    /// `Esc nn,nameNG` names a stored preset
+    fn set_preset_name(&mut self, preset_number: usize, name: &[u8]) {
+        let name = String::from_utf8_lossy(name).to_string();
+        if name.is_empty() || name.len() > MAX_NAME_LENGTH { return self.respond(INVALID_VALUE); }
+
+        match self.presets.get_mut(preset_number.wrapping_sub(1)) {
+            Some(Some(preset)) => preset.name = name.clone(),
+            _ => return self.respond(INVALID_PRESET)
         }
-        Ok(read_count)
+        self.respond(&format!("Nmg{:0>2},{}", preset_number, name));
     }
-}
 
-impl SerialPort for VirtualCrosspoint {
-    fn name(&self) -> Option<String> {
-        Some(String::from("dummy"))
-    }
+    fn save_preset(&mut self, preset_number: usize) {
+        if preset_number < 1 || preset_number > self.presets.len() {
+            return self.respond(INVALID_PRESET);
+        }
 
-    fn baud_rate(&self) -> serialport::Result<u32> {
-        Ok(9600)
+        let name = match &self.presets[preset_number - 1] {
+            Some(p) => p.name.clone(),
+            None => format!("Preset {:0>2}", preset_number)
+        };
+        self.presets[preset_number - 1] = Some(CrossPointPreset {
+            number: preset_number as i32,
+            name,
+            ties: self.current.ties.clone()
+        });
+        self.respond(&format!("Spr{:0>2}", preset_number));
     }
 
-    fn data_bits(&self) -> serialport::Result<DataBits> {
-        Ok(DataBits::Eight)
-    }
+    fn recall_preset(&mut self, preset_number: usize) {
+        if preset_number < 1 || preset_number > self.presets.len() {
+            return self.respond(INVALID_PRESET);
+        }
 
-    fn flow_control(&self) -> serialport::Result<FlowControl> {
-        Ok(FlowControl::None)
+        let ties = match &self.presets[preset_number - 1] {
+            Some(p) => p.ties.clone(),
+            None => return self.respond(INVALID_PRESET)
+        };
+        self.current.ties = ties;
+        self.respond(&format!("Rpr{:0>2}", preset_number));
     }
 
-    fn parity(&self) -> serialport::Result<Parity> {
-        Ok(Parity::None)
-    }
+    /// `Esc+nnP` followed by the preset's ties. A single `0*!` tie clears the preset.
+    fn write_preset(&mut self, buf: &[u8]) {
+        let (preset_number, rest) = read_number(buf);
+        let preset_number = preset_number.unwrap_or_default();
+        if preset_number < 1 || preset_number > self.presets.len() {
+            return self.respond(INVALID_PRESET);
+        }
+        if rest.first() != Some(&b'P') {
+            return self.respond(INVALID_COMMAND);
+        }
 
-    fn stop_bits(&self) -> serialport::Result<StopBits> {
-        Ok(StopBits::One)
-    }
+        if &rest[1..] == b"0*!" {
+            self.presets[preset_number - 1] = None;
+            return self.respond(&format!("Spr{:0>2}", preset_number));
+        }
 
-    fn timeout(&self) -> Duration {
-        Duration::from_secs(5)
+        let ties = match parse_ties(&rest[1..]) {
+            Some(t) => t,
+            None => return self.respond(INVALID_COMMAND)
+        };
+        for tie in &ties {
+            if let Some(error) = self.validate_tie(tie) { return self.respond(error); }
+        }
+
+        let name = match &self.presets[preset_number - 1] {
+            Some(p) => p.name.clone(),
+            None => format!("Preset {:0>2}", preset_number)
+        };
+        let mut preset = CrossPointPreset { number: preset_number as i32, name, ties: Vec::new() };
+        for tie in ties { apply_tie(&mut preset.ties, tie); }
+        self.presets[preset_number - 1] = Some(preset);
+        self.respond(&format!("Spr{:0>2}", preset_number));
     }
 
-    fn set_baud_rate(&mut self, _: u32) -> serialport::Result<()> {
-        Ok(())
+    fn tie(&mut self, tie: CrossPointTie) {
+        if let Some(error) = self.validate_tie(&tie) { return self.respond(error); }
+
+        apply_tie(&mut self.current.ties, tie);
+        self.report_tie(tie);
     }
 
-    fn set_data_bits(&mut self, _: DataBits) -> serialport::Result<()> {
-        Ok(())
+    fn report_tie(&mut self, tie: CrossPointTie) {
+        let io = match tie.io_type {
+            CrossPointIO::All => "All",
+            CrossPointIO::RGB => "RGB",
+            CrossPointIO::Vid => "Vid",
+            CrossPointIO::Aud => "Aud"
+        };
+        self.respond(&format!("Out{:0>2} In{:0>2} {}", tie.output_channel, tie.input_channel, io));
     }
 
-    fn set_flow_control(&mut self, _: FlowControl) -> serialport::Result<()> {
-        Ok(())
+    fn read_level(&mut self, channel: usize, command: u8) {
+        if !self.audio { return self.respond(INVALID_COMMAND); }
+
+        if command == b'V' {
+            if channel < 1 || channel > self.out_channels { return self.respond(INVALID_OUTPUT); }
+            let response = format!("Vol{:0>2}", self.volumes[channel - 1]);
+            self.respond(&response);
+        } else {
+            if channel < 1 || channel > self.in_channels { return self.respond(INVALID_INPUT); }
+            let response = format!("{:+03}", self.gains[channel - 1]);
+            self.respond(&response);
+        }
     }
 
-    fn set_parity(&mut self, _: Parity) -> serialport::Result<()> {
-        Ok(())
+    fn set_level(&mut self, channel: usize, level: usize, command: u8) {
+        if !self.audio { return self.respond(INVALID_COMMAND); }
+
+        if command == b'V' {
+            if channel < 1 || channel > self.out_channels { return self.respond(INVALID_OUTPUT); }
+            if level > MAX_VOLUME { return self.respond(INVALID_VALUE); }
+            self.volumes[channel - 1] = level;
+            return self.respond(&format!("Out{:0>2} Vol{:0>2}", channel, level));
+        }
+
+        if channel < 1 || channel > self.in_channels { return self.respond(INVALID_INPUT); }
+        let gain = match command {
+            b'G' if level <= MAX_GAIN => level as i32,
+            b'A' if level <= MAX_ATTENUATION => -(level as i32),
+            _ => return self.respond(INVALID_VALUE)
+        };
+        self.gains[channel - 1] = gain;
+        self.respond(&format!("In{:0>2} Aud{:+03}", channel, gain));
     }
 
-    fn set_stop_bits(&mut self, _: StopBits) -> serialport::Result<()> {
-        Ok(())
+    fn mute(&mut self, output: usize, muted: usize, command: u8) {
+        if command == b'Z' && !self.audio { return self.respond(INVALID_COMMAND); }
+        if output < 1 || output > self.out_channels { return self.respond(INVALID_OUTPUT); }
+        if muted > 1 { return self.respond(INVALID_VALUE); }
+
+        let (mutes, prefix) = if command == b'B' { (&mut self.video_mutes, "Vmt") } else { (&mut self.audio_mutes, "Amt") };
+        mutes[output - 1] = muted == 1;
+        self.respond(&format!("{}{:0>2}*{}", prefix, output, muted));
     }
 
-    fn set_timeout(&mut self, _: Duration) -> serialport::Result<()> {
-        Ok(())
+    fn global_mute(&mut self, muted: usize, command: u8) {
+        if command == b'Z' && !self.audio { return self.respond(INVALID_COMMAND); }
+        if muted > 1 { return self.respond(INVALID_VALUE); }
+
+        let out_channels = self.out_channels;
+        let (mutes, prefix) = if command == b'B' { (&mut self.video_mutes, "Vmt") } else { (&mut self.audio_mutes, "Amt") };
+        for m in mutes.iter_mut().take(out_channels) { *m = muted == 1; }
+        self.respond(&format!("{}{}", prefix, muted));
     }
 
-    fn write_request_to_send(&mut self, level: bool) -> serialport::Result<()> {
-        todo!()
+    /// `0LS` reports a 1 or 0 for each input, depending on whether it has a signal
+    fn signal_presence(&mut self) {
+        if !self.signal_support { return self.respond(INVALID_COMMAND); }
+
+        let flags: Vec<&str> = self.signals.iter().take(self.in_channels).map(|&s| if s { "1" } else { "0" }).collect();
+        let response = format!("Frq00 {}", flags.join(" "));
+        self.respond(&response);
     }
 
-    fn write_data_terminal_ready(&mut self, level: bool) -> serialport::Result<()> {
-        todo!()
+    fn read_tie(&mut self, output: usize, command: u8) {
+        if output < 1 || output > self.out_channels { return self.respond(INVALID_OUTPUT); }
+        if command == b'$' && !self.audio { return self.respond(INVALID_COMMAND); }
+
+        let input = tied_input(&self.current.ties, output, command == b'$');
+        self.respond(&format!("{:0>2}", input));
     }
 
-    fn read_clear_to_send(&mut self) -> serialport::Result<bool> {
-        Ok(true)
+    fn set_lockout(&mut self, level: usize) {
+        if level > 2 { return self.respond(INVALID_VALUE); }
+        self.lockout = level;
+        self.respond(&format!("Exe{}", level));
     }
 
-    fn read_data_set_ready(&mut self) -> serialport::Result<bool> {
-        todo!()
+    fn read_mute(&mut self, output: usize, command: u8) {
+        if command == b'Z' && !self.audio { return self.respond(INVALID_COMMAND); }
+        if output < 1 || output > self.out_channels { return self.respond(INVALID_OUTPUT); }
+
+        let muted = if command == b'B' { self.video_mutes[output - 1] } else { self.audio_mutes[output - 1] };
+        self.respond(if muted { "1" } else { "0" });
     }
 
-    fn read_ring_indicator(&mut self) -> serialport::Result<bool> {
-        todo!()
+    fn validate_tie(&self, tie: &CrossPointTie) -> Option<&'static str> {
+        if tie.input_channel as usize > self.in_channels { return Some(INVALID_INPUT); }
+        if tie.output_channel < 1 || tie.output_channel as usize > self.out_channels { return Some(INVALID_OUTPUT); }
+        None
     }
+}
 
-    fn read_carrier_detect(&mut self) -> serialport::Result<bool> {
-        todo!()
+/// Input feeding an output's audio, or its video, 0 if nothing is tied
+fn tied_input(ties: &[CrossPointTie], output: usize, audio: bool) -> u8 {
+    ties.iter().find(|t| t.output_channel as usize == output && match t.io_type {
+        CrossPointIO::All => true,
+        CrossPointIO::Aud => audio,
+        _ => !audio
+    }).map_or(0, |t| t.input_channel)
+}
+
+/// Replaces whatever feeds the tie's output on the same signal path. Splitting an `All`
+/// tie keeps the half of the signal that is not being re-tied. Input 0 unties the output.
+fn apply_tie(ties: &mut Vec<CrossPointTie>, tie: CrossPointTie) {
+    let output = tie.output_channel;
+    let mut kept = Vec::new();
+    for existing in ties.drain(..) {
+        if existing.output_channel != output {
+            kept.push(existing);
+            continue;
+        }
+        let remainder = match (existing.io_type, tie.io_type) {
+            (_, CrossPointIO::All) => None,
+            (CrossPointIO::All, CrossPointIO::Aud) => Some(CrossPointIO::Vid),
+            (CrossPointIO::All, _) => Some(CrossPointIO::Aud),
+            (CrossPointIO::Aud, CrossPointIO::Aud) => None,
+            (CrossPointIO::Aud, _) => Some(CrossPointIO::Aud),
+            (_, CrossPointIO::Aud) => Some(existing.io_type),
+            (_, _) => None
+        };
+        if let Some(io_type) = remainder {
+            kept.push(CrossPointTie { io_type, ..existing });
+        }
     }
+    if tie.input_channel > 0 { kept.push(tie); }
+    *ties = kept;
+}
+
+fn default_names(prefix: &str) -> Vec<String> {
+    (1..=MAX_CHANNELS).map(|n| format!("{} {}", prefix, n)).collect()
+}
+
+fn trim_line_end(buf: &[u8]) -> &[u8] {
+    let mut end = buf.len();
+    while end > 0 && (buf[end - 1] == b'\r' || buf[end - 1] == b'\n') { end -= 1; }
+    &buf[..end]
+}
+
+fn read_number(buf: &[u8]) -> (Option<usize>, &[u8]) {
+    let digits = buf.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 { return (None, buf); }
+    let number = buf[..digits].iter().fold(0, |n, b| (n * 10) + (b - 0x30) as usize);
+    (Some(number), &buf[digits..])
+}
+
+/// Parses a run of `in*out<type>` ties
+fn parse_ties(mut buf: &[u8]) -> Option<Vec<CrossPointTie>> {
+    let mut ties = Vec::new();
+    while !buf.is_empty() {
+        let (input, rest) = read_number(buf);
+        if rest.first() != Some(&b'*') { return None; }
+        let (output, rest) = read_number(&rest[1..]);
+        let io_type = CrossPointIO::from_char(*rest.first()? as char)?;
+        ties.push(CrossPointTie {
+            input_channel: u8::try_from(input?).ok()?,
+            output_channel: u8::try_from(output?).ok()?,
+            io_type
+        });
+        buf = &rest[1..];
+    }
+    Some(ties)
+}
 
-    fn bytes_to_read(&self) -> serialport::Result<u32> {
-        Ok(self.buffer.len() as u32)
+fn offline() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Virtual CrossPoint is offline")
+}
+
+impl Write for VirtualCrosspoint {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut state = self.state();
+        if !state.online { return Err(offline()); }
+        state.evaluate_command(buf);
+        Ok(buf.len())
     }
 
-    fn bytes_to_write(&self) -> serialport::Result<u32> {
-        todo!()
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
+}
 
-    fn clear(&self, buffer_to_clear: serialport::ClearBuffer) -> serialport::Result<()> {
-        todo!()
+impl Read for VirtualCrosspoint {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut state = self.state();
+        if !state.online { return Err(offline()); }
+        let read_count = buf.len().min(state.buffer.len());
+        for (dest, b) in buf.iter_mut().zip(state.buffer.drain(0..read_count)) {
+            *dest = b;
+        }
+        Ok(read_count)
     }
+}
 
-    fn try_clone(&self) -> serialport::Result<Box<dyn SerialPort>> {
-        todo!()
+impl Transport for VirtualCrosspoint {
+    fn timeout(&self) -> Duration {
+        self.state().timeout
     }
 
-    fn set_break(&self) -> serialport::Result<()> {
-        todo!()
+    fn set_timeout(&mut self, timeout: Duration) -> std::io::Result<()> {
+        self.state().timeout = timeout;
+        Ok(())
     }
 
-    fn clear_break(&self) -> serialport::Result<()> {
-        todo!()
+    fn reconnect(&mut self) -> std::io::Result<()> {
+        let mut state = self.state();
+        if !state.online { return Err(offline()); }
+        state.buffer.clear();
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,366 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, SocketAddrV4, TcpStream},
+    str::FromStr,
+    time::Duration
+};
+use crosspoint_api::{
+    config::ServiceConfiguration,
+    crosspoint::{CrossPoint, CrossPointIO, CrossPointTie},
+    http::HttpRequest,
+    service,
+    transport::Transport,
+    vextron::VirtualCrosspoint
+};
+
+const SIMULATOR: &str = r#"{
+    "input_channels": 12,
+    "output_channels": 8,
+    "audio_support": true,
+    "current_ties": [
+        { "in": 5, "out": 4 },
+        { "in": 1, "out": 2 }
+    ],
+    "presets": [
+        {
+            "number": 2,
+            "name": "SNES",
+            "ties": [ { "in": 6, "out": 3 } ]
+        }
+    ]
+}"#;
+
+fn tie(input_channel: u8, output_channel: u8, io_type: CrossPointIO) -> CrossPointTie {
+    CrossPointTie { input_channel, output_channel, io_type }
+}
+
+fn test_config(serial_port: &str) -> ServiceConfiguration {
+    ServiceConfiguration {
+        serial_port: Some(String::from(serial_port)),
+        binding: Some(SocketAddrV4::from_str("127.0.0.1:0").unwrap()),
+        http_root: Some(String::from("../site")),
+        ..Default::default()
+    }
+}
+
+/// Starts the service on an ephemeral port against a simulator the test keeps a handle to
+fn start_simulated() -> (SocketAddr, VirtualCrosspoint) {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    let address = service::start_with(test_config("virtual"), port).unwrap();
+    (address, simulator)
+}
+
+struct Response {
+    status: i32,
+    body: String
+}
+
+fn send(address: SocketAddr, method: &str, path: &str, body: Option<&str>) -> Response {
+    let mut stream = TcpStream::connect(address).unwrap();
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, address);
+    if let Some(body) = body {
+        request.push_str(&format!("Content-Type: application/json\r\nContent-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    request.push_str(body.unwrap_or_default());
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).unwrap();
+
+    let status = raw.split(' ').nth(1).unwrap().parse().unwrap();
+    let body = match raw.split_once("\n\n") {
+        Some((_, b)) => b.to_string(),
+        None => String::new()
+    };
+    Response { status, body }
+}
+
+fn get(address: SocketAddr, path: &str) -> Response {
+    send(address, "GET", path, None)
+}
+
+fn post(address: SocketAddr, path: &str, body: &str) -> Response {
+    send(address, "POST", path, Some(body))
+}
+
+#[test]
+fn start_connects_to_virtual_switcher() {
+    let address = service::start(test_config("virtual")).unwrap();
+
+    let response = get(address, "/presetNames");
+    assert_eq!(response.status, 200);
+    assert_eq!(json::parse(&response.body).unwrap()["Presets"].len(), 32);
+}
+
+#[test]
+fn start_requires_serial_port() {
+    let config = ServiceConfiguration { serial_port: None, ..test_config("virtual") };
+    assert!(service::start(config).is_err());
+}
+
+#[test]
+fn preset_names_lists_every_slot() {
+    let (address, _) = start_simulated();
+
+    let response = get(address, "/presetNames");
+    assert_eq!(response.status, 200);
+
+    let presets = json::parse(&response.body).unwrap();
+    assert_eq!(presets["PresetCount"], 32);
+    let presets = &presets["Presets"];
+    assert_eq!(presets.len(), 32);
+    assert_eq!(presets[0]["Number"], 1);
+    assert_eq!(presets[0]["Name"], "[unassigned]");
+    assert_eq!(presets[1]["Name"], "SNES");
+}
+
+#[test]
+fn preset_count_is_detected_from_switcher() {
+    let simulator = VirtualCrosspoint::parse_json(r#"{ "input_channels": 8, "output_channels": 4, "preset_count": 16 }"#).unwrap();
+    let port = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    let address = service::start_with(test_config("virtual"), port).unwrap();
+
+    let presets = json::parse(&get(address, "/presetNames").body).unwrap();
+    assert_eq!(presets["Presets"].len(), 16);
+    assert_eq!(get(address, "/saveCurrentToPreset?preset=16").status, 200);
+    assert_eq!(get(address, "/saveCurrentToPreset?preset=17").status, 400);
+    assert!(simulator.preset(16).is_some());
+}
+
+#[test]
+fn configured_preset_count_overrides_detection() {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator), "virtual").unwrap();
+    let config = ServiceConfiguration { preset_count: Some(8), ..test_config("virtual") };
+    let address = service::start_with(config, port).unwrap();
+
+    let presets = json::parse(&get(address, "/presetNames").body).unwrap();
+    assert_eq!(presets["Presets"].len(), 8);
+    assert_eq!(get(address, "/loadPreset?preset=9").status, 400);
+}
+
+/// A switcher that answers everything except preset name reads, which time out
+struct NoPresetNames {
+    device: VirtualCrosspoint,
+    silent: bool
+}
+
+impl Read for NoPresetNames {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.silent { return Ok(0); }
+        self.device.read(buf)
+    }
+}
+
+impl Write for NoPresetNames {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.silent = buf.starts_with(b"\x1B") && buf.ends_with(b"NG\r");
+        if self.silent { Ok(buf.len()) } else { self.device.write(buf) }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.device.flush()
+    }
+}
+
+impl Transport for NoPresetNames {
+    fn timeout(&self) -> Duration { self.device.timeout() }
+    fn set_timeout(&mut self, timeout: Duration) -> std::io::Result<()> { self.device.set_timeout(timeout) }
+    fn reconnect(&mut self) -> std::io::Result<()> { self.device.reconnect() }
+}
+
+#[test]
+fn preset_count_is_not_guessed_without_replies() {
+    let device = NoPresetNames { device: VirtualCrosspoint::parse_json(SIMULATOR).unwrap(), silent: false };
+    let port = CrossPoint::open(Box::new(device), "virtual").unwrap();
+    let address = service::start_with(test_config("virtual"), port).unwrap();
+
+    assert_eq!(get(address, "/presetNames").status, 500);
+    assert_ne!(get(address, "/loadPreset?preset=50").status, 200);
+}
+
+#[test]
+fn active_presets_skips_unassigned() {
+    let (address, _) = start_simulated();
+
+    let response = get(address, "/activePresets");
+    assert_eq!(response.status, 200);
+
+    let presets = json::parse(&response.body).unwrap();
+    assert_eq!(presets["Presets"].len(), 1);
+    assert_eq!(presets["Presets"][0]["Number"], 2);
+    assert_eq!(presets["Presets"][0]["Name"], "SNES");
+}
+
+#[test]
+fn save_current_stores_current_ties() {
+    let (address, simulator) = start_simulated();
+
+    let response = get(address, "/saveCurrentToPreset?preset=5");
+    assert_eq!(response.status, 200);
+
+    let preset = simulator.preset(5).unwrap();
+    assert_eq!(preset.ties, simulator.current_ties());
+    assert_eq!(preset.ties, vec![tie(5, 4, CrossPointIO::All), tie(1, 2, CrossPointIO::All)]);
+
+    let presets = json::parse(&get(address, "/activePresets").body).unwrap();
+    assert_eq!(presets["Presets"].len(), 2);
+}
+
+#[test]
+fn save_current_requires_preset_number() {
+    let (address, _) = start_simulated();
+
+    assert_eq!(get(address, "/saveCurrentToPreset").status, 400);
+    assert_eq!(get(address, "/saveCurrentToPreset?preset=abc").status, 400);
+    assert_eq!(get(address, "/saveCurrentToPreset?preset=0").status, 400);
+    assert_eq!(get(address, "/saveCurrentToPreset?preset=33").status, 400);
+}
+
+#[test]
+fn load_preset_applies_ties() {
+    let (address, simulator) = start_simulated();
+
+    let response = get(address, "/loadPreset?preset=2");
+    assert_eq!(response.status, 200);
+    assert_eq!(simulator.current_ties(), vec![tie(6, 3, CrossPointIO::All)]);
+}
+
+#[test]
+fn load_preset_rejects_unassigned_or_missing_preset() {
+    let (address, simulator) = start_simulated();
+    let before = simulator.current_ties();
+
+    assert_eq!(get(address, "/loadPreset?preset=7").status, 400);
+    assert_eq!(get(address, "/loadPreset").status, 400);
+    assert_eq!(simulator.current_ties(), before);
+}
+
+#[test]
+fn create_preset_writes_ties() {
+    let (address, simulator) = start_simulated();
+
+    let body = r#"{
+        "PresetNumber": 12,
+        "PresetName": "Framemeister",
+        "Inputs": [
+            { "InputChannel": 3, "OutputChannels": [4, 5], "IOType": "Vid" },
+            { "InputChannel": 11, "OutputChannels": 4, "IOType": "Aud" }
+        ]
+    }"#;
+    let response = post(address, "/createPreset", body);
+    assert_eq!(response.status, 200);
+
+    let preset = simulator.preset(12).unwrap();
+    assert_eq!(preset.name, "Framemeister");
+    assert_eq!(preset.ties, vec![
+        tie(3, 4, CrossPointIO::Vid),
+        tie(3, 5, CrossPointIO::Vid),
+        tie(11, 4, CrossPointIO::Aud)
+    ]);
+}
+
+#[test]
+fn create_preset_replaces_existing_ties() {
+    let (address, simulator) = start_simulated();
+
+    let body = r#"{ "PresetNumber": 2, "PresetName": "SNES", "Inputs": [ { "InputChannel": 2, "OutputChannels": 1 } ] }"#;
+    assert_eq!(post(address, "/createPreset", body).status, 200);
+
+    assert_eq!(simulator.preset(2).unwrap().ties, vec![tie(2, 1, CrossPointIO::All)]);
+
+    assert_eq!(get(address, "/loadPreset?preset=2").status, 200);
+    assert_eq!(simulator.current_ties(), vec![tie(2, 1, CrossPointIO::All)]);
+}
+
+#[test]
+fn create_preset_rejects_bad_content() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/createPreset", "not json").status, 400);
+    assert_eq!(post(address, "/createPreset", r#"{ "PresetName": "No number" }"#).status, 400);
+    assert_eq!(post(address, "/createPreset", r#"{ "PresetNumber": 3, "Inputs": [ { "OutputChannels": 1 } ] }"#).status, 400);
+    assert_eq!(post(address, "/createPreset", r#"{ "PresetNumber": 3, "Inputs": [ { "InputChannel": 2, "OutputChannels": 1, "IOType": "Video" } ] }"#).status, 400);
+    assert!(simulator.preset(3).is_none());
+}
+
+#[test]
+fn create_preset_rejects_invalid_name() {
+    let (address, simulator) = start_simulated();
+
+    let body = r#"{ "PresetNumber": 2, "PresetName": "Super Famicom Jr", "Inputs": [ { "InputChannel": 2, "OutputChannels": 1 } ] }"#;
+    assert_eq!(post(address, "/createPreset", body).status, 400);
+    assert_eq!(simulator.preset(2).unwrap().ties, vec![tie(6, 3, CrossPointIO::All)]);
+}
+
+#[test]
+fn rename_preset_keeps_ties() {
+    let (address, simulator) = start_simulated();
+
+    let response = post(address, "/renamePreset", r#"{ "PresetNumber": 2, "PresetName": "Super NES" }"#);
+    assert_eq!(response.status, 200);
+
+    let preset = simulator.preset(2).unwrap();
+    assert_eq!(preset.name, "Super NES");
+    assert_eq!(preset.ties, vec![tie(6, 3, CrossPointIO::All)]);
+
+    let presets = json::parse(&get(address, "/activePresets").body).unwrap();
+    assert_eq!(presets["Presets"][0]["Name"], "Super NES");
+}
+
+#[test]
+fn rename_preset_validates_name_and_number() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/renamePreset", r#"{ "PresetNumber": 2, "PresetName": "" }"#).status, 400);
+    assert_eq!(post(address, "/renamePreset", r#"{ "PresetNumber": 2, "PresetName": "Much too long a name" }"#).status, 400);
+    assert_eq!(post(address, "/renamePreset", r#"{ "PresetNumber": 2, "PresetName": "SNES<2>" }"#).status, 400);
+    assert_eq!(post(address, "/renamePreset", r#"{ "PresetNumber": 2 }"#).status, 400);
+    assert_eq!(post(address, "/renamePreset", r#"{ "PresetNumber": 33, "PresetName": "SNES" }"#).status, 400);
+    assert_eq!(post(address, "/renamePreset", r#"{ "PresetNumber": 7, "PresetName": "Empty" }"#).status, 400);
+    assert_eq!(simulator.preset(2).unwrap().name, "SNES");
+}
+
+#[test]
+fn create_preset_reports_device_errors() {
+    let (address, simulator) = start_simulated();
+
+    let body = r#"{ "PresetNumber": 3, "Inputs": [ { "InputChannel": 1, "OutputChannels": 9 } ] }"#;
+    let response = post(address, "/createPreset", body);
+    assert_eq!(response.status, 500);
+    assert_eq!(response.body, "Invalid output number");
+    assert!(simulator.preset(3).is_none());
+}
+
+#[test]
+fn serves_site_pages() {
+    let (address, _) = start_simulated();
+
+    let response = get(address, "/");
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains("<html"));
+
+    assert_eq!(get(address, "/missing.html").status, 404);
+}
+
+#[test]
+fn rejects_unknown_routes_and_methods() {
+    let (address, _) = start_simulated();
+
+    assert_eq!(send(address, "DELETE", "/createPreset", None).status, 404);
+    assert_eq!(send(address, "BREW", "/", None).status, 400);
+}
+
+#[test]
+fn reads_only_the_announced_content() {
+    let request = HttpRequest::read(&mut "POST /renamePreset HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}{\"more\": 1}".as_bytes()).unwrap();
+    assert_eq!(request.content.as_deref(), Some("{}"));
+
+    let large = HttpRequest::read(&mut "POST /importPresets HTTP/1.1\r\nContent-Length: 2000000\r\n\r\n".as_bytes());
+    assert_eq!(large.err().unwrap().code.code, 413);
+
+    let headers = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(20_000));
+    assert_eq!(HttpRequest::read(&mut headers.as_bytes()).err().unwrap().code.code, 431);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use crosspoint_api::{crosspoint::CrossPoint, service, vextron::VirtualCrosspoint};
+use common::*;
+
+#[test]
+fn set_volume_changes_output_level() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/setVolume?output=3&level=20", "").status, 200);
+    assert_eq!(simulator.volume(3), 20);
+}
+
+#[test]
+fn set_gain_sends_gain_or_attenuation() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/setGain?input=2&level=6", "").status, 200);
+    assert_eq!(simulator.gain(2), 6);
+
+    assert_eq!(post(address, "/setGain?input=4&level=-12", "").status, 200);
+    assert_eq!(simulator.gain(4), -12);
+}
+
+#[test]
+fn audio_levels_reads_back_every_channel() {
+    let (address, _) = start_simulated();
+    assert_eq!(post(address, "/setVolume?output=1&level=40", "").status, 200);
+    assert_eq!(post(address, "/setGain?input=12&level=-3", "").status, 200);
+
+    let response = get(address, "/audioLevels");
+    assert_eq!(response.status, 200);
+
+    let levels = json::parse(&response.body).unwrap();
+    assert_eq!(levels["Limits"]["MaxVolume"], 64);
+    assert_eq!(levels["Inputs"].len(), 12);
+    assert_eq!(levels["Outputs"].len(), 8);
+    assert_eq!(levels["Outputs"][0]["Volume"], 40);
+    assert_eq!(levels["Outputs"][1]["Volume"], 64);
+    assert_eq!(levels["Inputs"][11]["Channel"], 12);
+    assert_eq!(levels["Inputs"][11]["Gain"], -3);
+}
+
+#[test]
+fn rejects_levels_outside_model_range() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/setVolume?output=1&level=65", "").status, 400);
+    assert_eq!(post(address, "/setVolume?output=9&level=10", "").status, 400);
+    assert_eq!(post(address, "/setGain?input=1&level=25", "").status, 400);
+    assert_eq!(post(address, "/setGain?input=1&level=-19", "").status, 400);
+    assert_eq!(post(address, "/setGain?input=1", "").status, 400);
+    assert_eq!(simulator.volume(1), 64);
+    assert_eq!(simulator.gain(1), 0);
+}
+
+#[test]
+fn rejects_audio_on_video_only_model() {
+    let simulator = VirtualCrosspoint::parse_json(r#"{ "input_channels": 8, "output_channels": 4 }"#).unwrap();
+    let port = CrossPoint::open(Box::new(simulator), "virtual").unwrap();
+    assert!(!port.audio_is_supported());
+    let address = service::start_with(test_config("virtual"), port).unwrap();
+
+    let response = post(address, "/setVolume?output=1&level=10", "");
+    assert_eq!(response.status, 400);
+    assert_eq!(response.body, "CrossPoint has no audio");
+    assert_eq!(get(address, "/audioLevels").status, 400);
+}
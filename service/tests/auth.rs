@@ -0,0 +1,90 @@
+mod common;
+
+use std::net::SocketAddr;
+use crosspoint_api::{
+    auth::{self, Credential, Role, User},
+    config::ServiceConfiguration,
+    crosspoint::CrossPoint,
+    http::HttpMethod,
+    service,
+    vextron::VirtualCrosspoint
+};
+use common::*;
+
+const USERS: &str = r#"{
+    "users": [
+        { "name": "lounge", "password": "secret", "role": "operator" },
+        { "name": "admin", "password": "hunter22", "role": "admin" },
+        { "name": "panel", "token": "abc123", "role": "viewer" }
+    ]
+}"#;
+
+fn start_with_users() -> SocketAddr {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator), "virtual").unwrap();
+    let config = ServiceConfiguration { users: ServiceConfiguration::parse(USERS).unwrap().users, ..test_config("virtual") };
+    service::start_with(config, port).unwrap()
+}
+
+fn get_as(address: SocketAddr, path: &str, authorization: &str) -> Response {
+    send_with_headers(address, "GET", path, &[("Authorization", authorization)], None)
+}
+
+#[test]
+fn parses_users() {
+    let config = ServiceConfiguration::parse(USERS).unwrap();
+    assert_eq!(config.users[0], User { name: String::from("lounge"), credential: Credential::Password(String::from("secret")), role: Role::Operator });
+    assert_eq!(config.users[2].credential, Credential::Token(String::from("abc123")));
+
+    assert!(ServiceConfiguration::parse(r#"{ "users": [ { "name": "a", "password": "b", "role": "owner" } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "users": [ { "name": "a", "role": "admin" } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "users": [ { "name": "a", "password": "b", "token": "c", "role": "admin" } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "users": [
+        { "name": "a", "password": "b", "role": "admin" }, { "name": "a", "token": "c", "role": "viewer" } ] }"#).is_err());
+}
+
+#[test]
+fn routes_need_roles() {
+    assert_eq!(auth::required_role(HttpMethod::GET, "/presetNames"), Role::Viewer);
+    assert_eq!(auth::required_role(HttpMethod::GET, "/"), Role::Viewer);
+    assert_eq!(auth::required_role(HttpMethod::POST, "/comparePresets"), Role::Viewer);
+    assert_eq!(auth::required_role(HttpMethod::GET, "/loadPreset"), Role::Operator);
+    assert_eq!(auth::required_role(HttpMethod::POST, "/runMacro"), Role::Operator);
+    assert_eq!(auth::required_role(HttpMethod::POST, "/createPreset"), Role::Admin);
+    assert_eq!(auth::required_role(HttpMethod::GET, "/saveCurrentToPreset"), Role::Admin);
+}
+
+#[test]
+fn requires_credentials() {
+    let address = start_with_users();
+
+    let response = get(address, "/presetNames");
+    assert_eq!(response.status, 401);
+    assert_eq!(response.header("WWW-Authenticate"), Some("Basic realm=\"CrossPoint\""));
+    assert_eq!(get_as(address, "/presetNames", "Basic bG91bmdlOndyb25n").status, 401);
+    let response = get_as(address, "/presetNames", "Bearer abc124");
+    assert_eq!(response.status, 401);
+    assert_eq!(response.header("WWW-Authenticate"), Some("Bearer realm=\"CrossPoint\""));
+    assert_eq!(get_as(address, "/presetNames", "Bearer abc123").status, 200);
+    assert_eq!(get_as(address, "/presetNames", "Basic bG91bmdlOnNlY3JldA==").status, 200);
+}
+
+#[test]
+fn checks_role_of_each_route() {
+    let address = start_with_users();
+
+    assert_eq!(get_as(address, "/loadPreset?preset=2", "Bearer abc123").status, 403);
+    assert_eq!(get_as(address, "/loadPreset?preset=2", "Basic bG91bmdlOnNlY3JldA==").status, 200);
+    assert_eq!(get_as(address, "/saveCurrentToPreset?preset=3", "Basic bG91bmdlOnNlY3JldA==").status, 403);
+    assert_eq!(get_as(address, "/saveCurrentToPreset?preset=3", "Basic YWRtaW46aHVudGVyMjI=").status, 200);
+
+    let body = r#"{ "PresetNumber": 4, "PresetName": "Arcade" }"#;
+    let response = send_with_headers(address, "POST", "/renamePreset", &[("authorization", "Bearer abc123")], Some(body));
+    assert_eq!(response.status, 403);
+}
+
+#[test]
+fn open_without_users() {
+    let (address, _) = start_simulated();
+    assert_eq!(get(address, "/presetNames").status, 200);
+}
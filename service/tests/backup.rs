@@ -0,0 +1,89 @@
+mod common;
+
+use crosspoint_api::{
+    backup::{PresetBackup, PresetChangeKind},
+    crosspoint::{CrossPoint, CrossPointIO},
+    vextron::VirtualCrosspoint
+};
+use common::*;
+
+#[test]
+fn export_reads_every_preset() {
+    let (address, _) = start_simulated();
+
+    let response = get(address, "/exportPresets");
+    assert_eq!(response.status, 200);
+
+    let backup = json::parse(&response.body).unwrap();
+    assert_eq!(backup["Version"], 1);
+    assert_eq!(backup["Inputs"], 12);
+    assert_eq!(backup["Outputs"], 8);
+    assert_eq!(backup["Presets"].len(), 1);
+    assert_eq!(backup["Presets"][0]["PresetNumber"], 2);
+    assert_eq!(backup["Presets"][0]["PresetName"], "SNES");
+    assert_eq!(backup["Presets"][0]["Inputs"][0]["InputChannel"], 6);
+    assert_eq!(backup["Presets"][0]["Inputs"][0]["OutputChannels"][0], 3);
+}
+
+#[test]
+fn restores_backup_onto_reset_switcher() {
+    let mut source = CrossPoint::open(Box::new(VirtualCrosspoint::parse_json(SIMULATOR).unwrap()), "virtual").unwrap();
+    let backup = PresetBackup::read(&mut source).unwrap();
+    let backup = PresetBackup::parse(&json::stringify(backup.to_json())).unwrap();
+
+    let simulator = VirtualCrosspoint::new();
+    let mut target = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    let changes = backup.restore(&mut target).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].kind, PresetChangeKind::Create);
+
+    let preset = simulator.preset(2).unwrap();
+    assert_eq!(preset.name, "SNES");
+    assert_eq!(preset.ties, vec![tie(6, 3, CrossPointIO::All)]);
+    assert!(backup.plan(&mut target).unwrap().is_empty());
+}
+
+#[test]
+fn dry_run_lists_changes_without_writing() {
+    let (address, simulator) = start_simulated();
+    let backup = get(address, "/exportPresets").body;
+
+    assert_eq!(post(address, "/renamePreset", r#"{ "PresetNumber": 2, "PresetName": "Super NES" }"#).status, 200);
+    assert_eq!(get(address, "/saveCurrentToPreset?preset=5").status, 200);
+
+    let response = post(address, "/importPresets?dryRun=1", &backup);
+    assert_eq!(response.status, 200);
+    let result = json::parse(&response.body).unwrap();
+    assert_eq!(result["DryRun"], true);
+    assert_eq!(result["Changes"].len(), 2);
+    assert_eq!(result["Changes"][0]["PresetNumber"], 2);
+    assert_eq!(result["Changes"][0]["Change"], "Replace");
+    assert_eq!(result["Changes"][0]["PresetName"], "SNES");
+    assert_eq!(result["Changes"][1]["PresetNumber"], 5);
+    assert_eq!(result["Changes"][1]["Change"], "Clear");
+    assert_eq!(simulator.preset(2).unwrap().name, "Super NES");
+    assert!(simulator.preset(5).is_some());
+
+    let result = json::parse(&post(address, "/importPresets", &backup).body).unwrap();
+    assert_eq!(result["DryRun"], false);
+    assert_eq!(result["Changes"].len(), 2);
+    assert_eq!(simulator.preset(2).unwrap().name, "SNES");
+    assert!(simulator.preset(5).is_none());
+
+    let result = json::parse(&post(address, "/importPresets?dryRun=1", &backup).body).unwrap();
+    assert!(result["Changes"].is_empty());
+}
+
+#[test]
+fn import_rejects_bad_documents() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/importPresets", "not json").status, 400);
+    assert_eq!(post(address, "/importPresets", r#"{ "Presets": [] }"#).status, 400);
+    assert_eq!(post(address, "/importPresets", r#"{ "Version": 2, "Presets": [] }"#).status, 400);
+    assert_eq!(post(address, "/importPresets", r#"{ "Version": 1, "Presets": [
+        { "PresetNumber": 40, "Inputs": [ { "InputChannel": 1, "OutputChannels": [1] } ] } ] }"#).status, 400);
+    assert_eq!(post(address, "/importPresets", r#"{ "Version": 1, "Presets": [
+        { "PresetNumber": 3, "Inputs": [ { "InputChannel": 1, "OutputChannels": [9] } ] } ] }"#).status, 400);
+    assert_eq!(simulator.preset(2).unwrap().name, "SNES");
+}
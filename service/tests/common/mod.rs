@@ -0,0 +1,113 @@
+#![allow(dead_code)]
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, SocketAddrV4, TcpStream},
+    str::FromStr,
+    thread,
+    time::{Duration, Instant}
+};
+use crosspoint_api::{
+    config::ServiceConfiguration,
+    crosspoint::{CrossPoint, CrossPointIO, CrossPointTie},
+    service,
+    vextron::VirtualCrosspoint
+};
+
+pub const SIMULATOR: &str = r#"{
+    "input_channels": 12,
+    "output_channels": 8,
+    "audio_support": true,
+    "current_ties": [
+        { "in": 5, "out": 4 },
+        { "in": 1, "out": 2 }
+    ],
+    "presets": [
+        {
+            "number": 2,
+            "name": "SNES",
+            "ties": [ { "in": 6, "out": 3 } ]
+        }
+    ]
+}"#;
+
+pub fn tie(input_channel: u8, output_channel: u8, io_type: CrossPointIO) -> CrossPointTie {
+    CrossPointTie { input_channel, output_channel, io_type }
+}
+
+pub fn test_config(serial_port: &str) -> ServiceConfiguration {
+    ServiceConfiguration {
+        serial_port: Some(String::from(serial_port)),
+        binding: Some(SocketAddrV4::from_str("127.0.0.1:0").unwrap()),
+        http_root: Some(String::from("../site")),
+        ..Default::default()
+    }
+}
+
+/// Starts the service on an ephemeral port against a simulator the test keeps a handle to
+pub fn start_simulated() -> (SocketAddr, VirtualCrosspoint) {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    let address = service::start_with(test_config("virtual"), port).unwrap();
+    (address, simulator)
+}
+
+pub struct Response {
+    pub status: i32,
+    pub headers: Vec<(String, String)>,
+    pub body: String
+}
+
+impl Response {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+pub fn send(address: SocketAddr, method: &str, path: &str, body: Option<&str>) -> Response {
+    send_with_headers(address, method, path, &[], body)
+}
+
+pub fn send_with_headers(address: SocketAddr, method: &str, path: &str, headers: &[(&str, &str)], body: Option<&str>) -> Response {
+    let mut stream = TcpStream::connect(address).unwrap();
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", method, path, address);
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(body) = body {
+        request.push_str(&format!("Content-Type: application/json\r\nContent-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    request.push_str(body.unwrap_or_default());
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).unwrap();
+
+    let status = raw.split(' ').nth(1).unwrap().parse().unwrap();
+    let (head, body) = raw.split_once("\n\n").unwrap_or((&raw, ""));
+    let headers = head.lines().skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Response { status, headers, body: body.to_string() }
+}
+
+pub fn get(address: SocketAddr, path: &str) -> Response {
+    send(address, "GET", path, None)
+}
+
+pub fn post(address: SocketAddr, path: &str, body: &str) -> Response {
+    send(address, "POST", path, Some(body))
+}
+
+/// Polls `path` until `done` accepts the response, failing the test after a few seconds
+pub fn wait_for(address: SocketAddr, path: &str, done: impl Fn(&Response) -> bool) -> Response {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let response = get(address, path);
+        if done(&response) { return response; }
+        assert!(Instant::now() < deadline, "Timed out waiting on {}: {}", path, response.body);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use crosspoint_api::{
+    compare::{compare_ties, TieChange, TieChangeKind},
+    crosspoint::CrossPointIO
+};
+use common::*;
+
+fn change(output_channel: u8, io_type: CrossPointIO, kind: TieChangeKind, from_input: Option<u8>, to_input: Option<u8>) -> TieChange {
+    TieChange { output_channel, io_type, kind, from_input, to_input }
+}
+
+#[test]
+fn compares_ties_by_output_and_type() {
+    let from = vec![tie(5, 4, CrossPointIO::All), tie(1, 2, CrossPointIO::All)];
+    let to = vec![tie(6, 3, CrossPointIO::All), tie(1, 2, CrossPointIO::Vid), tie(7, 2, CrossPointIO::Aud)];
+
+    assert_eq!(compare_ties(&from, &to), vec![
+        change(2, CrossPointIO::Aud, TieChangeKind::Changed, Some(1), Some(7)),
+        change(3, CrossPointIO::All, TieChangeKind::Added, None, Some(6)),
+        change(4, CrossPointIO::All, TieChangeKind::Removed, Some(5), None)
+    ]);
+    assert!(compare_ties(&from, &from).is_empty());
+}
+
+#[test]
+fn compares_preset_with_current_ties() {
+    let (address, _) = start_simulated();
+
+    let response = get(address, "/comparePresets?to=2");
+    assert_eq!(response.status, 200);
+    let changes = json::parse(&response.body).unwrap();
+    let changes = &changes["Changes"];
+    assert_eq!(changes.len(), 3);
+    assert_eq!(changes[0]["OutputChannel"], 2);
+    assert_eq!(changes[0]["Change"], "Removed");
+    assert_eq!(changes[0]["FromInput"], 1);
+    assert!(changes[0]["ToInput"].is_null());
+    assert_eq!(changes[1]["OutputChannel"], 3);
+    assert_eq!(changes[1]["Change"], "Added");
+    assert_eq!(changes[1]["IOType"], "All");
+    assert_eq!(changes[1]["ToInput"], 6);
+
+    let changes = json::parse(&get(address, "/comparePresets?from=2&to=7").body).unwrap();
+    assert_eq!(changes["Changes"].len(), 1);
+    assert_eq!(changes["Changes"][0]["Change"], "Removed");
+}
+
+#[test]
+fn previews_overwriting_preset() {
+    let (address, _) = start_simulated();
+
+    let body = r#"{ "PresetNumber": 2, "Inputs": [ { "InputChannel": 6, "OutputChannels": [3, 5] } ] }"#;
+    let changes = json::parse(&post(address, "/comparePresets", body).body).unwrap();
+    assert_eq!(changes["Changes"].len(), 1);
+    assert_eq!(changes["Changes"][0]["OutputChannel"], 5);
+    assert_eq!(changes["Changes"][0]["Change"], "Added");
+}
+
+#[test]
+fn compare_rejects_bad_presets() {
+    let (address, _) = start_simulated();
+
+    assert_eq!(get(address, "/comparePresets").status, 400);
+    assert_eq!(get(address, "/comparePresets?to=40").status, 400);
+    assert_eq!(get(address, "/comparePresets?from=abc&to=2").status, 400);
+    assert_eq!(post(address, "/comparePresets", "{}").status, 400);
+}
@@ -0,0 +1,98 @@
+use std::time::Duration;
+use serialport::{DataBits,Parity,StopBits,FlowControl};
+use crosspoint_api::{
+    config::ServiceConfiguration,
+    transport::SerialSettings
+};
+
+#[test]
+fn serial_settings_default_to_9600_8n1() {
+    let config = ServiceConfiguration::parse(r#"{ "serial-port": "/dev/ttyUSB0" }"#).unwrap();
+    assert_eq!(config.serial, SerialSettings::default());
+    assert_eq!(config.serial.baud_rate, Some(9600));
+    assert_eq!(config.serial.timeout, Duration::from_secs(1));
+}
+
+#[test]
+fn parses_serial_settings() {
+    let config = ServiceConfiguration::parse(r#"{
+        "serial-port": "/dev/ttyUSB0",
+        "baud-rate": 38400,
+        "data-bits": 7,
+        "parity": "even",
+        "stop-bits": 2,
+        "flow-control": "hardware",
+        "serial-timeout": 250
+    }"#).unwrap();
+
+    assert_eq!(config.serial, SerialSettings {
+        baud_rate: Some(38400),
+        data_bits: DataBits::Seven,
+        parity: Parity::Even,
+        stop_bits: StopBits::Two,
+        flow_control: FlowControl::Hardware,
+        timeout: Duration::from_millis(250)
+    });
+}
+
+#[test]
+fn auto_baud_rate_leaves_rate_unset() {
+    let config = ServiceConfiguration::parse(r#"{ "baud-rate": "auto" }"#).unwrap();
+    assert_eq!(config.serial.baud_rate, None);
+}
+
+#[test]
+fn rejects_invalid_serial_settings() {
+    assert!(ServiceConfiguration::parse(r#"{ "baud-rate": "fast" }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "data-bits": 9 }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "parity": "mark" }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "stop-bits": 3 }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "flow-control": "xon" }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "serial-timeout": 0 }"#).is_err());
+}
+
+#[test]
+fn parses_front_panel_lockout() {
+    let config = ServiceConfiguration::parse(r#"{ "front-panel-lockout": 1 }"#).unwrap();
+    assert_eq!(config.front_panel_lockout, Some(1));
+
+    assert_eq!(ServiceConfiguration::parse("{}").unwrap().front_panel_lockout, None);
+    assert!(ServiceConfiguration::parse(r#"{ "front-panel-lockout": 5 }"#).is_err());
+}
+
+#[test]
+fn parses_preset_count() {
+    let config = ServiceConfiguration::parse(r#"{ "preset-count": 64 }"#).unwrap();
+    assert_eq!(config.preset_count, Some(64));
+
+    assert_eq!(ServiceConfiguration::parse("{}").unwrap().preset_count, None);
+    assert!(ServiceConfiguration::parse(r#"{ "preset-count": 0 }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "preset-count": 100 }"#).is_err());
+}
+
+#[test]
+fn saves_channel_names_to_config_file() {
+    let path = std::env::temp_dir().join(format!("crosspoint-config-{}.json", std::process::id()));
+    std::fs::write(&path, r#"{ "serial-port": "virtual", "inputs": [ { "channel": 1, "description": "NES" } ] }"#).unwrap();
+
+    let mut config = ServiceConfiguration::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    config.path = Some(path.to_str().unwrap().to_string());
+    config.inputs.insert(2, String::from("Super NES"));
+    config.outputs.insert(1, String::from("Shinybow"));
+    config.save_channel_names().unwrap();
+
+    let saved = ServiceConfiguration::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(saved.serial_port.as_deref(), Some("virtual"));
+    assert_eq!(saved.inputs, config.inputs);
+    assert_eq!(saved.outputs, config.outputs);
+}
+
+#[test]
+fn parses_signal_poll_interval() {
+    assert_eq!(ServiceConfiguration::parse("{}").unwrap().signal_poll_interval, None);
+
+    let config = ServiceConfiguration::parse(r#"{ "signal-poll-interval": 0 }"#).unwrap();
+    assert_eq!(config.signal_poll_interval, Some(Duration::ZERO));
+    assert!(ServiceConfiguration::parse(r#"{ "signal-poll-interval": "often" }"#).is_err());
+}
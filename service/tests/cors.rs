@@ -0,0 +1,87 @@
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+use crosspoint_api::{
+    config::ServiceConfiguration,
+    crosspoint::CrossPoint,
+    service,
+    vextron::VirtualCrosspoint
+};
+use common::*;
+
+const DASHBOARD: &str = "http://homeassistant.local:8123";
+
+fn start_with_cors(cors: &str) -> SocketAddr {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator), "virtual").unwrap();
+    let parsed = ServiceConfiguration::parse(&format!(r#"{{ "cors": {}, "users": [ {{ "name": "panel", "token": "abc123", "role": "viewer" }} ] }}"#, cors)).unwrap();
+    let config = ServiceConfiguration { cors: parsed.cors, users: parsed.users, ..test_config("virtual") };
+    service::start_with(config, port).unwrap()
+}
+
+#[test]
+fn parses_cors_policy() {
+    let config = ServiceConfiguration::parse(r#"{ "cors": { "origins": ["*"], "headers": ["X-Requested-With"], "max-age": 600 } }"#).unwrap();
+    let cors = config.cors.unwrap();
+    assert_eq!(cors.origins, vec!["*"]);
+    assert_eq!(cors.methods, vec!["GET", "HEAD", "POST", "OPTIONS"]);
+    assert_eq!(cors.headers, vec!["X-Requested-With"]);
+    assert_eq!(cors.max_age, Some(Duration::from_secs(600)));
+
+    assert!(ServiceConfiguration::parse("{}").unwrap().cors.is_none());
+    assert!(ServiceConfiguration::parse(r#"{ "cors": {} }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "cors": { "origins": "*" } }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "cors": { "origins": ["*"], "max-age": "soon" } }"#).is_err());
+}
+
+#[test]
+fn answers_preflight_without_credentials() {
+    let address = start_with_cors(&format!(r#"{{ "origins": ["{}"], "max-age": 600 }}"#, DASHBOARD));
+
+    let response = send_with_headers(address, "OPTIONS", "/undo", &[
+        ("Origin", DASHBOARD), ("Access-Control-Request-Method", "POST"), ("Access-Control-Request-Headers", "authorization")
+    ], None);
+    assert_eq!(response.status, 204);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some(DASHBOARD));
+    assert_eq!(response.header("Access-Control-Allow-Methods"), Some("GET, HEAD, POST, OPTIONS"));
+    assert_eq!(response.header("Access-Control-Allow-Headers"), Some("Authorization, Content-Type"));
+    assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+
+    let response = send_with_headers(address, "GET", "/presetNames", &[("Origin", DASHBOARD), ("Authorization", "Bearer abc123")], None);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some(DASHBOARD));
+    assert_eq!(response.header("Vary"), Some("Origin"));
+    assert!(response.header("Access-Control-Allow-Methods").is_none());
+}
+
+#[test]
+fn ignores_other_origins() {
+    let address = start_with_cors(&format!(r#"{{ "origins": ["{}"] }}"#, DASHBOARD));
+
+    let response = send_with_headers(address, "OPTIONS", "/presetNames", &[("Origin", "http://evil.example")], None);
+    assert_eq!(response.status, 204);
+    assert!(response.header("Access-Control-Allow-Origin").is_none());
+    assert_eq!(response.header("Allow"), Some("GET, HEAD, POST, OPTIONS"));
+
+    let address = start_with_cors(r#"{ "origins": ["*"] }"#);
+    let response = send_with_headers(address, "GET", "/presetNames", &[("Origin", "http://evil.example")], None);
+    assert_eq!(response.status, 401);
+    assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+}
+
+#[test]
+fn head_leaves_out_content() {
+    let (address, _) = start_simulated();
+
+    let full = get(address, "/presetNames");
+    let response = send(address, "HEAD", "/presetNames", None);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("Content-Length"), Some(full.body.len().to_string().as_str()));
+    assert_eq!(response.header("Content-Type"), Some("application/json"));
+    assert!(response.body.is_empty());
+
+    assert_eq!(send(address, "HEAD", "/loadPreset?preset=2", None).status, 405);
+    assert_eq!(send_with_headers(address, "OPTIONS", "/loadPreset", &[("Access-Control-Request-Method", "GET")], None).status, 405);
+    assert_eq!(get(address, "/undo").status, 404);
+    assert_eq!(send(address, "OPTIONS", "/", None).status, 204);
+}
@@ -0,0 +1,47 @@
+mod common;
+
+use std::io::ErrorKind;
+use crosspoint_api::{discovery, service, transport::{ConnectionSettings,SerialSettings}};
+use common::*;
+
+#[test]
+fn lists_serial_ports() {
+    let (address, _) = start_simulated();
+
+    let response = get(address, "/serialPorts");
+    assert_eq!(response.status, 200);
+
+    let ports = json::parse(&response.body).unwrap();
+    let expected = discovery::list_ports().unwrap();
+    assert_eq!(ports["Ports"].len(), expected.len());
+    for (port, info) in ports["Ports"].members().zip(expected.iter()) {
+        assert_eq!(port["Name"], info.port_name.as_str());
+        assert_eq!(port["InUse"], false);
+        assert!(port["Type"].is_string());
+    }
+}
+
+#[test]
+fn auto_detect_reports_missing_switcher() {
+    if !discovery::list_ports().unwrap().is_empty() { return; }
+
+    let address = service::start(test_config("auto")).unwrap();
+
+    let state = json::parse(&get(address, "/linkState").body).unwrap();
+    assert_eq!(state["Port"], "auto");
+    assert_eq!(state["State"], "Disconnected");
+    assert_eq!(state["Error"], "No CrossPoint found on any serial port");
+}
+
+#[test]
+fn auto_baud_stops_on_missing_port() {
+    let settings = ConnectionSettings {
+        serial: SerialSettings { baud_rate: None, ..Default::default() },
+        ..ConnectionSettings::new("/dev/crosspoint-missing")
+    };
+
+    match discovery::detect_baud_rate(&settings) {
+        Ok(_) => panic!("Detected a switcher on a missing port"),
+        Err(e) => assert_eq!(e.kind(), ErrorKind::NotFound)
+    }
+}
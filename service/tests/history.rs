@@ -0,0 +1,79 @@
+mod common;
+
+use crosspoint_api::{
+    crosspoint::{CrossPoint, CrossPointIO},
+    history::RoutingHistory,
+    vextron::VirtualCrosspoint
+};
+use common::*;
+
+#[test]
+fn recall_can_be_undone() {
+    let (address, simulator) = start_simulated();
+    let before = simulator.current_ties();
+
+    assert_eq!(post(address, "/undo", "").status, 404);
+    assert_eq!(get(address, "/loadPreset?preset=2").status, 200);
+
+    let history = json::parse(&get(address, "/history").body).unwrap();
+    assert_eq!(history["History"].len(), 1);
+    assert_eq!(history["History"][0]["Id"], 1);
+    assert_eq!(history["History"][0]["Cause"], "Recall preset 2");
+    assert_eq!(history["History"][0]["Ties"].len(), 2);
+
+    assert_eq!(post(address, "/undo", "").status, 200);
+    let after = simulator.current_ties();
+    assert_eq!(after.len(), before.len());
+    assert!(before.iter().all(|t| after.contains(t)));
+
+    let history = json::parse(&get(address, "/history").body).unwrap();
+    assert_eq!(history["History"][0]["Cause"], "Restore 1");
+}
+
+#[test]
+fn front_panel_changes_are_recorded() {
+    let (address, simulator) = start_simulated();
+
+    simulator.front_panel_tie(tie(7, 4, CrossPointIO::All));
+    let history = wait_for(address, "/history", |r| r.body.contains("Front panel"));
+    let history = json::parse(&history.body).unwrap();
+    assert!(history["History"][0]["Ties"].members().any(|t| t["InputChannel"] == 5 && t["OutputChannel"] == 4));
+
+    assert_eq!(post(address, "/restoreHistory?id=1", "").status, 200);
+    assert!(simulator.current_ties().contains(&tie(5, 4, CrossPointIO::All)));
+}
+
+#[test]
+fn restore_requires_known_entry() {
+    let (address, _) = start_simulated();
+
+    assert_eq!(post(address, "/restoreHistory", "").status, 400);
+    assert_eq!(post(address, "/restoreHistory?id=abc", "").status, 400);
+    assert_eq!(post(address, "/restoreHistory?id=99", "").status, 404);
+}
+
+#[test]
+fn change_of_several_ties_is_one_entry() {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let mut cp = CrossPoint::open(Box::new(simulator), "virtual").unwrap();
+    cp.keep_history();
+
+    cp.as_one_change("Macro", |cp| {
+        cp.tie(tie(3, 1, CrossPointIO::All)).unwrap();
+        cp.tie(tie(3, 2, CrossPointIO::All)).unwrap();
+    });
+    cp.tie(tie(4, 1, CrossPointIO::Vid)).unwrap();
+
+    let causes: Vec<&str> = cp.history().unwrap().entries().map(|e| e.cause.as_str()).collect();
+    assert_eq!(causes, vec!["Macro", "Tie 4*1%"]);
+}
+
+#[test]
+fn history_is_bounded() {
+    let mut history = RoutingHistory::new();
+    for _ in 0..60 { history.record("Tie", Vec::new()); }
+
+    assert_eq!(history.entries().count(), 50);
+    assert_eq!(history.entries().next().unwrap().id, 11);
+    assert_eq!(history.last().unwrap().id, 60);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use crosspoint_api::service;
+use common::*;
+
+#[test]
+fn recovers_after_switcher_is_unplugged() {
+    let (address, simulator) = start_simulated();
+
+    simulator.set_online(false);
+    wait_for(address, "/linkState", |r| r.body.contains("\"Disconnected\""));
+
+    let state = json::parse(&get(address, "/linkState").body).unwrap();
+    assert_eq!(state["State"], "Disconnected");
+    assert_eq!(get(address, "/activePresets").status, 503);
+
+    simulator.set_online(true);
+    let state = wait_for(address, "/linkState", |r| r.body.contains("\"Connected\""));
+    let state = json::parse(&state.body).unwrap();
+    assert_eq!(state["Inputs"], 12);
+    assert_eq!(state["Outputs"], 8);
+
+    assert_eq!(get(address, "/activePresets").status, 200);
+}
+
+#[test]
+fn starts_without_reachable_switcher() {
+    let address = service::start(test_config("replay:missing.log")).unwrap();
+
+    let state = json::parse(&get(address, "/linkState").body).unwrap();
+    assert_eq!(state["State"], "Disconnected");
+    assert_eq!(state["Port"], "replay:missing.log");
+    assert!(state["Error"].as_str().is_some());
+
+    assert_eq!(get(address, "/presetNames").status, 503);
+    assert_eq!(get(address, "/").status, 200);
+
+    let state = wait_for(address, "/linkState", |r| json::parse(&r.body).unwrap()["Attempts"].as_u32().unwrap_or(0) > 0);
+    assert!(state.body.contains("Disconnected"));
+}
+
+#[test]
+fn rejects_switcher_reporting_other_sizes() {
+    let (address, simulator) = start_simulated();
+
+    simulator.set_online(false);
+    wait_for(address, "/linkState", |r| r.body.contains("\"Disconnected\""));
+    simulator.set_channel_counts(8, 4);
+    simulator.set_online(true);
+
+    let state = wait_for(address, "/linkState", |r| json::parse(&r.body).unwrap()["Attempts"].as_u32().unwrap_or(0) > 0);
+    let state = json::parse(&state.body).unwrap();
+    assert_eq!(state["State"], "Disconnected");
+    assert_eq!(state["Error"], "Device reports 8x4, expected 12x8");
+
+    simulator.set_channel_counts(12, 8);
+    wait_for(address, "/linkState", |r| r.body.contains("\"Connected\""));
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use crosspoint_api::{config::ServiceConfiguration, crosspoint::CrossPoint, service, vextron::VirtualCrosspoint};
+use common::*;
+
+#[test]
+fn set_lockout_locks_front_panel() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/setLockout?level=1", "").status, 200);
+    assert_eq!(simulator.lockout(), 1);
+
+    let lockout = json::parse(&get(address, "/lockout").body).unwrap();
+    assert_eq!(lockout["Level"], 1);
+
+    assert_eq!(post(address, "/setLockout?level=0", "").status, 200);
+    assert_eq!(simulator.lockout(), 0);
+
+    let events = json::parse(&get(address, "/events").body).unwrap();
+    assert_eq!(events["Events"].len(), 2);
+    assert_eq!(events["Events"][0]["Type"], "Lockout");
+    assert_eq!(events["Events"][0]["Level"], 1);
+}
+
+#[test]
+fn rejects_unknown_lockout_level() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/setLockout?level=3", "").status, 400);
+    assert_eq!(post(address, "/setLockout", "").status, 400);
+    assert_eq!(simulator.lockout(), 0);
+}
+
+#[test]
+fn startup_enforces_configured_lockout() {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    let config = ServiceConfiguration { front_panel_lockout: Some(2), ..test_config("virtual") };
+    service::start_with(config, port).unwrap();
+
+    assert_eq!(simulator.lockout(), 2);
+}
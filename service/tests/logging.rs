@@ -0,0 +1,103 @@
+mod common;
+
+use std::{
+    env, fs,
+    time::{Duration, UNIX_EPOCH}
+};
+use log::{Level, LevelFilter, Log, Record};
+use crosspoint_api::{
+    config::ServiceConfiguration,
+    logging::{self, LogSettings, Logger}
+};
+use common::*;
+
+fn temp_file(name: &str) -> String {
+    let path = env::temp_dir().join(format!("crosspoint-{}-{}.log", name, std::process::id()));
+    _ = fs::remove_file(&path);
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn parses_log_settings() {
+    let config = ServiceConfiguration::parse(r#"{ "log-level": "warn", "log-levels": { "serial": "trace", "http": "off" }, "log-file": "service.log" }"#).unwrap();
+    assert_eq!(config.logging.level, LevelFilter::Warn);
+    assert_eq!(config.logging.level_for("crosspoint_api::http"), LevelFilter::Off);
+    assert_eq!(config.logging.level_for("serial"), LevelFilter::Trace);
+    assert_eq!(config.logging.level_for("crosspoint_api::service"), LevelFilter::Warn);
+    assert_eq!(config.logging.max_level(), LevelFilter::Trace);
+    assert_eq!(config.logging.file.as_deref(), Some("service.log"));
+
+    assert_eq!(ServiceConfiguration::parse("{}").unwrap().logging, LogSettings::default());
+    assert!(ServiceConfiguration::parse(r#"{ "log-level": "loud" }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "log-levels": { "serial": 5 } }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "log-levels": "trace" }"#).is_err());
+}
+
+#[test]
+fn formats_timestamps() {
+    let moment = UNIX_EPOCH + Duration::from_millis(1_709_242_200_123);
+    assert_eq!(logging::timestamp(moment), "2024-02-29T21:30:00.123Z");
+    assert_eq!(logging::access_timestamp(moment), "29/Feb/2024:21:30:00 +0000");
+}
+
+#[test]
+fn filters_by_module_level() {
+    let file = temp_file("levels");
+    let access_file = temp_file("levels-access");
+    let logger = Logger::new(LogSettings {
+        levels: [(String::from("routing"), LevelFilter::Debug)].into(),
+        file: Some(file.clone()),
+        access_log: Some(access_file.clone()),
+        ..Default::default()
+    }).unwrap();
+
+    logger.log(&Record::builder().target("crosspoint_api::routing").level(Level::Debug).args(format_args!("Rule fired")).build());
+    logger.log(&Record::builder().target("crosspoint_api::service").level(Level::Debug).args(format_args!("Hidden")).build());
+    logger.log(&Record::builder().target("crosspoint_api::service").level(Level::Warn).args(format_args!("Lost CrossPoint")).build());
+    logger.log(&Record::builder().target("access").level(Level::Info).args(format_args!("127.0.0.1 - - request")).build());
+
+    let log = fs::read_to_string(&file).unwrap();
+    let access = fs::read_to_string(&access_file).unwrap();
+    fs::remove_file(&file).unwrap();
+    fs::remove_file(&access_file).unwrap();
+
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with("Z DEBUG routing: Rule fired"), "{}", lines[0]);
+    assert!(lines[1].ends_with("Z WARN  service: Lost CrossPoint"), "{}", lines[1]);
+    assert_eq!(access, "127.0.0.1 - - request\n");
+}
+
+#[test]
+fn logs_requests_and_serial_traffic() {
+    let file = temp_file("service");
+    let access_file = temp_file("access");
+    let settings = LogSettings {
+        levels: [(String::from("serial"), LevelFilter::Trace)].into(),
+        file: Some(file.clone()),
+        access_log: Some(access_file.clone()),
+        ..Default::default()
+    };
+    logging::init(&settings).unwrap();
+    assert!(logging::init(&settings).is_err());
+
+    let (address, _) = start_simulated();
+    assert_eq!(get(address, "/loadPreset?preset=2").status, 200);
+    assert_eq!(get(address, "/loadPreset").status, 400);
+    log::logger().flush();
+
+    let log = fs::read_to_string(&file).unwrap();
+    let access = fs::read_to_string(&access_file).unwrap();
+    fs::remove_file(&file).unwrap();
+    fs::remove_file(&access_file).unwrap();
+
+    assert!(log.contains("INFO  service: Listening on: http://"), "{}", log);
+    assert!(log.contains("TRACE serial: > 02.\\r\\n"), "{}", log);
+    assert!(log.contains("TRACE serial: < Rpr02"), "{}", log);
+    let lines: Vec<&str> = access.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("127.0.0.1 - - ["), "{}", lines[0]);
+    assert!(lines[0].contains("] \"GET /loadPreset?preset=2 HTTP/1.1\" 200 0 "), "{}", lines[0]);
+    assert!(lines[1].contains("\"GET /loadPreset HTTP/1.1\" 400 30 "), "{}", lines[1]);
+    assert!(lines[1].ends_with("ms"));
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant}
+};
+use crosspoint_api::{
+    config::ServiceConfiguration,
+    crosspoint::{CrossPoint, CrossPointIO},
+    macros::MacroStep,
+    service,
+    vextron::VirtualCrosspoint
+};
+use common::*;
+
+const MACROS: &str = r#"{
+    "inputs": [ { "channel": 8, "description": "Blu-ray" } ],
+    "outputs": [ { "channel": 2, "description": "Projector" } ],
+    "macros": [
+        { "name": "Movie", "steps": [
+            { "action": "recall", "preset": 2 },
+            { "action": "tie", "input": "Blu-ray", "type": "Aud" },
+            { "action": "mute", "output": "Projector" },
+            { "action": "delay", "ms": 10 },
+            { "action": "volume", "output": 1, "level": 40 }
+        ] },
+        { "name": "Broken", "steps": [
+            { "action": "tie", "input": 7, "output": 1 },
+            { "action": "mute", "output": 3 },
+            { "action": "volume", "output": 1, "level": 99 }
+        ] }
+    ]
+}"#;
+
+fn start_with_macros() -> (SocketAddr, VirtualCrosspoint) {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    let config = ServiceConfiguration { macros: ServiceConfiguration::parse(MACROS).unwrap().macros, ..test_config("virtual") };
+    (service::start_with(config, port).unwrap(), simulator)
+}
+
+#[test]
+fn parses_macro_steps() {
+    let config = ServiceConfiguration::parse(MACROS).unwrap();
+    assert_eq!(config.macros.len(), 2);
+    assert_eq!(config.macros[0].name, "Movie");
+    assert_eq!(config.macros[0].steps, vec![
+        MacroStep::Recall(2),
+        MacroStep::Tie { input: 8, output: None, io_type: CrossPointIO::Aud },
+        MacroStep::Mute { output: Some(2), audio: false, muted: true },
+        MacroStep::Delay(Duration::from_millis(10)),
+        MacroStep::Volume { output: 1, level: 40 }
+    ]);
+}
+
+#[test]
+fn rejects_invalid_macros() {
+    assert!(ServiceConfiguration::parse(r#"{ "macros": [ { "steps": [ { "action": "recall", "preset": 1 } ] } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "macros": [ { "name": "Empty", "steps": [] } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "macros": [ { "name": "A", "steps": [ { "action": "dance" } ] } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "macros": [ { "name": "A", "steps": [ { "action": "tie", "input": "Laserdisc" } ] } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "macros": [ { "name": "A", "steps": [ { "action": "delay", "ms": 600000 } ] } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "macros": [
+        { "name": "A", "steps": [ { "action": "recall", "preset": 1 } ] },
+        { "name": "A", "steps": [ { "action": "recall", "preset": 2 } ] } ] }"#).is_err());
+}
+
+#[test]
+fn runs_every_step() {
+    let (address, simulator) = start_with_macros();
+
+    let macros = json::parse(&get(address, "/macros").body).unwrap();
+    assert_eq!(macros["Macros"].len(), 2);
+    assert_eq!(macros["Macros"][0]["Steps"], 5);
+
+    let response = post(address, "/runMacro", r#"{ "Name": "Movie" }"#);
+    assert_eq!(response.status, 200);
+    let report = json::parse(&response.body).unwrap();
+    assert_eq!(report["StepsCompleted"], 5);
+    assert!(report["Error"].is_null());
+
+    let ties = simulator.current_ties();
+    assert!(ties.contains(&tie(6, 3, CrossPointIO::Vid)));
+    assert!(ties.contains(&tie(8, 3, CrossPointIO::Aud)));
+    assert!(ties.contains(&tie(8, 1, CrossPointIO::Aud)));
+    assert!(simulator.video_muted(2));
+    assert_eq!(simulator.volume(1), 40);
+
+    let events = json::parse(&get(address, "/events").body).unwrap();
+    assert!(events["Events"].members().any(|e| e["Type"] == "Macro" && e["Name"] == "Movie"));
+}
+
+#[test]
+fn failed_step_rolls_back_earlier_steps() {
+    let (address, simulator) = start_with_macros();
+    let before = simulator.current_ties();
+
+    let response = post(address, "/runMacro", r#"{ "Name": "Broken" }"#);
+    assert_eq!(response.status, 500);
+    let report = json::parse(&response.body).unwrap();
+    assert_eq!(report["StepsCompleted"], 2);
+    assert_eq!(report["FailedStep"], 3);
+    assert_eq!(report["RolledBack"], true);
+
+    assert!(!simulator.video_muted(3));
+    let after = simulator.current_ties();
+    assert_eq!(after.len(), before.len());
+    assert!(before.iter().all(|t| after.contains(t)));
+}
+
+#[test]
+fn run_macro_requires_known_name() {
+    let (address, _) = start_with_macros();
+
+    assert_eq!(post(address, "/runMacro", r#"{ "Name": "Karaoke" }"#).status, 404);
+    assert_eq!(post(address, "/runMacro", "{}").status, 404);
+    assert_eq!(post(address, "/runMacro", "Movie").status, 400);
+}
+
+#[test]
+fn delay_leaves_switcher_free() {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = Arc::new(Mutex::new(CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap()));
+    port.lock().unwrap().keep_history();
+    let definition = ServiceConfiguration::parse(r#"{ "macros": [ { "name": "Slow", "steps": [
+        { "action": "tie", "input": 7, "output": 1 }, { "action": "delay", "ms": 500 }, { "action": "tie", "input": 8, "output": 2 }
+    ] } ] }"#).unwrap().macros.remove(0);
+
+    let runner = {
+        let port = port.clone();
+        thread::spawn(move || definition.run(&port))
+    };
+    thread::sleep(Duration::from_millis(100));
+    let started = Instant::now();
+    port.lock().unwrap().tie(tie(3, 4, CrossPointIO::All)).unwrap();
+    assert!(started.elapsed() < Duration::from_millis(300));
+    assert!(!simulator.current_ties().contains(&tie(8, 2, CrossPointIO::All)));
+
+    assert_eq!(runner.join().unwrap().failure, None);
+    let ties = simulator.current_ties();
+    assert!(ties.contains(&tie(7, 1, CrossPointIO::All)));
+    assert!(ties.contains(&tie(3, 4, CrossPointIO::All)));
+    assert!(ties.contains(&tie(8, 2, CrossPointIO::All)));
+    // The tie made during the delay has a snapshot of its own
+    let cp = port.lock().unwrap();
+    let causes: Vec<&str> = cp.history().unwrap().entries().map(|e| e.cause.as_str()).collect();
+    assert_eq!(causes, vec!["Macro Slow", "Tie 3*4!", "Macro Slow"]);
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use crosspoint_api::{crosspoint::CrossPoint, service, vextron::VirtualCrosspoint};
+use common::*;
+
+#[test]
+fn video_mute_keeps_tie() {
+    let (address, simulator) = start_simulated();
+    let ties = simulator.current_ties();
+
+    assert_eq!(post(address, "/videoMute?output=4&mute=1", "").status, 200);
+    assert!(simulator.video_muted(4));
+    assert!(!simulator.audio_muted(4));
+    assert_eq!(simulator.current_ties(), ties);
+
+    assert_eq!(post(address, "/videoMute?output=4&mute=0", "").status, 200);
+    assert!(!simulator.video_muted(4));
+}
+
+#[test]
+fn global_mute_covers_every_output() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/audioMute?mute=1", "").status, 200);
+    assert!((1..=8).all(|o| simulator.audio_muted(o)));
+    assert!((1..=8).all(|o| !simulator.video_muted(o)));
+}
+
+#[test]
+fn mute_status_lists_outputs() {
+    let (address, _) = start_simulated();
+    assert_eq!(post(address, "/videoMute?output=2&mute=1", "").status, 200);
+    assert_eq!(post(address, "/audioMute?output=3&mute=true", "").status, 200);
+
+    let status = json::parse(&get(address, "/muteStatus").body).unwrap();
+    assert_eq!(status["Outputs"].len(), 8);
+    assert_eq!(status["Outputs"][1]["Video"], true);
+    assert_eq!(status["Outputs"][1]["Audio"], false);
+    assert_eq!(status["Outputs"][2]["Video"], false);
+    assert_eq!(status["Outputs"][2]["Audio"], true);
+}
+
+#[test]
+fn mute_publishes_events() {
+    let (address, _) = start_simulated();
+    assert_eq!(post(address, "/videoMute?output=4&mute=1", "").status, 200);
+    assert_eq!(post(address, "/audioMute?mute=0", "").status, 200);
+
+    let events = json::parse(&get(address, "/events").body).unwrap();
+    assert_eq!(events["Latest"], 2);
+    assert_eq!(events["Events"].len(), 2);
+    assert_eq!(events["Events"][0]["Type"], "VideoMute");
+    assert_eq!(events["Events"][0]["Output"], 4);
+    assert_eq!(events["Events"][0]["Muted"], true);
+    assert_eq!(events["Events"][1]["Type"], "AudioMute");
+    assert!(events["Events"][1]["Output"].is_null());
+
+    let events = json::parse(&get(address, "/events?since=1").body).unwrap();
+    assert_eq!(events["Events"].len(), 1);
+    assert_eq!(events["Events"][0]["Sequence"], 2);
+}
+
+#[test]
+fn rejects_bad_mute_requests() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/videoMute?output=9&mute=1", "").status, 400);
+    assert_eq!(post(address, "/videoMute?output=1", "").status, 400);
+    assert_eq!(post(address, "/videoMute?output=1&mute=2", "").status, 400);
+    assert!(!simulator.video_muted(1));
+
+    let events = json::parse(&get(address, "/events").body).unwrap();
+    assert_eq!(events["Latest"], 0);
+}
+
+#[test]
+fn audio_mute_needs_audio() {
+    let simulator = VirtualCrosspoint::parse_json(r#"{ "input_channels": 8, "output_channels": 4 }"#).unwrap();
+    let port = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    let address = service::start_with(test_config("virtual"), port).unwrap();
+
+    assert_eq!(post(address, "/audioMute?output=1&mute=1", "").status, 400);
+    assert_eq!(post(address, "/videoMute?mute=1", "").status, 200);
+    assert!((1..=4).all(|o| simulator.video_muted(o)));
+
+    let status = json::parse(&get(address, "/muteStatus").body).unwrap();
+    assert!(status["Outputs"][0]["Audio"].is_null());
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use std::{collections::HashMap, net::SocketAddr};
+use crosspoint_api::{config::ServiceConfiguration, crosspoint::CrossPoint, service, vextron::VirtualCrosspoint};
+use common::*;
+
+fn start_with_names(inputs: &[(i32, &str)], outputs: &[(i32, &str)]) -> (SocketAddr, VirtualCrosspoint) {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    let config = ServiceConfiguration {
+        inputs: inputs.iter().map(|(c, n)| (*c, n.to_string())).collect::<HashMap<_, _>>(),
+        outputs: outputs.iter().map(|(c, n)| (*c, n.to_string())).collect::<HashMap<_, _>>(),
+        ..test_config("virtual")
+    };
+    (service::start_with(config, port).unwrap(), simulator)
+}
+
+#[test]
+fn channel_names_lists_configured_names() {
+    let (address, _) = start_with_names(&[(2, "Super NES"), (1, "NES")], &[(1, "Shinybow")]);
+
+    let names = json::parse(&get(address, "/channelNames").body).unwrap();
+    assert_eq!(names["Inputs"].len(), 2);
+    assert_eq!(names["Inputs"][0]["Channel"], 1);
+    assert_eq!(names["Inputs"][0]["Name"], "NES");
+    assert_eq!(names["Outputs"][0]["Name"], "Shinybow");
+}
+
+#[test]
+fn push_stores_names_on_switcher() {
+    let (address, simulator) = start_with_names(&[(1, "NES"), (12, "Nintendo 64")], &[(4, "Shinybow")]);
+
+    assert_eq!(post(address, "/syncNames?direction=push", "").status, 200);
+    assert_eq!(simulator.input_name(1), "NES");
+    assert_eq!(simulator.input_name(12), "Nintendo 64");
+    assert_eq!(simulator.input_name(2), "Input 2");
+    assert_eq!(simulator.output_name(4), "Shinybow");
+}
+
+#[test]
+fn push_rejects_names_the_switcher_cannot_store() {
+    let (address, simulator) = start_with_names(&[(1, "NES"), (2, "Sega Mega Drive")], &[]);
+    let response = post(address, "/syncNames?direction=push", "");
+    assert_eq!(response.status, 400);
+    assert_eq!(response.body, "Input 2: Name must be 1 to 12 characters");
+    assert_eq!(simulator.input_name(1), "Input 1");
+
+    let (address, _) = start_with_names(&[(1, "A,B")], &[]);
+    assert_eq!(post(address, "/syncNames?direction=push", "").status, 400);
+
+    let (address, _) = start_with_names(&[], &[(9, "Nowhere")]);
+    assert_eq!(post(address, "/syncNames?direction=push", "").status, 400);
+}
+
+#[test]
+fn pull_replaces_configured_names() {
+    let (address, _) = start_with_names(&[(1, "NES")], &[]);
+    assert_eq!(post(address, "/syncNames?direction=push", "").status, 200);
+
+    let names = json::parse(&post(address, "/syncNames?direction=pull", "").body).unwrap();
+    assert_eq!(names["Inputs"].len(), 12);
+    assert_eq!(names["Inputs"][0]["Name"], "NES");
+    assert_eq!(names["Inputs"][1]["Name"], "Input 2");
+    assert_eq!(names["Outputs"].len(), 8);
+
+    let names = json::parse(&get(address, "/channelNames").body).unwrap();
+    assert_eq!(names["Outputs"][7]["Name"], "Output 8");
+}
+
+#[test]
+fn sync_requires_direction() {
+    let (address, _) = start_with_names(&[], &[]);
+    assert_eq!(post(address, "/syncNames", "").status, 400);
+    assert_eq!(post(address, "/syncNames?direction=sideways", "").status, 400);
+}
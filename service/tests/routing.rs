@@ -0,0 +1,127 @@
+mod common;
+
+use std::{net::{SocketAddr, SocketAddrV4}, str::FromStr, thread, time::Duration};
+use crosspoint_api::{
+    config::ServiceConfiguration,
+    crosspoint::{CrossPoint, CrossPointIO},
+    routing::RoutingAction,
+    service,
+    vextron::VirtualCrosspoint
+};
+use common::*;
+
+const RULES: &str = r#"{
+    "inputs": [
+        { "channel": 3, "description": "Nintendo 64" },
+        { "channel": 4, "description": "GameCube" }
+    ],
+    "outputs": [ { "channel": 2, "description": "Framemeister" } ],
+    "auto-routing": [
+        { "name": "N64", "input": "Nintendo 64", "priority": 1, "ties": [ { "output": "Framemeister", "type": "RGB" } ] },
+        { "name": "GameCube", "input": 4, "priority": 5, "ties": [ { "output": 2 } ] },
+        { "name": "Slow", "input": 6, "debounce": 1000, "ties": [ { "output": 5 } ] },
+        { "name": "Idle", "input": 7, "when": "no-signal", "preset": 2 }
+    ]
+}"#;
+
+fn start_routed() -> (SocketAddr, VirtualCrosspoint) {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    let config = ServiceConfiguration {
+        serial_port: Some(String::from("virtual")),
+        binding: Some(SocketAddrV4::from_str("127.0.0.1:0").unwrap()),
+        signal_poll_interval: Some(Duration::from_millis(50)),
+        ..ServiceConfiguration::parse(RULES).unwrap()
+    };
+    let address = service::start_with(config, port).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    (address, simulator)
+}
+
+fn auto_routes(address: SocketAddr) -> Vec<String> {
+    let events = json::parse(&get(address, "/events").body).unwrap();
+    events["Events"].members().filter(|e| e["Type"] == "AutoRoute").map(|e| e["Rule"].to_string()).collect()
+}
+
+#[test]
+fn parses_rules_with_channel_names() {
+    let config = ServiceConfiguration::parse(RULES).unwrap();
+    assert_eq!(config.routing_rules.len(), 4);
+
+    let rule = &config.routing_rules[0];
+    assert_eq!(rule.input, 3);
+    assert!(rule.on_signal);
+    assert_eq!(rule.action, RoutingAction::Ties(vec![tie(3, 2, CrossPointIO::RGB)]));
+    assert_eq!(config.routing_rules[3].action, RoutingAction::Preset(2));
+    assert!(!config.routing_rules[3].on_signal);
+}
+
+#[test]
+fn rejects_invalid_rules() {
+    assert!(ServiceConfiguration::parse(r#"{ "auto-routing": [ { "input": "Nowhere", "preset": 1 } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "auto-routing": [ { "input": 1 } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "auto-routing": [ { "input": 1, "when": "later", "preset": 1 } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "auto-routing": [ { "input": 1, "ties": [ { "output": 1, "type": "SDI" } ] } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "auto-routing": [ { "input": 256, "ties": [ { "output": 1 } ] } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "auto-routing": [ { "input": 1, "preset": 100 } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "preset-count": 16, "auto-routing": [ { "input": 1, "preset": 17 } ] }"#).is_err());
+}
+
+#[test]
+fn signal_ties_input() {
+    let (address, simulator) = start_routed();
+
+    simulator.set_signal(3, true);
+    wait_for(address, "/events", |r| r.body.contains("AutoRoute"));
+    assert!(simulator.current_ties().contains(&tie(3, 2, CrossPointIO::RGB)));
+
+    let events = json::parse(&get(address, "/events").body).unwrap();
+    let route = events["Events"].members().find(|e| e["Type"] == "AutoRoute").unwrap();
+    assert_eq!(route["Rule"], "N64");
+    assert_eq!(route["Action"], "Tie 3*2&");
+    assert!(route["Error"].is_null());
+}
+
+#[test]
+fn higher_priority_rule_holds_output() {
+    let (address, simulator) = start_routed();
+
+    simulator.set_signal(4, true);
+    wait_for(address, "/events", |r| r.body.contains("AutoRoute"));
+    simulator.set_signal(3, true);
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(auto_routes(address), vec!["GameCube"]);
+    assert!(simulator.current_ties().contains(&tie(4, 2, CrossPointIO::All)));
+
+    simulator.set_signal(4, false);
+    wait_for(address, "/events", |r| r.body.matches("AutoRoute").count() == 2);
+    assert_eq!(auto_routes(address), vec!["GameCube", "N64"]);
+    assert!(simulator.current_ties().contains(&tie(3, 2, CrossPointIO::RGB)));
+}
+
+#[test]
+fn debounce_ignores_brief_signals() {
+    let (address, simulator) = start_routed();
+
+    simulator.set_signal(6, true);
+    thread::sleep(Duration::from_millis(300));
+    simulator.set_signal(6, false);
+    thread::sleep(Duration::from_millis(1200));
+    assert!(auto_routes(address).is_empty());
+
+    simulator.set_signal(6, true);
+    wait_for(address, "/events", |r| r.body.contains("AutoRoute"));
+    assert!(simulator.current_ties().contains(&tie(6, 5, CrossPointIO::All)));
+}
+
+#[test]
+fn lost_signal_recalls_preset() {
+    let (address, simulator) = start_routed();
+
+    simulator.set_signal(7, true);
+    thread::sleep(Duration::from_millis(200));
+    simulator.set_signal(7, false);
+    wait_for(address, "/events", |r| r.body.contains("AutoRoute"));
+    assert_eq!(auto_routes(address), vec!["Idle"]);
+    assert_eq!(simulator.current_ties(), vec![tie(6, 3, CrossPointIO::All)]);
+}
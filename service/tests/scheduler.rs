@@ -0,0 +1,149 @@
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH}
+};
+use crosspoint_api::{
+    config::ServiceConfiguration,
+    crosspoint::{CrossPoint, CrossPointIO},
+    events::{EventLog, EventKind},
+    scheduler::{self, CronExpression, ScheduleAction, ScheduleTime, Schedules},
+    service,
+    vextron::VirtualCrosspoint
+};
+use common::*;
+
+const SCHEDULES: &str = r#"{
+    "outputs": [ { "channel": 2, "description": "Lounge" } ],
+    "macros": [ { "name": "Movie", "steps": [ { "action": "recall", "preset": 2 } ] } ],
+    "utc-offset": "+01:00",
+    "schedules": [
+        { "name": "Attract mode", "cron": "0 22 * * *", "preset": 2 },
+        { "name": "Morning", "cron": "0 10 * * mon-fri", "ties": [ { "input": 7, "output": "Lounge", "type": "Vid" } ] },
+        { "name": "Film night", "cron": "30 20 * * 5", "macro": "Movie" }
+    ]
+}"#;
+
+fn time(minute: u32, hour: u32, day: u32, month: u32, weekday: u32) -> ScheduleTime {
+    ScheduleTime { minute, hour, day, month, weekday }
+}
+
+#[test]
+fn matches_cron_fields() {
+    let cron = CronExpression::parse("*/15 9-17 * * mon-fri").unwrap();
+    assert!(cron.matches(&time(45, 9, 4, 3, 1)));
+    assert!(!cron.matches(&time(50, 9, 4, 3, 1)));
+    assert!(!cron.matches(&time(0, 18, 4, 3, 1)));
+    assert!(!cron.matches(&time(0, 12, 3, 3, 0)));
+
+    let cron = CronExpression::parse("0 0 1,15 jan 7").unwrap();
+    assert!(cron.matches(&time(0, 0, 15, 1, 3)));
+    assert!(cron.matches(&time(0, 0, 7, 1, 0)));
+    assert!(!cron.matches(&time(0, 0, 7, 2, 0)));
+
+    let cron = CronExpression::parse("0 10 */2 * *").unwrap();
+    assert!(cron.matches(&time(0, 10, 3, 3, 0)));
+    assert!(!cron.matches(&time(0, 10, 4, 3, 1)));
+
+    for invalid in ["0 22 * *", "60 * * * *", "* 5-2 * * *", "*/0 * * * *", "* * 0 * *", "* * * * funday"] {
+        assert!(CronExpression::parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn converts_time_with_offset() {
+    let moment = UNIX_EPOCH + Duration::from_secs(1_709_242_200);
+    assert_eq!(ScheduleTime::at(moment, 0), time(30, 21, 29, 2, 4));
+    assert_eq!(ScheduleTime::at(moment, 180), time(30, 0, 1, 3, 5));
+    assert_eq!(ScheduleTime::at(UNIX_EPOCH, -1), time(59, 23, 31, 12, 3));
+
+    let cron = CronExpression::parse("0 22 * * *").unwrap();
+    assert_eq!(cron.next_run(moment, 0), Some(moment + Duration::from_secs(30 * 60)));
+    assert_eq!(cron.next_run(moment, 60), Some(moment + Duration::from_secs(23 * 3600 + 30 * 60)));
+}
+
+#[test]
+fn catches_up_on_missed_minutes() {
+    assert_eq!(scheduler::minutes_to_run(100, 101), 101..=101);
+    assert_eq!(scheduler::minutes_to_run(100, 103), 101..=103);
+    assert_eq!(scheduler::minutes_to_run(100, 500), 491..=500);
+    assert!(scheduler::minutes_to_run(100, 90).is_empty());
+}
+
+#[test]
+fn parses_schedules() {
+    let config = ServiceConfiguration::parse(SCHEDULES).unwrap();
+    assert_eq!(config.utc_offset, 60);
+    assert_eq!(config.schedules.len(), 3);
+    assert_eq!(config.schedules[0].action, ScheduleAction::Preset(2));
+    assert_eq!(config.schedules[1].action, ScheduleAction::Ties(vec![tie(7, 2, CrossPointIO::Vid)]));
+    assert_eq!(config.schedules[2].action, ScheduleAction::Macro(String::from("Movie")));
+
+    assert!(ServiceConfiguration::parse(r#"{ "schedules": [ { "name": "A", "cron": "0 22 * *", "preset": 2 } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "schedules": [ { "name": "A", "cron": "0 22 * * *" } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "schedules": [ { "name": "A", "cron": "0 22 * * *", "macro": "Karaoke" } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "preset-count": 16, "schedules": [ { "name": "A", "cron": "0 22 * * *", "preset": 20 } ] }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "utc-offset": "1" }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "utc-offset": "+15:00" }"#).is_err());
+}
+
+#[test]
+fn runs_due_schedules() {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let mut cp = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    cp.keep_history();
+    let port = Arc::new(Mutex::new(cp));
+    let config = ServiceConfiguration::parse(SCHEDULES).unwrap();
+    let schedules = Schedules::new(config.schedules.clone());
+    let events = EventLog::new();
+
+    scheduler::run_due(&port, &events, &schedules, &config.macros, &time(0, 10, 4, 3, 1));
+    assert!(simulator.current_ties().contains(&tie(7, 2, CrossPointIO::Vid)));
+
+    scheduler::run_due(&port, &events, &schedules, &config.macros, &time(0, 22, 4, 3, 1));
+    assert!(simulator.current_ties().contains(&tie(6, 3, CrossPointIO::All)));
+
+    let events = events.since(0);
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[1].kind, EventKind::Schedule {
+        name: String::from("Attract mode"), action: String::from("Recall preset 2"), error: None
+    });
+    let causes: Vec<String> = port.lock().unwrap().history().unwrap().entries().map(|e| e.cause.clone()).collect();
+    assert_eq!(causes, vec!["Schedule Morning", "Schedule Attract mode"]);
+}
+
+#[test]
+fn schedules_are_managed_and_saved() {
+    let path = std::env::temp_dir().join(format!("crosspoint-schedules-{}.json", std::process::id()));
+    std::fs::write(&path, r#"{ "serial-port": "virtual" }"#).unwrap();
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator), "virtual").unwrap();
+    let config = ServiceConfiguration { path: Some(path.to_str().unwrap().to_string()), ..test_config("virtual") };
+    let address = service::start_with(config, port).unwrap();
+
+    let response = post(address, "/saveSchedule", r#"{ "name": "Attract mode", "cron": "0 22 * * *", "preset": 2 }"#);
+    assert_eq!(response.status, 200);
+    let schedules = json::parse(&response.body).unwrap();
+    assert_eq!(schedules["Schedules"][0]["Action"], "Recall preset 2");
+    assert!(schedules["Schedules"][0]["NextRun"].as_u64().is_some());
+
+    assert_eq!(post(address, "/saveSchedule", r#"{ "name": "Attract mode", "cron": "0 23 * * *", "preset": 3 }"#).status, 200);
+    assert_eq!(post(address, "/saveSchedule", r#"{ "name": "Broken", "cron": "whenever", "preset": 3 }"#).status, 400);
+    // The simulator has 32 presets
+    assert_eq!(post(address, "/saveSchedule", r#"{ "name": "Broken", "cron": "0 18 * * *", "preset": 40 }"#).status, 400);
+    assert_eq!(post(address, "/saveSchedule", r#"{ "name": "Evening", "cron": "0 18 * * *", "preset": 4 }"#).status, 200);
+
+    let saved = ServiceConfiguration::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved.serial_port.as_deref(), Some("virtual"));
+    assert_eq!(saved.schedules.len(), 2);
+    assert_eq!(saved.schedules[0].cron.text, "0 23 * * *");
+    assert_eq!(saved.schedules[0].action, ScheduleAction::Preset(3));
+
+    assert_eq!(post(address, "/removeSchedule", r#"{ "Name": "Attract mode" }"#).status, 200);
+    assert_eq!(post(address, "/removeSchedule", r#"{ "Name": "Attract mode" }"#).status, 404);
+    let saved = ServiceConfiguration::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(saved.schedules.len(), 1);
+    assert_eq!(json::parse(&get(address, "/schedules").body).unwrap()["Schedules"].len(), 1);
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use std::time::Duration;
+use crosspoint_api::{config::ServiceConfiguration, crosspoint::CrossPoint, service, vextron::VirtualCrosspoint};
+use common::*;
+
+fn start_polled(simulator: &VirtualCrosspoint) -> std::net::SocketAddr {
+    let port = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    let mut config = ServiceConfiguration { signal_poll_interval: Some(Duration::from_millis(50)), ..test_config("virtual") };
+    config.inputs.insert(1, String::from("NES"));
+    service::start_with(config, port).unwrap()
+}
+
+#[test]
+fn inputs_report_signal_presence() {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    simulator.set_signal(3, true);
+    let address = start_polled(&simulator);
+
+    let inputs = json::parse(&get(address, "/inputs").body).unwrap();
+    assert_eq!(inputs["Inputs"].len(), 12);
+    assert_eq!(inputs["Inputs"][0]["Name"], "NES");
+    assert_eq!(inputs["Inputs"][0]["Signal"], false);
+    assert!(inputs["Inputs"][1]["Name"].is_null());
+    assert_eq!(inputs["Inputs"][2]["Signal"], true);
+}
+
+#[test]
+fn poller_publishes_signal_changes() {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let address = start_polled(&simulator);
+    std::thread::sleep(Duration::from_millis(200));
+
+    simulator.set_signal(5, true);
+    let events = wait_for(address, "/events", |r| json::parse(&r.body).unwrap()["Latest"] == 1);
+    let events = json::parse(&events.body).unwrap();
+    assert_eq!(events["Events"][0]["Type"], "SignalPresence");
+    assert_eq!(events["Events"][0]["Input"], 5);
+    assert_eq!(events["Events"][0]["Present"], true);
+
+    simulator.set_signal(5, false);
+    let events = wait_for(address, "/events?since=1", |r| json::parse(&r.body).unwrap()["Latest"] == 2);
+    assert_eq!(json::parse(&events.body).unwrap()["Events"][0]["Present"], false);
+}
+
+#[test]
+fn signal_is_null_when_model_cannot_report_it() {
+    let simulator = VirtualCrosspoint::parse_json(r#"{ "input_channels": 4, "output_channels": 4, "signal_support": false }"#).unwrap();
+    let address = start_polled(&simulator);
+
+    let response = get(address, "/inputs");
+    assert_eq!(response.status, 200);
+    let inputs = json::parse(&response.body).unwrap();
+    assert!(inputs["Inputs"][0]["Signal"].is_null());
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use crosspoint_api::{crosspoint::{CrossPoint, CrossPointIO}, vextron::VirtualCrosspoint};
+use common::*;
+
+fn has_tie(body: &str, input: u8, output: u8, io_type: &str) -> bool {
+    let ties = json::parse(body).unwrap();
+    ties["Ties"].members().any(|t| t["InputChannel"] == input && t["OutputChannel"] == output && t["IOType"] == io_type)
+}
+
+#[test]
+fn reads_are_served_from_cache() {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let mut cp = CrossPoint::open(Box::new(simulator.clone()), "virtual").unwrap();
+    cp.refresh_state().unwrap();
+
+    simulator.set_online(false);
+    let names = cp.preset_names().unwrap();
+    assert_eq!(names.len(), 32);
+    assert_eq!(names[1], "SNES");
+    assert_eq!(cp.get_input_name(3).unwrap(), "Input 3");
+
+    let ties = cp.current_ties().unwrap();
+    assert!(ties.contains(&tie(5, 4, CrossPointIO::All)));
+    assert!(ties.contains(&tie(1, 2, CrossPointIO::All)));
+}
+
+#[test]
+fn acknowledgements_update_cache() {
+    let (address, simulator) = start_simulated();
+
+    assert_eq!(post(address, "/renamePreset", r#"{ "PresetNumber": 2, "PresetName": "Super NES" }"#).status, 200);
+    assert_eq!(get(address, "/saveCurrentToPreset?preset=5").status, 200);
+
+    let presets = json::parse(&get(address, "/activePresets").body).unwrap();
+    assert_eq!(presets["Presets"][0]["Name"], "Super NES");
+    assert_eq!(presets["Presets"][1]["Number"], 5);
+    assert_eq!(presets["Presets"][1]["Name"].as_str(), Some(simulator.preset(5).unwrap().name.as_str()));
+}
+
+#[test]
+fn front_panel_ties_reach_cache() {
+    let (address, simulator) = start_simulated();
+    assert!(has_tie(&get(address, "/ties").body, 5, 4, "All"));
+
+    simulator.front_panel_tie(tie(7, 4, CrossPointIO::Vid));
+    let ties = wait_for(address, "/ties", |r| has_tie(&r.body, 7, 4, "Vid"));
+    assert!(has_tie(&ties.body, 5, 4, "Aud"));
+}
+
+#[test]
+fn recall_reads_ties_again() {
+    let (address, _) = start_simulated();
+
+    assert_eq!(get(address, "/loadPreset?preset=2").status, 200);
+    let ties = get(address, "/ties");
+    assert!(has_tie(&ties.body, 6, 3, "All"));
+    assert!(!has_tie(&ties.body, 5, 4, "All"));
+}
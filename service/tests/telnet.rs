@@ -0,0 +1,155 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, SocketAddrV4, TcpListener, TcpStream},
+    str::FromStr,
+    sync::mpsc::{channel, Sender},
+    thread,
+    time::Duration
+};
+use crosspoint_api::{
+    config::ServiceConfiguration,
+    crosspoint::CrossPoint,
+    service,
+    telnet::{self, TelnetTransport, DEFAULT_KEEP_ALIVE},
+    transport::{self, ConnectionSettings},
+    vextron::VirtualCrosspoint
+};
+
+const PASSWORD: &str = "extron";
+const WILL_ECHO: [u8; 3] = [255, 251, 1];
+const DONT_ECHO: [u8; 3] = [255, 254, 1];
+
+/// Pretends to be an Ethernet CrossPoint: banner, optional password prompt, then SIS
+/// commands answered by the simulator. Each connection is closed after `commands`
+/// commands, and every line received during login is reported on `logins`.
+fn serve_telnet(password: Option<&'static str>, commands: usize, logins: Sender<Vec<u8>>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let simulator = VirtualCrosspoint::new();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = stream.unwrap();
+            _ = serve_session(stream, password, commands, simulator.clone(), &logins);
+        }
+    });
+    address
+}
+
+fn serve_session(mut stream: TcpStream, password: Option<&str>, commands: usize, mut device: VirtualCrosspoint,
+        logins: &Sender<Vec<u8>>) -> std::io::Result<()> {
+    stream.write_all(&WILL_ECHO)?;
+    stream.write_all(b"(c) Copyright 2026, Extron Electronics, CrossPoint 84, V1.00, 60-336-01\r\n")?;
+    stream.write_all(b"Sat, 18 Oct 2026 10:00:00\r\n")?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    if let Some(password) = password {
+        loop {
+            stream.write_all(b"\r\nPassword:")?;
+            let mut line = Vec::new();
+            reader.read_until(b'\n', &mut line)?;
+            _ = logins.send(line.clone());
+            if line.ends_with(format!("{}\r\n", password).as_bytes()) { break; }
+        }
+        stream.write_all(b"\r\nLogin Administrator\r\n")?;
+    }
+
+    for _ in 0..commands {
+        let mut command = [0_u8; 64];
+        let count = reader.read(&mut command)?;
+        if count == 0 { break; }
+        device.write_all(&command[..count])?;
+        let mut reply = Vec::new();
+        device.read_to_end(&mut reply)?;
+        stream.write_all(&reply)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn logs_in_and_runs_sis_commands() {
+    let (logins, received) = channel();
+    let address = serve_telnet(Some(PASSWORD), 10, logins);
+
+    let transport = TelnetTransport::connect(&address.to_string(), Some(PASSWORD), DEFAULT_KEEP_ALIVE).unwrap();
+    let mut cp = CrossPoint::open(Box::new(transport), "tcp").unwrap();
+
+    let login = received.recv().unwrap();
+    assert!(login.starts_with(&DONT_ECHO));
+    assert!(login.ends_with(b"extron\r\n"));
+
+    assert_eq!(cp.input_port_count(), 12);
+    assert_eq!(cp.get_preset_name(1).unwrap(), "[unassigned]");
+}
+
+#[test]
+fn connects_without_password() {
+    let (logins, _received) = channel();
+    let address = serve_telnet(None, 10, logins);
+
+    let transport = TelnetTransport::connect(&address.to_string(), None, DEFAULT_KEEP_ALIVE).unwrap();
+    let cp = CrossPoint::open(Box::new(transport), "tcp").unwrap();
+    assert_eq!(cp.output_port_count(), 8);
+}
+
+#[test]
+fn reports_missing_or_wrong_password() {
+    let (logins, _received) = channel();
+    let address = serve_telnet(Some(PASSWORD), 10, logins);
+
+    assert!(TelnetTransport::connect(&address.to_string(), None, DEFAULT_KEEP_ALIVE).is_err());
+    assert!(TelnetTransport::connect(&address.to_string(), Some("wrong"), DEFAULT_KEEP_ALIVE).is_err());
+}
+
+#[test]
+fn keep_alive_logs_in_again_after_drop() {
+    let (logins, received) = channel();
+    let address = serve_telnet(Some(PASSWORD), 1, logins);
+
+    let config = ServiceConfiguration::parse(&format!(
+        r#"{{ "serial-port": "tcp://{}", "device-password": "{}", "device-keep-alive": 1 }}"#, address, PASSWORD
+    )).unwrap();
+    let settings = ConnectionSettings { keep_alive: Some(Duration::ZERO), ..config.connection_settings().unwrap() };
+    assert_eq!(config.connection_settings().unwrap().keep_alive, Some(Duration::from_secs(1)));
+
+    let mut transport = transport::open(&settings).unwrap();
+    transport.keep_alive().unwrap();
+    transport.keep_alive().unwrap();
+    assert_eq!(received.iter().take(2).count(), 2);
+
+    let cp = CrossPoint::open(transport, "tcp").unwrap();
+    assert_eq!(cp.input_port_count(), 12);
+}
+
+#[test]
+fn service_connects_over_tcp_url() {
+    let (logins, _received) = channel();
+    let address = serve_telnet(Some(PASSWORD), 100, logins);
+
+    let config = ServiceConfiguration {
+        serial_port: Some(format!("tcp://{}", address)),
+        device_password: Some(String::from(PASSWORD)),
+        binding: Some(SocketAddrV4::from_str("127.0.0.1:0").unwrap()),
+        ..Default::default()
+    };
+    let api = service::start(config).unwrap();
+
+    let mut stream = TcpStream::connect(api).unwrap();
+    stream.write_all(b"GET /activePresets HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.ends_with("{\"PresetCount\":32,\"Presets\":[]}"));
+
+    assert!(CrossPoint::connect(&ConnectionSettings::new(&format!("tcp://{}", address))).is_err());
+}
+
+#[test]
+fn adds_telnet_port_when_missing() {
+    assert_eq!(telnet::with_default_port("192.168.1.50"), "192.168.1.50:23");
+    assert_eq!(telnet::with_default_port("192.168.1.50:2323"), "192.168.1.50:2323");
+    assert_eq!(telnet::with_default_port("switcher.local"), "switcher.local:23");
+    assert_eq!(telnet::with_default_port("switcher.local:2323"), "switcher.local:2323");
+    assert_eq!(telnet::with_default_port("fe80::1"), "[fe80::1]:23");
+    assert_eq!(telnet::with_default_port("[fe80::1]"), "[fe80::1]:23");
+    assert_eq!(telnet::with_default_port("[fe80::1]:2323"), "[fe80::1]:2323");
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, SocketAddrV4, TcpListener, TcpStream},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant}
+};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use rustls_pki_types::{CertificateDer, ServerName, pem::PemObject};
+use crosspoint_api::{
+    config::ServiceConfiguration,
+    crosspoint::CrossPoint,
+    service,
+    vextron::VirtualCrosspoint
+};
+use common::*;
+
+const CERTIFICATE: &str = "tests/certificates/certificate.pem";
+const KEY: &str = "tests/certificates/key.pem";
+
+fn tls_config(redirect_binding: Option<SocketAddrV4>) -> ServiceConfiguration {
+    ServiceConfiguration {
+        tls_certificate: Some(String::from(CERTIFICATE)),
+        tls_key: Some(String::from(KEY)),
+        redirect_binding,
+        ..test_config("virtual")
+    }
+}
+
+fn start_tls(config: ServiceConfiguration) -> std::io::Result<SocketAddr> {
+    let simulator = VirtualCrosspoint::parse_json(SIMULATOR).unwrap();
+    let port = CrossPoint::open(Box::new(simulator), "virtual").unwrap();
+    service::start_with(config, port)
+}
+
+/// Sends a GET over TLS, trusting only the test certificate authority
+fn get_tls(address: SocketAddr, path: &str) -> String {
+    let mut roots = RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_file("tests/certificates/ca.pem").unwrap()).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions().unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connection = ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+    let mut stream = StreamOwned::new(connection, TcpStream::connect(address).unwrap());
+
+    stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).unwrap();
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).unwrap();
+    raw
+}
+
+#[test]
+fn serves_https() {
+    let address = start_tls(tls_config(None)).unwrap();
+
+    let response = get_tls(address, "/presetNames");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("\"PresetCount\":32"));
+}
+
+#[test]
+fn stalled_handshake_does_not_block_others() {
+    let address = start_tls(tls_config(None)).unwrap();
+
+    let mut stalled = TcpStream::connect(address).unwrap();
+    stalled.write_all(&[0x16, 0x03, 0x01]).unwrap();
+    let started = Instant::now();
+    let response = get_tls(address, "/presetNames");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+fn redirects_plain_http() {
+    let redirect_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let redirect_binding = SocketAddrV4::from_str(&format!("127.0.0.1:{}", redirect_port)).unwrap();
+    let address = start_tls(tls_config(Some(redirect_binding))).unwrap();
+
+    let mut stream = TcpStream::connect(redirect_binding).unwrap();
+    stream.write_all(b"GET /loadPreset?preset=2 HTTP/1.1\r\nHost: evil.example\r\n\r\n").unwrap();
+    let mut raw = String::new();
+    stream.read_to_string(&mut raw).unwrap();
+
+    assert!(raw.starts_with("HTTP/1.1 301"), "{}", raw);
+    assert!(raw.contains(&format!("Location: https://{}/loadPreset?preset=2\n", address)), "{}", raw);
+}
+
+#[test]
+fn rejects_incomplete_tls_settings() {
+    assert!(ServiceConfiguration::parse(r#"{ "tls-certificate": "certificate.pem" }"#).is_err());
+    assert!(ServiceConfiguration::parse(r#"{ "https-redirect": "0.0.0.0:80" }"#).is_err());
+
+    let config = ServiceConfiguration::parse(r#"{ "tls-certificate": "c.pem", "tls-key": "k.pem", "https-redirect": "0.0.0.0:80" }"#).unwrap();
+    assert_eq!(config.tls_key.as_deref(), Some("k.pem"));
+    assert_eq!(config.redirect_binding, Some(SocketAddrV4::from_str("0.0.0.0:80").unwrap()));
+
+    let config = ServiceConfiguration { tls_key: Some(String::from("tests/certificates/missing.pem")), ..tls_config(None) };
+    assert!(start_tls(config).is_err());
+}
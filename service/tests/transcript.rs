@@ -0,0 +1,68 @@
+use std::{
+    env,
+    fs,
+    path::PathBuf
+};
+use crosspoint_api::{
+    crosspoint::CrossPoint,
+    transcript::TranscriptReplay,
+    transport::ConnectionSettings
+};
+
+fn temp_transcript(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("crosspoint-{}-{}.log", name, std::process::id()));
+    _ = fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn recorded_session_replays_identically() {
+    let path = temp_transcript("record");
+    let path_str = path.to_str().unwrap();
+
+    let settings = ConnectionSettings { transcript: Some(String::from(path_str)), ..ConnectionSettings::new("virtual") };
+    let mut recorded = CrossPoint::connect(&settings).unwrap();
+    let names: Vec<String> = (1..=3).map(|i| recorded.get_preset_name(i).unwrap()).collect();
+    let saved = recorded.save_current_config(4).unwrap();
+    drop(recorded);
+
+    let transcript = fs::read_to_string(&path).unwrap();
+    assert!(transcript.contains(" TX 49 0D 0A ; I\\r\\n"));
+    assert!(transcript.contains(" RX "));
+
+    let mut replayed = CrossPoint::connect(&ConnectionSettings::new(&format!("replay:{}", path_str))).unwrap();
+    assert_eq!(replayed.input_port_count(), 12);
+    assert_eq!(replayed.output_port_count(), 8);
+    for (i, name) in names.iter().enumerate() {
+        assert_eq!(&replayed.get_preset_name(i as i32 + 1).unwrap(), name);
+    }
+    assert_eq!(replayed.save_current_config(4).unwrap(), saved);
+
+    _ = fs::remove_file(&path);
+}
+
+#[test]
+fn replay_rejects_commands_not_in_transcript() {
+    let mut replayed = CrossPoint::connect(&ConnectionSettings::new("replay:tests/transcripts/snes_preset.log")).unwrap();
+
+    assert!(replayed.load_preset(3).is_err());
+}
+
+#[test]
+fn replays_field_transcript() {
+    let replay = TranscriptReplay::load("tests/transcripts/snes_preset.log").unwrap();
+    let mut cp = CrossPoint::open(Box::new(replay), "replay").unwrap();
+
+    assert_eq!(cp.input_port_count(), 12);
+    assert!(cp.audio_is_supported());
+    assert_eq!(cp.get_preset_name(2).unwrap(), "SNES");
+    assert_eq!(cp.load_preset(2).unwrap(), "Rpr02");
+    assert_eq!(cp.get_preset_name(7).unwrap(), "[unassigned]");
+}
+
+#[test]
+fn rejects_malformed_transcript() {
+    assert!(TranscriptReplay::parse("1697040000123 XX 49").is_err());
+    assert!(TranscriptReplay::parse("1697040000123 TX ZZ").is_err());
+    assert!(TranscriptReplay::parse("# comment only\n").unwrap().is_finished());
+}
@@ -0,0 +1,76 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, SocketAddr},
+    thread,
+    time::Duration
+};
+use crosspoint_api::{
+    crosspoint::CrossPoint,
+    transport::{self, ConnectionSettings, Transport},
+    vextron::VirtualCrosspoint
+};
+
+/// Serves the simulator over TCP, one connection at a time, for `connections` connections
+fn serve_simulator(simulator: VirtualCrosspoint, connections: usize) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().take(connections) {
+            let mut stream = stream.unwrap();
+            let mut device = simulator.clone();
+            let mut buffer = [0_u8; 64];
+            loop {
+                let count = match stream.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(c) => c
+                };
+                device.write_all(&buffer[..count]).unwrap();
+                let mut reply = Vec::new();
+                device.read_to_end(&mut reply).unwrap();
+                if stream.write_all(&reply).is_err() { break; }
+            }
+        }
+    });
+    address
+}
+
+#[test]
+fn crosspoint_runs_over_tcp() {
+    let simulator = VirtualCrosspoint::new();
+    let address = serve_simulator(simulator.clone(), 1);
+
+    let transport = transport::open(&ConnectionSettings::new(&format!("tcp://{}", address))).unwrap();
+    let mut cp = CrossPoint::open(transport, "tcp").unwrap();
+
+    assert_eq!(cp.input_port_count(), 12);
+    assert_eq!(cp.output_port_count(), 8);
+    assert_eq!(cp.get_preset_name(1).unwrap(), "[unassigned]");
+    assert_eq!(cp.save_current_config(1).unwrap(), "Spr01");
+    assert!(simulator.preset(1).is_some());
+}
+
+#[test]
+fn telnet_transport_reconnects() {
+    let address = serve_simulator(VirtualCrosspoint::new(), 2);
+
+    let mut transport = transport::open(&ConnectionSettings::new(&format!("tcp://{}", address))).unwrap();
+    transport.set_timeout(Duration::from_millis(500)).unwrap();
+    assert_eq!(transport.timeout(), Duration::from_millis(500));
+    transport.reconnect().unwrap();
+
+    let cp = CrossPoint::open(transport, "tcp").unwrap();
+    assert_eq!(cp.input_port_count(), 12);
+}
+
+#[test]
+fn simulator_is_a_transport() {
+    let mut simulator = VirtualCrosspoint::new();
+    simulator.set_timeout(Duration::from_millis(10)).unwrap();
+    assert_eq!(simulator.timeout(), Duration::from_millis(10));
+
+    simulator.write_all(b"I\r\n").unwrap();
+    simulator.reconnect().unwrap();
+    let mut reply = Vec::new();
+    simulator.read_to_end(&mut reply).unwrap();
+    assert!(reply.is_empty());
+}